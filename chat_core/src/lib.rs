@@ -32,14 +32,29 @@ pub struct ChatUser {
   pub id: i64,
   pub fullname: String,
   pub email: String,
+  pub is_bot: bool,
+  pub is_active: bool,
+  pub display_name: Option<String>,
+  pub title: Option<String>,
+  pub timezone: Option<String>,
+  pub avatar_url: Option<String>,
+  pub status_text: Option<String>,
+  pub status_emoji: Option<String>,
+  pub status_expires_at: Option<DateTime<Utc>>,
+  pub pronouns: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, PartialOrd, sqlx::Type)]
 #[sqlx(type_name = "chat_type", rename_all = "snake_case")]
 pub enum ChatType {
+  // the database names, as in the chats sent by pg_notify
+  #[serde(alias = "single")]
   Single,
+  #[serde(alias = "group")]
   Group,
+  #[serde(alias = "private_channel")]
   PrivateChannel,
+  #[serde(alias = "public_channel")]
   PublicChannel,
 }
 
@@ -49,17 +64,316 @@ pub struct Chat {
   pub ws_id: i64,
   pub name: Option<String>,
   pub r#type: ChatType,
+  pub topic: Option<String>,
+  pub description: Option<String>,
+  pub icon: Option<String>,
+  /// normalized channel name, unique in the workspace, e.g. `general` for `#general`
+  pub slug: Option<String>,
+  pub created_by: Option<i64>,
   pub members: Vec<i64>,
+  /// archived chats are read-only
+  pub archived_at: Option<DateTime<Utc>>,
+  pub created_at: DateTime<Utc>,
+}
+
+/// A public channel in the channel browser.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ChannelSummary {
+  pub id: i64,
+  pub ws_id: i64,
+  pub name: Option<String>,
+  pub slug: Option<String>,
+  pub topic: Option<String>,
+  pub description: Option<String>,
+  pub icon: Option<String>,
+  pub member_count: i64,
+  pub is_member: bool,
+  pub created_at: DateTime<Utc>,
+}
+
+/// A chat in the chat list, with a preview of its latest message.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ChatSummary {
+  #[sqlx(flatten)]
+  #[serde(flatten)]
+  pub chat: Chat,
+  pub is_member: bool,
+  pub last_message_id: Option<i64>,
+  pub last_message_sender_id: Option<i64>,
+  pub last_message_preview: Option<String>,
+  pub last_activity_at: DateTime<Utc>,
+  pub last_read_id: Option<i64>,
+  /// muted chats don't alert, mentions still do. Events are still sent, clients decide what alerts
+  pub muted: bool,
+  /// messages from others after the last read one
+  pub unread_count: i64,
+  /// unread messages that mention the user, every unread message counts in direct messages
+  pub mention_count: i64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, PartialOrd, sqlx::Type)]
+#[sqlx(type_name = "chat_role", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ChatRole {
+  Member,
+  Admin,
+  Creator,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ChatMember {
+  pub chat_id: i64,
+  pub user_id: i64,
+  pub role: ChatRole,
+  pub last_read_id: Option<i64>,
+  /// a client-side preference, the notify server sends events for muted chats all the same
+  pub muted: bool,
+  pub joined_at: DateTime<Utc>,
+}
+
+/// The last message a user has read in a chat.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ReadMarker {
+  pub chat_id: i64,
+  pub user_id: i64,
+  pub last_read_id: Option<i64>,
+}
+
+/// A change to one metadata field of a chat, e.g. its topic.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ChatChange {
+  pub id: i64,
+  pub chat_id: i64,
+  pub changed_by: i64,
+  pub field: String,
+  pub old_value: Option<String>,
+  pub new_value: Option<String>,
   pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
 pub struct Message {
+  pub id: i64,
+  pub chat_id: i64,
+  pub sender_id: i64,
+  pub content: String,
+  /// the content parsed into blocks, `None` for messages from before rich text
+  #[sqlx(default, json)]
+  #[serde(default)]
+  pub rich_text: Option<RichText>,
+  pub files: Vec<String>,
+  /// the thread this message replies to
+  pub parent_id: Option<i64>,
+  /// a thread reply that is shown in the chat as well
+  pub also_in_chat: bool,
+  pub reply_count: i32,
+  pub last_reply_at: Option<DateTime<Utc>>,
+  /// reactions grouped by emoji, in the order they were first used
+  #[sqlx(default, json)]
+  #[serde(default)]
+  pub reactions: Vec<ReactionCount>,
+  /// previews of the links in the content, filled in shortly after the message is sent
+  #[sqlx(default, json)]
+  #[serde(default)]
+  pub link_previews: Vec<LinkPreview>,
+  pub edited_at: Option<DateTime<Utc>>,
+  /// deleted messages are kept as tombstones without content
+  pub deleted_at: Option<DateTime<Utc>>,
+  /// the client nonce it was sent with, only set when it is sent
+  #[sqlx(default)]
+  #[serde(default)]
+  pub nonce: Option<String>,
+  pub created_at: DateTime<Utc>,
+}
+
+/// OpenGraph metadata of a linked page. All fields are plain text, never HTML.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LinkPreview {
+  pub url: String,
+  pub title: Option<String>,
+  pub description: Option<String>,
+  pub image: Option<String>,
+}
+
+/// A workspace slash command answered by a webhook.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct SlashCommand {
+  pub id: i64,
+  pub ws_id: i64,
+  /// invoked as `/name`
+  pub name: String,
+  pub description: String,
+  pub url: String,
+  /// signs the webhook calls, only returned when the command is added
+  #[sqlx(default)]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub secret: Option<String>,
+  pub created_by: i64,
+  pub created_at: DateTime<Utc>,
+}
+
+/// The reply to a slash command, only shown to the user who ran it.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CommandResponse {
+  pub command: String,
+  pub text: String,
+  /// the message the command posted in the chat
+  pub message: Option<Message>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Pin {
+  pub id: i64,
+  pub message_id: i64,
+  pub chat_id: i64,
+  pub pinned_by: i64,
+  pub created_at: DateTime<Utc>,
+}
+
+/// A message in the pins of a chat.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct PinnedMessage {
+  #[sqlx(flatten)]
+  #[serde(flatten)]
+  pub message: Message,
+  pub pin_id: i64,
+  pub pinned_by: i64,
+  pub pinned_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Bookmark {
+  pub id: i64,
+  pub user_id: i64,
+  pub message_id: i64,
+  pub chat_id: i64,
+  pub note: Option<String>,
+  pub created_at: DateTime<Utc>,
+}
+
+/// A message in the user's bookmarks.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct BookmarkedMessage {
+  #[sqlx(flatten)]
+  #[serde(flatten)]
+  pub message: Message,
+  pub bookmark_id: i64,
+  pub note: Option<String>,
+  pub bookmarked_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "scheduled_message_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduledMessageStatus {
+  Pending,
+  Sent,
+  Failed,
+}
+
+/// A message waiting to be sent at `send_at`.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ScheduledMessage {
   pub id: i64,
   pub chat_id: i64,
   pub sender_id: i64,
   pub content: String,
   pub files: Vec<String>,
+  pub parent_id: Option<i64>,
+  pub also_in_chat: bool,
+  pub send_at: DateTime<Utc>,
+  pub status: ScheduledMessageStatus,
+  /// the message it was sent as
+  pub message_id: Option<i64>,
+  /// why it couldn't be sent
+  pub error: Option<String>,
+  pub created_at: DateTime<Utc>,
+}
+
+/// Retention setting of a workspace, or of a chat if `chat_id` is set.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct RetentionPolicy {
+  pub ws_id: i64,
+  pub chat_id: Option<i64>,
+  /// messages older than this are deleted, `None` keeps them forever
+  /// or falls back to the workspace setting for a chat
+  pub retention_days: Option<i32>,
+  /// the chat keeps its messages whatever the workspace setting, always false for a workspace
+  pub keep_forever: bool,
+}
+
+/// Messages of a chat that the retention job would delete.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct ChatRetention {
+  pub chat_id: i64,
+  pub name: Option<String>,
+  /// the chat setting, or the workspace one
+  pub retention_days: i32,
+  pub expired_messages: i64,
+}
+
+/// What the retention job would delete in a workspace, nothing is deleted.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RetentionReport {
+  pub ws_id: i64,
+  pub retention_days: Option<i32>,
+  pub chats: Vec<ChatRetention>,
+  /// urls of uploaded files no message references any more
+  pub orphan_files: Vec<String>,
+}
+
+/// A page of chat messages, newest first.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MessagePage {
+  pub messages: Vec<Message>,
+  /// there are older messages than the ones in the page
+  pub has_more_before: bool,
+  /// there are newer messages than the ones in the page
+  pub has_more_after: bool,
+}
+
+/// A page of workspace users, best matches first.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UserPage {
+  pub users: Vec<ChatUser>,
+  /// pass as `cursor` to fetch the next page, absent on the last page
+  pub next_cursor: Option<String>,
+}
+
+/// A message matching a search, with the matched terms highlighted in the snippet.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct SearchHit {
+  #[sqlx(flatten)]
+  #[serde(flatten)]
+  pub message: Message,
+  pub rank: f32,
+  /// HTML escaped excerpt with matches wrapped in `<mark>`
+  pub snippet: String,
+}
+
+/// A previous version of an edited message.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct MessageRevision {
+  pub id: i64,
+  pub message_id: i64,
+  pub content: String,
+  pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReactionCount {
+  pub emoji: String,
+  pub count: i64,
+  /// whether the current user is one of the reactors
+  pub reacted: bool,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize, PartialEq)]
+pub struct Reaction {
+  pub message_id: i64,
+  pub chat_id: i64,
+  pub user_id: i64,
+  pub emoji: String,
   pub created_at: DateTime<Utc>,
 }
 
@@ -1,5 +1,5 @@
 use axum::{
-  extract::{FromRequestParts, Request, State},
+  extract::{FromRequestParts, Query, Request, State},
   http::StatusCode,
   middleware::Next,
   response::{IntoResponse, Response},
@@ -8,40 +8,82 @@ use axum_extra::{
   headers::{authorization::Bearer, Authorization},
   TypedHeader,
 };
+use serde::Deserialize;
 use tracing::warn;
 
 use super::TokenVerify;
 
+#[derive(Debug, Deserialize)]
+struct Params {
+  access_token: String,
+}
+
 pub async fn verify_token<T>(State(state): State<T>, req: Request, next: Next) -> Response
 where
   T: TokenVerify + Clone + Send + Sync + 'static,
 {
   let (mut parts, body) = req.into_parts();
-  let req = match TypedHeader::<Authorization<Bearer>>::from_request_parts(&mut parts, &state).await
-  {
-    Ok(TypedHeader(Authorization(bearer))) => {
-      let token = bearer.token();
-      match state.verify(token) {
-        Ok(user) => {
-          let mut req = Request::from_parts(parts, body);
-          req.extensions_mut().insert(user);
-          req
-        }
-        Err(e) => {
-          let msg = format!("verify token failed: {:?}", e);
-          warn!(msg);
-          return (StatusCode::FORBIDDEN, msg).into_response();
+  let token =
+    match TypedHeader::<Authorization<Bearer>>::from_request_parts(&mut parts, &state).await {
+      Ok(TypedHeader(Authorization(bearer))) => bearer.token().to_string(),
+      Err(e) => {
+        let msg = format!("parse Authorization header failed: {}", e);
+        warn!(msg);
+        return (StatusCode::UNAUTHORIZED, msg).into_response();
+      }
+    };
+
+  run_authorized(&state, Request::from_parts(parts, body), &token, next).await
+}
+
+/// Like `verify_token`, but also takes the token from the `access_token` query param. Only for
+/// routes opened by browser EventSource, which can't set headers, as urls end up in access logs.
+pub async fn verify_query_token<T>(State(state): State<T>, req: Request, next: Next) -> Response
+where
+  T: TokenVerify + Clone + Send + Sync + 'static,
+{
+  let (mut parts, body) = req.into_parts();
+  let token =
+    match TypedHeader::<Authorization<Bearer>>::from_request_parts(&mut parts, &state).await {
+      Ok(TypedHeader(Authorization(bearer))) => bearer.token().to_string(),
+      Err(e) if e.is_missing() => {
+        match Query::<Params>::from_request_parts(&mut parts, &state).await {
+          Ok(params) => params.0.access_token,
+          Err(e) => {
+            let msg = format!("parse query params failed: {}", e);
+            warn!(msg);
+            return (StatusCode::UNAUTHORIZED, msg).into_response();
+          }
         }
       }
+      Err(e) => {
+        let msg = format!("parse Authorization header failed: {}", e);
+        warn!(msg);
+        return (StatusCode::UNAUTHORIZED, msg).into_response();
+      }
+    };
+
+  run_authorized(&state, Request::from_parts(parts, body), &token, next).await
+}
+
+/// Verifies the token and runs the request with its user.
+async fn run_authorized<T: TokenVerify>(
+  state: &T,
+  mut req: Request,
+  token: &str,
+  next: Next,
+) -> Response {
+  match state.verify(token) {
+    Ok(user) => {
+      req.extensions_mut().insert(user);
+      next.run(req).await
     }
     Err(e) => {
-      let msg = format!("parse Authorization header failed: {}", e);
+      let msg = format!("verify token failed: {:?}", e);
       warn!(msg);
-      return (StatusCode::UNAUTHORIZED, msg).into_response();
+      (StatusCode::FORBIDDEN, msg).into_response()
     }
-  };
-
-  next.run(req).await
+  }
 }
 
 #[cfg(test)]
@@ -98,6 +140,13 @@ mod tests {
     let res = app.clone().oneshot(req).await?;
     assert_eq!(res.status(), StatusCode::OK);
 
+    // tokens in the query string are only accepted by verify_query_token
+    let req = Request::builder()
+      .uri(format!("/?access_token={}", token))
+      .body(Body::empty())?;
+    let res = app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
     // no token
     let req = Request::builder().uri("/").body(Body::empty())?;
     let res = app.clone().oneshot(req).await?;
@@ -113,4 +162,50 @@ mod tests {
 
     Ok(())
   }
+
+  #[tokio::test]
+  async fn verify_query_token_middleware_should_work() -> Result<()> {
+    let encoding_pem = include_str!("../../fixtures/encoding.pem");
+    let decoding_pem = include_str!("../../fixtures/decoding.pem");
+    let ek = EncodingKey::load(encoding_pem)?;
+    let dk = DecodingKey::load(decoding_pem)?;
+
+    let state = AppState(Arc::new(AppStateInner { ek, dk }));
+
+    let user = User::new(1, "Halzzzz", "halzzz@acme.org");
+    let token = state.0.ek.sign(user)?;
+
+    let app = Router::new()
+      .route("/", get(handler))
+      .layer(from_fn_with_state(
+        state.clone(),
+        verify_query_token::<AppState>,
+      ))
+      .with_state(state);
+
+    let req = Request::builder()
+      .uri("/")
+      .header("Authorization", format!("Bearer {}", token))
+      .body(Body::empty())?;
+    let res = app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let req = Request::builder()
+      .uri(format!("/?access_token={}", token))
+      .body(Body::empty())?;
+    let res = app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let req = Request::builder().uri("/").body(Body::empty())?;
+    let res = app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let req = Request::builder()
+      .uri("/?access_token=bad-token")
+      .body(Body::empty())?;
+    let res = app.oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    Ok(())
+  }
 }
@@ -15,7 +15,7 @@ use tower_http::{
 use tracing::Level;
 
 use self::{request_id::set_request_id, server_time::ServerTimeLayer};
-pub use auth::verify_token;
+pub use auth::{verify_query_token, verify_token};
 
 pub trait TokenVerify {
   type Error: fmt::Debug;
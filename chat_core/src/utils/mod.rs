@@ -1,3 +1,5 @@
 mod jwt;
+mod rich_text;
 
 pub use jwt::{DecodingKey, EncodingKey};
+pub use rich_text::{Block, Inline, ListItem, RichText, RICH_TEXT_VERSION};
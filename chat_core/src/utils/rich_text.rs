@@ -0,0 +1,494 @@
+use serde::{Deserialize, Serialize};
+
+/// Version of the block format, bumped on incompatible changes.
+pub const RICH_TEXT_VERSION: u32 = 1;
+
+// deeper quotes and spans are kept as plain text
+const MAX_DEPTH: usize = 8;
+const MAX_LANGUAGE_LENGTH: usize = 32;
+// spans are only looked for this far ahead, so unclosed markup doesn't rescan the whole line
+const MAX_SPAN_LENGTH: usize = 1024;
+const LINK_SCHEMES: [&str; 3] = ["http://", "https://", "mailto:"];
+
+/// Message content parsed from a markdown subset: bold, italics, code, code blocks, links,
+/// lists, quotes and mentions.
+///
+/// Every string in it is plain text that must be rendered as text, never as HTML. Links only
+/// use http, https or mailto urls.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RichText {
+  pub version: u32,
+  pub blocks: Vec<Block>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Block {
+  Paragraph {
+    children: Vec<Inline>,
+  },
+  CodeBlock {
+    language: Option<String>,
+    text: String,
+  },
+  Quote {
+    blocks: Vec<Block>,
+  },
+  List {
+    ordered: bool,
+    items: Vec<ListItem>,
+  },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ListItem {
+  pub children: Vec<Inline>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Inline {
+  Text {
+    text: String,
+  },
+  Bold {
+    children: Vec<Inline>,
+  },
+  Italic {
+    children: Vec<Inline>,
+  },
+  Code {
+    text: String,
+  },
+  Link {
+    url: String,
+    children: Vec<Inline>,
+  },
+  /// `<@id>`
+  UserMention {
+    user_id: i64,
+  },
+  /// `@handle`, lowercased, `channel` and `here` address the whole chat
+  Mention {
+    handle: String,
+  },
+  LineBreak,
+}
+
+impl RichText {
+  pub fn parse(content: &str) -> Self {
+    Self {
+      version: RICH_TEXT_VERSION,
+      blocks: parse_blocks(content, 0),
+    }
+  }
+
+  /// All inlines outside of code, depth first.
+  pub fn inlines(&self) -> Vec<&Inline> {
+    let mut inlines = Vec::new();
+    collect_blocks(&self.blocks, &mut inlines);
+    inlines
+  }
+}
+
+fn collect_blocks<'a>(blocks: &'a [Block], out: &mut Vec<&'a Inline>) {
+  for block in blocks {
+    match block {
+      Block::Paragraph { children } => collect_inlines(children, out),
+      Block::CodeBlock { .. } => {}
+      Block::Quote { blocks } => collect_blocks(blocks, out),
+      Block::List { items, .. } => {
+        for item in items {
+          collect_inlines(&item.children, out);
+        }
+      }
+    }
+  }
+}
+
+fn collect_inlines<'a>(inlines: &'a [Inline], out: &mut Vec<&'a Inline>) {
+  for inline in inlines {
+    out.push(inline);
+    match inline {
+      Inline::Bold { children } | Inline::Italic { children } | Inline::Link { children, .. } => {
+        collect_inlines(children, out)
+      }
+      _ => {}
+    }
+  }
+}
+
+fn parse_blocks(text: &str, depth: usize) -> Vec<Block> {
+  let lines: Vec<&str> = text.lines().collect();
+  let mut blocks = Vec::new();
+  let mut paragraph: Vec<&str> = Vec::new();
+  let mut i = 0;
+  while i < lines.len() {
+    let line = lines[i];
+    if let Some(language) = line.trim_start().strip_prefix("```") {
+      flush_paragraph(&mut paragraph, &mut blocks, depth);
+      let end = lines[i + 1..]
+        .iter()
+        .position(|l| l.trim() == "```")
+        .map_or(lines.len(), |p| i + 1 + p);
+      blocks.push(Block::CodeBlock {
+        language: code_language(language),
+        text: strip_control(&lines[i + 1..end].join("\n")),
+      });
+      i = end + 1;
+    } else if depth < MAX_DEPTH && quote_line(line).is_some() {
+      flush_paragraph(&mut paragraph, &mut blocks, depth);
+      let mut quoted = Vec::new();
+      while let Some(rest) = lines.get(i).and_then(|l| quote_line(l)) {
+        quoted.push(rest);
+        i += 1;
+      }
+      blocks.push(Block::Quote {
+        blocks: parse_blocks(&quoted.join("\n"), depth + 1),
+      });
+    } else if let Some((ordered, _)) = list_item(line) {
+      flush_paragraph(&mut paragraph, &mut blocks, depth);
+      let mut items = Vec::new();
+      while let Some((_, rest)) = lines
+        .get(i)
+        .and_then(|l| list_item(l))
+        .filter(|(o, _)| *o == ordered)
+      {
+        items.push(ListItem {
+          children: parse_inlines(rest, depth),
+        });
+        i += 1;
+      }
+      blocks.push(Block::List { ordered, items });
+    } else if line.trim().is_empty() {
+      flush_paragraph(&mut paragraph, &mut blocks, depth);
+      i += 1;
+    } else {
+      paragraph.push(line);
+      i += 1;
+    }
+  }
+  flush_paragraph(&mut paragraph, &mut blocks, depth);
+  blocks
+}
+
+fn flush_paragraph(lines: &mut Vec<&str>, blocks: &mut Vec<Block>, depth: usize) {
+  if lines.is_empty() {
+    return;
+  }
+  let mut children = Vec::new();
+  for (i, line) in lines.drain(..).enumerate() {
+    if i > 0 {
+      children.push(Inline::LineBreak);
+    }
+    children.extend(parse_inlines(line, depth));
+  }
+  blocks.push(Block::Paragraph { children });
+}
+
+fn quote_line(line: &str) -> Option<&str> {
+  let rest = line.trim_start().strip_prefix('>')?;
+  Some(rest.strip_prefix(' ').unwrap_or(rest))
+}
+
+/// `- item`, `* item` or `1. item`, with whether the list is ordered.
+fn list_item(line: &str) -> Option<(bool, &str)> {
+  let line = line.trim_start();
+  if let Some(rest) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
+    return Some((false, rest));
+  }
+  let digits = line.find(|c: char| !c.is_ascii_digit())?;
+  let rest = line[digits..].strip_prefix(". ")?;
+  (digits > 0).then_some((true, rest))
+}
+
+fn code_language(language: &str) -> Option<String> {
+  let language = language.trim();
+  let valid = !language.is_empty()
+    && language.len() <= MAX_LANGUAGE_LENGTH
+    && language
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '#' | '.' | '_'));
+  valid.then(|| language.to_lowercase())
+}
+
+fn parse_inlines(s: &str, depth: usize) -> Vec<Inline> {
+  let mut inlines = Vec::new();
+  let mut text = String::new();
+  let mut i = 0;
+  while let Some(c) = s[i..].chars().next() {
+    let prev = s[..i].chars().next_back();
+    if let Some((inline, len)) = parse_span(&s[i..], prev, depth) {
+      if !text.is_empty() {
+        inlines.push(Inline::Text {
+          text: std::mem::take(&mut text),
+        });
+      }
+      inlines.push(inline);
+      i += len;
+      continue;
+    }
+    if !c.is_control() || c == '\t' {
+      text.push(c);
+    }
+    i += c.len_utf8();
+  }
+  if !text.is_empty() {
+    inlines.push(Inline::Text { text });
+  }
+  inlines
+}
+
+/// The span starting at `rest`, with its length in bytes.
+fn parse_span(rest: &str, prev: Option<char>, depth: usize) -> Option<(Inline, usize)> {
+  // an @ or _ inside a word, like in an email address or snake_case, isn't markup
+  let at_word_start = prev.is_none_or(|p| !p.is_alphanumeric());
+  let first = rest.chars().next()?;
+  match first {
+    '`' => {
+      let end = span_window(&rest[1..]).find('`').filter(|end| *end > 0)?;
+      let text = strip_control(&rest[1..end + 1]);
+      Some((Inline::Code { text }, end + 2))
+    }
+    '<' => {
+      let body = rest.strip_prefix("<@")?;
+      let end = span_window(body).find('>')?;
+      let user_id = body[..end].parse().ok()?;
+      Some((Inline::UserMention { user_id }, end + 3))
+    }
+    '@' if at_word_start => {
+      let handle: String = rest[1..]
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        .collect();
+      // trailing punctuation ends the sentence, not the handle
+      let handle = handle.trim_end_matches(['.', '-']);
+      if handle.is_empty() {
+        return None;
+      }
+      let len = handle.len() + 1;
+      let handle = handle.to_lowercase();
+      Some((Inline::Mention { handle }, len))
+    }
+    'h' if at_word_start && rest.starts_with("http") => {
+      let url = rest
+        .split(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"'))
+        .next()?
+        .trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '\'']);
+      let url = safe_url(url)?;
+      let len = url.len();
+      let children = vec![Inline::Text { text: url.clone() }];
+      Some((Inline::Link { url, children }, len))
+    }
+    _ if depth >= MAX_DEPTH => None,
+    '*' if rest.starts_with("**") => {
+      let end = span_window(&rest[2..]).find("**").filter(|end| *end > 0)?;
+      let children = parse_inlines(&rest[2..end + 2], depth + 1);
+      Some((Inline::Bold { children }, end + 4))
+    }
+    '*' | '_' if at_word_start => {
+      let body = &rest[1..];
+      let end = span_window(body).find(first)?;
+      let inner = &body[..end];
+      let next = body[end + 1..].chars().next();
+      let valid = !inner.is_empty()
+        && !inner.starts_with(char::is_whitespace)
+        && !inner.ends_with(char::is_whitespace)
+        && next.is_none_or(|n| !n.is_alphanumeric());
+      if !valid {
+        return None;
+      }
+      let children = parse_inlines(inner, depth + 1);
+      Some((Inline::Italic { children }, end + 2))
+    }
+    '[' => {
+      let (label, after) = span_window(&rest[1..]).split_once("](")?;
+      let (url, _) = after.split_once(')')?;
+      let len = label.len() + url.len() + 4;
+      let url = safe_url(url.trim())?;
+      let children = parse_inlines(label, depth + 1);
+      Some((Inline::Link { url, children }, len))
+    }
+    _ => None,
+  }
+}
+
+/// The start of `s` a span can end in.
+fn span_window(s: &str) -> &str {
+  if s.len() <= MAX_SPAN_LENGTH {
+    return s;
+  }
+  let mut end = MAX_SPAN_LENGTH;
+  while !s.is_char_boundary(end) {
+    end -= 1;
+  }
+  &s[..end]
+}
+
+/// Urls with an allowed scheme and no characters that could break out of an attribute.
+fn safe_url(url: &str) -> Option<String> {
+  let lower = url.to_ascii_lowercase();
+  let allowed = LINK_SCHEMES
+    .iter()
+    .any(|scheme| lower.starts_with(scheme) && lower.len() > scheme.len());
+  let clean = !url
+    .chars()
+    .any(|c| c.is_whitespace() || c.is_control() || matches!(c, '<' | '>' | '"' | '\'' | '`'));
+  (allowed && clean).then(|| url.to_string())
+}
+
+fn strip_control(s: &str) -> String {
+  s.chars()
+    .filter(|c| !c.is_control() || matches!(c, '\n' | '\t'))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn text(text: &str) -> Inline {
+    Inline::Text {
+      text: text.to_string(),
+    }
+  }
+
+  #[test]
+  fn rich_text_should_parse_inlines() {
+    let rich = RichText::parse("**Hi** _all_, see `a*b` at [docs](https://x.org/a) <@3> @Bob.");
+    assert_eq!(rich.version, RICH_TEXT_VERSION);
+    let Block::Paragraph { children } = &rich.blocks[0] else {
+      panic!("expected a paragraph");
+    };
+    assert_eq!(
+      children,
+      &[
+        Inline::Bold {
+          children: vec![text("Hi")]
+        },
+        text(" "),
+        Inline::Italic {
+          children: vec![text("all")]
+        },
+        text(", see "),
+        Inline::Code {
+          text: "a*b".to_string()
+        },
+        text(" at "),
+        Inline::Link {
+          url: "https://x.org/a".to_string(),
+          children: vec![text("docs")]
+        },
+        text(" "),
+        Inline::UserMention { user_id: 3 },
+        text(" "),
+        Inline::Mention {
+          handle: "bob".to_string()
+        },
+        text("."),
+      ]
+    );
+  }
+
+  #[test]
+  fn rich_text_should_parse_blocks() {
+    let content =
+      "intro\nnext line\n\n> quoted\n> - item\n\n1. one\n2. two\n```rust\nlet a = 1;\n```";
+    let rich = RichText::parse(content);
+    assert_eq!(
+      rich.blocks,
+      [
+        Block::Paragraph {
+          children: vec![text("intro"), Inline::LineBreak, text("next line")]
+        },
+        Block::Quote {
+          blocks: vec![
+            Block::Paragraph {
+              children: vec![text("quoted")]
+            },
+            Block::List {
+              ordered: false,
+              items: vec![ListItem {
+                children: vec![text("item")]
+              }]
+            },
+          ]
+        },
+        Block::List {
+          ordered: true,
+          items: vec![
+            ListItem {
+              children: vec![text("one")]
+            },
+            ListItem {
+              children: vec![text("two")]
+            },
+          ]
+        },
+        Block::CodeBlock {
+          language: Some("rust".to_string()),
+          text: "let a = 1;".to_string()
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn rich_text_should_drop_unsafe_markup() {
+    let content = "[x](javascript:alert(1)) <script>x</script> snake_case_name 2 * 3 * 4";
+    let rich = RichText::parse(content);
+    // nothing but text survives
+    assert_eq!(
+      rich.blocks,
+      [Block::Paragraph {
+        children: vec![text(content)]
+      }]
+    );
+
+    let rich = RichText::parse("```<img onerror=x>\n\u{0}code\n```");
+    assert_eq!(
+      rich.blocks,
+      [Block::CodeBlock {
+        language: None,
+        text: "code".to_string()
+      }]
+    );
+
+    // deep nesting stops at a fixed depth
+    let content = format!("{}deep", "> ".repeat(100));
+    let mut blocks = &RichText::parse(&content).blocks;
+    let mut depth = 0;
+    while let [Block::Quote { blocks: inner }] = blocks.as_slice() {
+      blocks = inner;
+      depth += 1;
+    }
+    assert_eq!(depth, MAX_DEPTH);
+  }
+
+  #[test]
+  fn rich_text_should_bound_unclosed_spans() {
+    for content in [
+      "[".repeat(200_000),
+      "<@".repeat(100_000),
+      "_a".repeat(100_000),
+    ] {
+      let rich = RichText::parse(&content);
+      assert_eq!(
+        rich.blocks,
+        [Block::Paragraph {
+          children: vec![text(&content)]
+        }]
+      );
+    }
+
+    // spans longer than the window stay text
+    let long = format!("**{}**", "a".repeat(MAX_SPAN_LENGTH));
+    let rich = RichText::parse(&long);
+    assert_eq!(
+      rich.blocks,
+      [Block::Paragraph {
+        children: vec![text(&long)]
+      }]
+    );
+  }
+}
@@ -10,13 +10,33 @@ INSERT INTO users(ws_id, email, fullname, password_hash)
 (1, 'charlie@acme.org', 'Charlie Chen', '$argon2id$v=19$m=19456,t=2,p=1$MxGhY+ib/kplwBPLa7u2ug$c5h9u7Sc8Px8J5+qgNdOjSY7ZJO2QN4rugKpapGW4XU'),
 (1, 'daisy@acme.org', 'Daisy Chen', '$argon2id$v=19$m=19456,t=2,p=1$MxGhY+ib/kplwBPLa7u2ug$c5h9u7Sc8Px8J5+qgNdOjSY7ZJO2QN4rugKpapGW4XU');
 
-INSERT INTO chats(ws_id, name, type, members)
-  VALUES (1, 'general', 'public_channel', '{1,2,3,4,5}'),
-(1, 'private', 'private_channel', '{1,2,3}');
+INSERT INTO chats(ws_id, name, slug, type, created_by)
+  VALUES (1, 'general', 'general', 'public_channel', 1),
+(1, 'private', 'private', 'private_channel', 1);
 
-INSERT INTO chats(ws_id, type, members)
-  VALUES (1, 'single', '{1,2}'),
-(1, 'group', '{1,3,4}');
+INSERT INTO chats(ws_id, type, created_by)
+  VALUES (1, 'single', 1),
+(1, 'group', 1);
+
+INSERT INTO chat_members(chat_id, user_id)
+  VALUES (1, 1),
+(1, 2),
+(1, 3),
+(1, 4),
+(1, 5),
+(2, 1),
+(2, 2),
+(2, 3),
+(3, 1),
+(3, 2),
+(4, 1),
+(4, 3),
+(4, 4);
+
+-- hal created the channels
+UPDATE chat_members
+SET role = 'creator'
+WHERE user_id = 1 AND chat_id IN (1, 2);
 
 INSERT INTO messages(chat_id, sender_id, content)
   VALUES (1, 1, 'Hello, world!'),
@@ -34,6 +34,9 @@ pub enum AppError {
   #[error("delete chat error: {0}")]
   DeleteChatError(String),
 
+  #[error("chat member error: {0}")]
+  ChatMemberError(String),
+
   #[error("not found: {0}")]
   NotFound(String),
 
@@ -45,6 +48,42 @@ pub enum AppError {
 
   #[error("create message error: {0}")]
   CreateMessageError(String),
+
+  #[error("update message error: {0}")]
+  UpdateMessageError(String),
+
+  #[error("update user error: {0}")]
+  UpdateUserError(String),
+
+  #[error("invalid password")]
+  InvalidPassword,
+
+  #[error("permission denied: {0}")]
+  PermissionDenied(String),
+
+  #[error("chat {0} is archived")]
+  ChatArchived(u64),
+
+  #[error("chat already exists: {0}")]
+  ChatAlreadyExists(String),
+
+  #[error("invalid reaction: {0}")]
+  InvalidReaction(String),
+
+  #[error("invalid search: {0}")]
+  InvalidSearch(String),
+
+  #[error("invalid pagination: {0}")]
+  InvalidPagination(String),
+
+  #[error("bookmark error: {0}")]
+  BookmarkError(String),
+
+  #[error("retention error: {0}")]
+  RetentionError(String),
+
+  #[error("command error: {0}")]
+  CommandError(String),
 }
 
 impl IntoResponse for AppError {
@@ -59,9 +98,22 @@ impl IntoResponse for AppError {
       Self::NotFound(_) => StatusCode::NOT_FOUND,
       Self::UpdateChatError(_) => StatusCode::BAD_REQUEST,
       Self::DeleteChatError(_) => StatusCode::BAD_REQUEST,
+      Self::ChatMemberError(_) => StatusCode::BAD_REQUEST,
       Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
       Self::ChatFileError(_) => StatusCode::BAD_REQUEST,
       Self::CreateMessageError(_) => StatusCode::BAD_REQUEST,
+      Self::UpdateMessageError(_) => StatusCode::BAD_REQUEST,
+      Self::UpdateUserError(_) => StatusCode::BAD_REQUEST,
+      Self::InvalidPassword => StatusCode::FORBIDDEN,
+      Self::PermissionDenied(_) => StatusCode::FORBIDDEN,
+      Self::ChatArchived(_) => StatusCode::CONFLICT,
+      Self::ChatAlreadyExists(_) => StatusCode::CONFLICT,
+      Self::InvalidReaction(_) => StatusCode::BAD_REQUEST,
+      Self::InvalidSearch(_) => StatusCode::BAD_REQUEST,
+      Self::InvalidPagination(_) => StatusCode::BAD_REQUEST,
+      Self::BookmarkError(_) => StatusCode::BAD_REQUEST,
+      Self::RetentionError(_) => StatusCode::BAD_REQUEST,
+      Self::CommandError(_) => StatusCode::BAD_REQUEST,
     };
 
     (status, Json(ErrorOutput::new(self.to_string()))).into_response()
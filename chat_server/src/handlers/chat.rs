@@ -1,6 +1,9 @@
-use crate::{AppError, AppState, CreateChat, UpdateChat};
+use crate::{
+  AppError, AppState, CreateChat, ListChannels, ListChats, MarkRead, OpenDirectChat, UpdateChat,
+  UpdateMemberRole,
+};
 use axum::{
-  extract::{Path, State},
+  extract::{Path, Query, State},
   http::StatusCode,
   response::IntoResponse,
   Extension, Json,
@@ -14,15 +17,36 @@ pub(crate) async fn create_chat_handler(
   Json(input): Json<CreateChat>,
 ) -> Result<impl IntoResponse, AppError> {
   info!("user: {:?}", user);
-  let chat = state.create_chat(input, user.ws_id as _).await?;
+  let chat = state
+    .create_chat(input, user.ws_id as _, user.id as _)
+    .await?;
   Ok((StatusCode::CREATED, Json(chat)))
 }
 
+pub(crate) async fn open_direct_chat_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Json(input): Json<OpenDirectChat>,
+) -> Result<impl IntoResponse, AppError> {
+  let (chat, created) = state
+    .get_or_create_direct_chat(user.ws_id as _, user.id as _, input)
+    .await?;
+  let status = if created {
+    StatusCode::CREATED
+  } else {
+    StatusCode::OK
+  };
+  Ok((status, Json(chat)))
+}
+
 pub(crate) async fn list_chat_handler(
   Extension(user): Extension<User>,
   State(state): State<AppState>,
+  Query(input): Query<ListChats>,
 ) -> Result<impl IntoResponse, AppError> {
-  let chat = state.fetch_chats(user.ws_id as _).await?;
+  let chat = state
+    .fetch_chats(user.ws_id as _, user.id as _, input)
+    .await?;
   info!("user: {:?}", user);
   Ok((StatusCode::OK, Json(chat)))
 }
@@ -39,18 +63,134 @@ pub(crate) async fn get_chat_handler(
 }
 
 pub(crate) async fn update_chat_handler(
+  Extension(user): Extension<User>,
   Path(id): Path<u64>,
   State(state): State<AppState>,
   Json(input): Json<UpdateChat>,
 ) -> Result<impl IntoResponse, AppError> {
-  let chat = state.update_chat(id, input).await?;
+  let chat = state.update_chat(id, user.id as _, input).await?;
   Ok((StatusCode::ACCEPTED, Json(chat)))
 }
 
 pub(crate) async fn delete_chat_handler(
+  Extension(user): Extension<User>,
   State(state): State<AppState>,
   Path(id): Path<u64>,
 ) -> Result<impl IntoResponse, AppError> {
-  state.delete_chat(id).await?;
+  state.delete_chat(id, user.id as _).await?;
   Ok((StatusCode::OK, "Delete Successfully"))
 }
+
+pub(crate) async fn archive_chat_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+  let chat = state.set_chat_archived(id, user.id as _, true).await?;
+  Ok(Json(chat))
+}
+
+pub(crate) async fn unarchive_chat_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+  let chat = state.set_chat_archived(id, user.id as _, false).await?;
+  Ok(Json(chat))
+}
+
+pub(crate) async fn list_chat_members_handler(
+  State(state): State<AppState>,
+  Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+  let members = state.fetch_chat_members(id).await?;
+  Ok(Json(members))
+}
+
+pub(crate) async fn update_member_role_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path((id, member_id)): Path<(u64, u64)>,
+  Json(input): Json<UpdateMemberRole>,
+) -> Result<impl IntoResponse, AppError> {
+  let member = state
+    .update_member_role(id, user.id as _, member_id, input.role)
+    .await?;
+  Ok(Json(member))
+}
+
+pub(crate) async fn mark_chat_read_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path(id): Path<u64>,
+  Json(input): Json<MarkRead>,
+) -> Result<impl IntoResponse, AppError> {
+  let marker = state.mark_chat_read(id, user.id as _, input).await?;
+  Ok(Json(marker))
+}
+
+pub(crate) async fn mute_chat_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+  let member = state.set_chat_muted(id, user.id as _, true).await?;
+  Ok(Json(member))
+}
+
+pub(crate) async fn unmute_chat_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+  let member = state.set_chat_muted(id, user.id as _, false).await?;
+  Ok(Json(member))
+}
+
+pub(crate) async fn list_chat_changes_handler(
+  State(state): State<AppState>,
+  Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+  let changes = state.fetch_chat_changes(id).await?;
+  Ok(Json(changes))
+}
+
+pub(crate) async fn get_channel_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path(slug): Path<String>,
+) -> Result<impl IntoResponse, AppError> {
+  let chat = state
+    .get_channel_by_slug(user.ws_id as _, user.id as _, &slug)
+    .await?;
+  Ok(Json(chat))
+}
+
+pub(crate) async fn list_channels_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Query(input): Query<ListChannels>,
+) -> Result<impl IntoResponse, AppError> {
+  let channels = state
+    .fetch_public_channels(user.ws_id as _, user.id as _, input)
+    .await?;
+  Ok(Json(channels))
+}
+
+pub(crate) async fn join_chat_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+  let chat = state.join_chat(id, user.id as _, user.ws_id as _).await?;
+  Ok(Json(chat))
+}
+
+pub(crate) async fn leave_chat_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+  state.leave_chat(id, user.id as _).await?;
+  Ok(StatusCode::NO_CONTENT)
+}
@@ -1,13 +1,17 @@
 use axum::{
   extract::{Multipart, Path, Query, State},
-  http::HeaderMap,
+  http::{HeaderMap, StatusCode},
   response::IntoResponse,
   Extension, Json,
 };
 use tokio::fs;
 use tracing::{info, warn};
 
-use crate::{AppError, AppState, ChatFile, CreateMessage, ListMessages};
+use crate::{
+  parse_command, AppError, AppState, ChatFile, CreateMessage, ListBookmarks, ListMentions,
+  ListMessages, ListPins, ListReplies, ListScheduledMessages, SaveBookmark, ScheduleMessage,
+  SearchMessages, UpdateMessage, UpdateScheduledMessage,
+};
 use chat_core::User;
 
 pub(crate) async fn send_message_handler(
@@ -16,19 +20,215 @@ pub(crate) async fn send_message_handler(
   Path(id): Path<u64>,
   Json(input): Json<CreateMessage>,
 ) -> Result<impl IntoResponse, AppError> {
+  // slash commands are run instead of posted, their response is only for the sender. Anything
+  // else starting with a slash, like an unknown `/word`, is sent as it is
+  if let Some(command) = parse_command(&input.content) {
+    if let Some(response) = state.run_command(command, &input, id, &user).await? {
+      return Ok(Json(response).into_response());
+    }
+  }
   let msg = state.create_message(input, id, user.id as _).await?;
-  Ok(Json(msg))
+  Ok(Json(msg).into_response())
+}
+
+pub(crate) async fn schedule_message_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path(id): Path<u64>,
+  Json(input): Json<ScheduleMessage>,
+) -> Result<impl IntoResponse, AppError> {
+  let scheduled = state.schedule_message(input, id, user.id as _).await?;
+  Ok((StatusCode::CREATED, Json(scheduled)))
+}
+
+pub(crate) async fn list_scheduled_messages_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Query(input): Query<ListScheduledMessages>,
+) -> Result<impl IntoResponse, AppError> {
+  let scheduled = state.list_scheduled_messages(user.id as _, input).await?;
+  Ok(Json(scheduled))
+}
+
+pub(crate) async fn update_scheduled_message_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path(id): Path<u64>,
+  Json(input): Json<UpdateScheduledMessage>,
+) -> Result<impl IntoResponse, AppError> {
+  let scheduled = state
+    .update_scheduled_message(id, user.id as _, input)
+    .await?;
+  Ok(Json(scheduled))
+}
+
+pub(crate) async fn cancel_scheduled_message_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+  state.cancel_scheduled_message(id, user.id as _).await?;
+  Ok(StatusCode::NO_CONTENT)
 }
 
 pub(crate) async fn list_message_handler(
+  Extension(user): Extension<User>,
   State(state): State<AppState>,
   Path(id): Path<u64>,
   Query(input): Query<ListMessages>,
 ) -> Result<impl IntoResponse, AppError> {
-  let messages = state.list_messages(input, id).await?;
+  let messages = state.list_messages(input, id, user.id as _).await?;
   Ok(Json(messages))
 }
 
+pub(crate) async fn list_mentions_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Query(input): Query<ListMentions>,
+) -> Result<impl IntoResponse, AppError> {
+  let messages = state.fetch_mentions(user.id as _, input).await?;
+  Ok(Json(messages))
+}
+
+pub(crate) async fn search_messages_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Query(input): Query<SearchMessages>,
+) -> Result<impl IntoResponse, AppError> {
+  let hits = state
+    .search_messages(user.ws_id as _, user.id as _, input)
+    .await?;
+  Ok(Json(hits))
+}
+
+pub(crate) async fn update_message_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path((id, message_id)): Path<(u64, u64)>,
+  Json(input): Json<UpdateMessage>,
+) -> Result<impl IntoResponse, AppError> {
+  let msg = state
+    .update_message(input, id, message_id, user.id as _)
+    .await?;
+  Ok(Json(msg))
+}
+
+pub(crate) async fn delete_message_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path((id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+  let msg = state.delete_message(id, message_id, user.id as _).await?;
+  Ok(Json(msg))
+}
+
+pub(crate) async fn list_message_revisions_handler(
+  State(state): State<AppState>,
+  Path((id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+  let revisions = state.list_message_revisions(id, message_id).await?;
+  Ok(Json(revisions))
+}
+
+pub(crate) async fn list_replies_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path((id, message_id)): Path<(u64, u64)>,
+  Query(input): Query<ListReplies>,
+) -> Result<impl IntoResponse, AppError> {
+  let replies = state
+    .list_replies(input, id, message_id, user.id as _)
+    .await?;
+  Ok(Json(replies))
+}
+
+pub(crate) async fn pin_message_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path((id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+  let pin = state.pin_message(id, message_id, user.id as _).await?;
+  Ok(Json(pin))
+}
+
+pub(crate) async fn unpin_message_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path((id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+  state.unpin_message(id, message_id, user.id as _).await?;
+  Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn list_pins_handler(
+  State(state): State<AppState>,
+  Path(id): Path<u64>,
+  Query(input): Query<ListPins>,
+) -> Result<impl IntoResponse, AppError> {
+  let pins = state.list_pins(id, input).await?;
+  Ok(Json(pins))
+}
+
+pub(crate) async fn save_bookmark_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path((id, message_id)): Path<(u64, u64)>,
+  Json(input): Json<SaveBookmark>,
+) -> Result<impl IntoResponse, AppError> {
+  let bookmark = state
+    .save_bookmark(id, message_id, user.id as _, input)
+    .await?;
+  Ok(Json(bookmark))
+}
+
+pub(crate) async fn remove_bookmark_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path((id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+  state.remove_bookmark(id, message_id, user.id as _).await?;
+  Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn list_bookmarks_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Query(input): Query<ListBookmarks>,
+) -> Result<impl IntoResponse, AppError> {
+  let bookmarks = state.list_bookmarks(user.id as _, input).await?;
+  Ok(Json(bookmarks))
+}
+
+pub(crate) async fn add_reaction_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path((id, message_id, emoji)): Path<(u64, u64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+  let reaction = state
+    .add_reaction(id, message_id, user.id as _, &emoji)
+    .await?;
+  Ok(Json(reaction))
+}
+
+pub(crate) async fn remove_reaction_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path((id, message_id, emoji)): Path<(u64, u64, String)>,
+) -> Result<impl IntoResponse, AppError> {
+  state
+    .remove_reaction(id, message_id, user.id as _, &emoji)
+    .await?;
+  Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn list_reactions_handler(
+  State(state): State<AppState>,
+  Path((id, message_id)): Path<(u64, u64)>,
+) -> Result<impl IntoResponse, AppError> {
+  let reactions = state.list_reactions(id, message_id).await?;
+  Ok(Json(reactions))
+}
+
 pub(crate) async fn file_handler(
   Extension(user): Extension<User>,
   State(state): State<AppState>,
@@ -0,0 +1,34 @@
+use crate::{AppError, AppState, ChangePassword, UpdateProfile};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+use chat_core::User;
+
+pub(crate) async fn get_profile_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+  let profile = state.get_chat_user_by_id(user.id as _).await?;
+  match profile {
+    Some(profile) => Ok(Json(profile)),
+    None => Err(AppError::NotFound(format!("user id: {}", user.id))),
+  }
+}
+
+pub(crate) async fn update_profile_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Json(input): Json<UpdateProfile>,
+) -> Result<impl IntoResponse, AppError> {
+  let profile = state
+    .update_profile(user.id as _, user.ws_id as _, input)
+    .await?;
+  Ok(Json(profile))
+}
+
+pub(crate) async fn change_password_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Json(input): Json<ChangePassword>,
+) -> Result<impl IntoResponse, AppError> {
+  state.change_password(user.id as _, &input).await?;
+  Ok(StatusCode::NO_CONTENT)
+}
@@ -1,11 +1,80 @@
-use crate::{AppError, AppState};
-use axum::{extract::State, response::IntoResponse, Extension, Json};
+use crate::{AppError, AppState, CreateSlashCommand, ListUsers, UpdateRetention};
+use axum::{
+  extract::{Path, Query, State},
+  http::StatusCode,
+  response::IntoResponse,
+  Extension, Json,
+};
 use chat_core::User;
 
 pub(crate) async fn list_chat_users_handler(
   Extension(user): Extension<User>,
   State(state): State<AppState>,
+  Query(input): Query<ListUsers>,
 ) -> Result<impl IntoResponse, AppError> {
-  let users = state.fetch_chat_users(user.ws_id as _).await?;
+  let users = state
+    .fetch_chat_users(user.ws_id as _, user.id as _, input)
+    .await?;
   Ok(Json(users))
 }
+
+pub(crate) async fn set_workspace_retention_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Json(input): Json<UpdateRetention>,
+) -> Result<impl IntoResponse, AppError> {
+  let policy = state
+    .set_workspace_retention(user.ws_id as _, user.id as _, input)
+    .await?;
+  Ok(Json(policy))
+}
+
+pub(crate) async fn set_chat_retention_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path(id): Path<u64>,
+  Json(input): Json<UpdateRetention>,
+) -> Result<impl IntoResponse, AppError> {
+  let policy = state.set_chat_retention(id, user.id as _, input).await?;
+  Ok(Json(policy))
+}
+
+pub(crate) async fn retention_report_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+  let report = state
+    .retention_report(user.ws_id as _, user.id as _)
+    .await?;
+  Ok(Json(report))
+}
+
+pub(crate) async fn list_slash_commands_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppError> {
+  let commands = state.list_slash_commands(user.ws_id as _).await?;
+  Ok(Json(commands))
+}
+
+pub(crate) async fn create_slash_command_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Json(input): Json<CreateSlashCommand>,
+) -> Result<impl IntoResponse, AppError> {
+  let command = state
+    .create_slash_command(user.ws_id as _, user.id as _, input)
+    .await?;
+  Ok((StatusCode::CREATED, Json(command)))
+}
+
+pub(crate) async fn delete_slash_command_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path(id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+  state
+    .delete_slash_command(user.ws_id as _, id, user.id as _)
+    .await?;
+  Ok(StatusCode::NO_CONTENT)
+}
@@ -7,7 +7,7 @@ mod models;
 use anyhow::Context;
 use axum::{
   middleware::from_fn_with_state,
-  routing::{delete, get, patch, post},
+  routing::{delete, get, patch, post, put},
   Router,
 };
 use chat_core::{
@@ -34,22 +34,99 @@ pub struct AppStateInner {
   pub(crate) dk: DecodingKey,
   pub(crate) ek: EncodingKey,
   pub(crate) pool: PgPool,
+  pub(crate) unfurler: Unfurler,
 }
 
 pub async fn get_router(config: AppConfig) -> Result<Router, AppError> {
   let state = AppState::try_new(config).await?;
+  spawn_scheduled_dispatcher(state.clone());
+  spawn_retention_job(state.clone());
+  Ok(app_router(state))
+}
+
+fn app_router(state: AppState) -> Router {
   let chat = Router::new()
     .route("/:id", get(get_chat_handler))
     .route("/:id", patch(update_chat_handler))
-    .route("/:id", delete(delete_chat_handler))
     .route("/:id", post(send_message_handler))
     .route("/:id/messages", get(list_message_handler))
+    .route("/:id/scheduled", post(schedule_message_handler))
+    .route(
+      "/:id/messages/:message_id",
+      patch(update_message_handler).delete(delete_message_handler),
+    )
+    .route(
+      "/:id/messages/:message_id/revisions",
+      get(list_message_revisions_handler),
+    )
+    .route(
+      "/:id/messages/:message_id/replies",
+      get(list_replies_handler),
+    )
+    .route(
+      "/:id/messages/:message_id/reactions",
+      get(list_reactions_handler),
+    )
+    .route(
+      "/:id/messages/:message_id/reactions/:emoji",
+      put(add_reaction_handler).delete(remove_reaction_handler),
+    )
+    .route(
+      "/:id/messages/:message_id/pin",
+      post(pin_message_handler).delete(unpin_message_handler),
+    )
+    .route(
+      "/:id/messages/:message_id/bookmark",
+      put(save_bookmark_handler).delete(remove_bookmark_handler),
+    )
+    .route("/:id/pins", get(list_pins_handler))
+    .route("/:id/members", get(list_chat_members_handler))
+    .route("/:id/changes", get(list_chat_changes_handler))
+    .route("/:id/read", post(mark_chat_read_handler))
+    .route(
+      "/:id/mute",
+      post(mute_chat_handler).delete(unmute_chat_handler),
+    )
+    .route("/:id/members/:user_id", patch(update_member_role_handler))
+    .route("/:id/leave", post(leave_chat_handler))
     .layer(from_fn_with_state(state.clone(), verify_chat))
+    // joining is how non-members get in, so it skips verify_chat
+    .route("/:id/join", post(join_chat_handler))
+    // workspace admins can archive, delete and set retention of chats they are not a member of
+    .route("/:id", delete(delete_chat_handler))
+    .route(
+      "/:id/archive",
+      post(archive_chat_handler).delete(unarchive_chat_handler),
+    )
+    .route("/:id/retention", put(set_chat_retention_handler))
+    .route("/direct", post(open_direct_chat_handler))
     .route("/", get(list_chat_handler).post(create_chat_handler));
 
   let api = Router::new()
     .route("/users", get(list_chat_users_handler))
+    .route(
+      "/users/me",
+      get(get_profile_handler).patch(update_profile_handler),
+    )
+    .route("/users/me/password", put(change_password_handler))
     .nest("/chats", chat)
+    .route("/channels", get(list_channels_handler))
+    .route("/channels/:slug", get(get_channel_handler))
+    .route("/mentions", get(list_mentions_handler))
+    .route("/search", get(search_messages_handler))
+    .route("/bookmarks", get(list_bookmarks_handler))
+    .route("/scheduled", get(list_scheduled_messages_handler))
+    .route("/retention", put(set_workspace_retention_handler))
+    .route("/retention/report", get(retention_report_handler))
+    .route(
+      "/commands",
+      get(list_slash_commands_handler).post(create_slash_command_handler),
+    )
+    .route("/commands/:id", delete(delete_slash_command_handler))
+    .route(
+      "/scheduled/:id",
+      patch(update_scheduled_message_handler).delete(cancel_scheduled_message_handler),
+    )
     .route("/upload", post(upload_handler))
     .route("/files/:ws_id/*path", get(file_handler))
     .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
@@ -61,7 +138,7 @@ pub async fn get_router(config: AppConfig) -> Result<Router, AppError> {
     .nest("/api", api)
     .with_state(state);
 
-  Ok(set_layer(app))
+  set_layer(app)
 }
 
 // state.config => state.inner.config
@@ -97,6 +174,7 @@ impl AppState {
         ek,
         dk,
         pool,
+        unfurler: Unfurler::default(),
       }),
     })
   }
@@ -130,6 +208,11 @@ mod test_util {
           dk,
           ek,
           pool,
+          // tests unfurl links to local fixture servers
+          unfurler: Unfurler {
+            allow_private: true,
+            ..Default::default()
+          },
         }),
       };
       Ok((tdb, state))
@@ -157,3 +240,43 @@ mod test_util {
     (tdb, pool)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use anyhow::Result;
+  use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+  };
+  use tower::ServiceExt;
+
+  #[tokio::test]
+  async fn chat_routes_with_several_path_params_should_work() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let user = state.find_user_by_id(1).await?.expect("user should exist");
+    let token = state.ek.sign(user)?;
+    let app = app_router(state);
+
+    // verify_chat only reads the chat id of /:id/members/:user_id
+    let req = Request::builder()
+      .method(Method::PATCH)
+      .uri("/api/chats/1/members/2")
+      .header("Authorization", format!("Bearer {}", token))
+      .header("Content-Type", "application/json")
+      .body(Body::from(r#"{"role":"admin"}"#))?;
+    let res = app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // not a member of the chat
+    let req = Request::builder()
+      .method(Method::PATCH)
+      .uri("/api/chats/5/members/2")
+      .header("Authorization", format!("Bearer {}", token))
+      .header("Content-Type", "application/json")
+      .body(Body::from(r#"{"role":"admin"}"#))?;
+    let res = app.oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    Ok(())
+  }
+}
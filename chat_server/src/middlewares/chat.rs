@@ -6,12 +6,20 @@ use axum::{
 
 use crate::{AppError, AppState};
 use chat_core::User;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+struct ChatPath {
+  id: u64,
+}
 
 pub async fn verify_chat(State(state): State<AppState>, req: Request, next: Next) -> Response {
   let (mut parts, body) = req.into_parts();
-  let Path(chat_id) = Path::<u64>::from_request_parts(&mut parts, &state)
-    .await
-    .unwrap();
+  // nested routes like /:id/messages/:message_id carry more params than the chat id
+  let chat_id = match Path::<ChatPath>::from_request_parts(&mut parts, &state).await {
+    Ok(Path(path)) => path.id,
+    Err(e) => return e.into_response(),
+  };
 
   let user = parts.extensions.get::<User>().unwrap();
   if !state
@@ -52,7 +60,7 @@ mod tests {
       .route("/chat/:id/messages", get(handler))
       .layer(from_fn_with_state(state.clone(), verify_chat))
       .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
-      .with_state(state);
+      .with_state(state.clone());
 
     // user in chat
     let req = Request::builder()
@@ -71,6 +79,19 @@ mod tests {
 
     let res = app.oneshot(req).await?;
     assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    // routes with more params than the chat id
+    let app = Router::new()
+      .route("/chat/:id/messages/:message_id", get(handler))
+      .layer(from_fn_with_state(state.clone(), verify_chat))
+      .layer(from_fn_with_state(state.clone(), verify_token::<AppState>))
+      .with_state(state);
+    let req = Request::builder()
+      .uri("/chat/1/messages/2")
+      .header("Authorization", format!("Bearer {}", token))
+      .body(Body::empty())?;
+    let res = app.oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::OK);
     Ok(())
   }
 }
@@ -0,0 +1,180 @@
+use crate::{AppError, AppState};
+use chat_core::{Bookmark, BookmarkedMessage};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SaveBookmark {
+  /// empty notes are cleared
+  pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListBookmarks {
+  /// bookmarks saved before this one
+  pub last_id: Option<u64>,
+  pub limit: Option<u64>,
+}
+
+const MAX_NOTE_LENGTH: usize = 500;
+const DEFAULT_BOOKMARK_PAGE_SIZE: u64 = 20;
+const MAX_BOOKMARK_PAGE_SIZE: u64 = 100;
+
+impl AppState {
+  /// Saves a message to the user's bookmarks, saving it again replaces the note.
+  pub async fn save_bookmark(
+    &self,
+    chat_id: u64,
+    message_id: u64,
+    user_id: u64,
+    input: SaveBookmark,
+  ) -> Result<Bookmark, AppError> {
+    let note = input
+      .note
+      .map(|note| note.trim().to_string())
+      .filter(|note| !note.is_empty());
+    if note
+      .as_ref()
+      .is_some_and(|note| note.chars().count() > MAX_NOTE_LENGTH)
+    {
+      return Err(AppError::BookmarkError(format!(
+        "note must be at most {} characters",
+        MAX_NOTE_LENGTH
+      )));
+    }
+
+    let bookmark = sqlx::query_as(
+      r#"
+      INSERT INTO bookmarks (user_id, message_id, chat_id, note)
+      SELECT $3, id, chat_id, $4
+      FROM messages
+      WHERE id = $2 AND chat_id = $1 AND deleted_at IS NULL
+      ON CONFLICT (user_id, message_id) DO UPDATE SET note = EXCLUDED.note
+      RETURNING id, user_id, message_id, chat_id, note, created_at
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(message_id as i64)
+    .bind(user_id as i64)
+    .bind(note)
+    .fetch_optional(&self.pool)
+    .await?;
+
+    bookmark.ok_or_else(|| {
+      AppError::NotFound(format!(
+        "Message {} not found in chat {}",
+        message_id, chat_id
+      ))
+    })
+  }
+
+  pub async fn remove_bookmark(
+    &self,
+    chat_id: u64,
+    message_id: u64,
+    user_id: u64,
+  ) -> Result<(), AppError> {
+    let ret = sqlx::query(
+      r#"
+      DELETE FROM bookmarks
+      WHERE chat_id = $1 AND message_id = $2 AND user_id = $3
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(message_id as i64)
+    .bind(user_id as i64)
+    .execute(&self.pool)
+    .await?;
+
+    if ret.rows_affected() == 0 {
+      return Err(AppError::NotFound(format!(
+        "Message {} is not bookmarked",
+        message_id
+      )));
+    }
+    Ok(())
+  }
+
+  /// The user's bookmarks, most recently saved first. Bookmarks of deleted messages and
+  /// of chats the user left are hidden.
+  pub async fn list_bookmarks(
+    &self,
+    user_id: u64,
+    input: ListBookmarks,
+  ) -> Result<Vec<BookmarkedMessage>, AppError> {
+    let bookmarks = sqlx::query_as(
+      r#"
+      SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.parent_id, m.also_in_chat,
+        m.reply_count, m.last_reply_at, m.edited_at, m.deleted_at, m.rich_text, m.link_previews, m.created_at,
+        b.id AS bookmark_id, b.note, b.created_at AS bookmarked_at
+      FROM bookmarks b
+      JOIN messages m ON m.id = b.message_id
+      JOIN chat_members cm ON cm.chat_id = b.chat_id AND cm.user_id = b.user_id
+      WHERE b.user_id = $1 AND b.id < $2 AND m.deleted_at IS NULL
+      ORDER BY b.id DESC
+      LIMIT $3
+      "#,
+    )
+    .bind(user_id as i64)
+    .bind(input.last_id.unwrap_or(i64::MAX as _) as i64)
+    .bind(
+      input
+        .limit
+        .unwrap_or(DEFAULT_BOOKMARK_PAGE_SIZE)
+        .clamp(1, MAX_BOOKMARK_PAGE_SIZE) as i64,
+    )
+    .fetch_all(&self.pool)
+    .await?;
+
+    Ok(bookmarks)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use anyhow::Result;
+
+  #[tokio::test]
+  async fn bookmarks_should_be_private_and_paginated() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let note = |note: &str| SaveBookmark {
+      note: Some(note.to_string()),
+    };
+    state.save_bookmark(1, 1, 2, note("read later")).await?;
+    state
+      .save_bookmark(1, 3, 2, SaveBookmark::default())
+      .await?;
+    // saving again updates the note
+    let bookmark = state.save_bookmark(1, 1, 2, note("  ")).await?;
+    assert_eq!(bookmark.note, None);
+    let err = state.save_bookmark(2, 1, 2, note("x")).await.unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    let err = state
+      .save_bookmark(1, 4, 2, note(&"x".repeat(501)))
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::BookmarkError(_)));
+
+    let input = ListBookmarks {
+      limit: Some(1),
+      ..Default::default()
+    };
+    let bookmarks = state.list_bookmarks(2, input).await?;
+    assert_eq!(bookmarks[0].message.id, 3);
+    let input = ListBookmarks {
+      last_id: Some(bookmarks[0].bookmark_id as _),
+      limit: Some(1),
+    };
+    let bookmarks = state.list_bookmarks(2, input).await?;
+    assert_eq!(bookmarks[0].message.id, 1);
+    assert!(state
+      .list_bookmarks(1, ListBookmarks::default())
+      .await?
+      .is_empty());
+
+    state.remove_bookmark(1, 3, 2).await?;
+    let err = state.remove_bookmark(1, 3, 2).await.unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    Ok(())
+  }
+}
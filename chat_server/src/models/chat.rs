@@ -1,5 +1,7 @@
-use crate::{AppError, AppState};
-use chat_core::{Chat, ChatType};
+use crate::{escape_like, AppError, AppState};
+use chat_core::{
+  ChannelSummary, Chat, ChatChange, ChatMember, ChatRole, ChatSummary, ChatType, ReadMarker,
+};
 use serde::{Deserialize, Serialize};
 use tracing::info;
 
@@ -15,11 +17,60 @@ pub struct UpdateChat {
   pub name: Option<String>,
   pub members: Option<Vec<i64>>,
   pub public: Option<bool>,
+  /// empty string clears the topic
+  pub topic: Option<String>,
+  /// empty string clears the description
+  pub description: Option<String>,
+  /// emoji or image url, empty string clears the icon
+  pub icon: Option<String>,
 }
 
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OpenDirectChat {
+  /// the other members, the current user is always included
+  pub members: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MarkRead {
+  /// defaults to the latest message of the chat
+  pub message_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateMemberRole {
+  pub role: ChatRole,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListChannels {
+  /// prefix match against the channel name
+  pub q: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListChats {
+  /// also list public channels the user hasn't joined
+  #[serde(default)]
+  pub include_public: bool,
+  /// also list archived chats
+  #[serde(default)]
+  pub include_archived: bool,
+}
+
+const PREVIEW_LENGTH: i32 = 100;
+const MAX_TOPIC_LENGTH: usize = 250;
+const MAX_DESCRIPTION_LENGTH: usize = 500;
+const MAX_ICON_LENGTH: usize = 250;
+
 #[allow(unused)]
 impl AppState {
-  pub async fn create_chat(&self, input: CreateChat, ws_id: u64) -> Result<Chat, AppError> {
+  pub async fn create_chat(
+    &self,
+    input: CreateChat,
+    ws_id: u64,
+    user_id: u64,
+  ) -> Result<Chat, AppError> {
     let len = input.members.len();
     if len < 2 {
       return Err(AppError::CreateChatError(
@@ -33,6 +84,12 @@ impl AppState {
       ));
     }
 
+    if !input.members.contains(&(user_id as i64)) {
+      return Err(AppError::CreateChatError(
+        "Chat creator must be a member".to_string(),
+      ));
+    }
+
     let users = self.fetch_chat_user_by_ids(&input.members).await?;
     if users.len() != len {
       return Err(AppError::CreateChatError(
@@ -52,32 +109,160 @@ impl AppState {
       }
     };
 
-    let chat = sqlx::query_as(
+    let slug = match &input.name {
+      Some(name) => Some(channel_slug(name).ok_or_else(|| {
+        AppError::CreateChatError("Channel name must contain letters or digits".to_string())
+      })?),
+      None => None,
+    };
+
+    let mut members = input.members.clone();
+    members.sort_unstable();
+    let member_key = matches!(chat_type, ChatType::Single | ChatType::Group).then_some(&members);
+
+    let mut tx = self.pool.begin().await?;
+    let mut chat: Chat = sqlx::query_as(
       r#"
-      INSERT INTO chats (ws_id, name, type, members)
-      VALUES ($1, $2, $3, $4)
-      RETURNING id, ws_id, name, type, members, created_at
+      INSERT INTO chats (ws_id, name, slug, type, member_key, created_by)
+      VALUES ($1, $2, $3, $4, $5, $6)
+      RETURNING id, ws_id, name, type, topic, description, icon, slug, created_by,
+        '{}'::bigint[] AS members, archived_at, created_at
       "#,
     )
     .bind(ws_id as i64)
     .bind(input.name)
+    .bind(&slug)
     .bind(chat_type)
-    .bind(input.members)
-    .fetch_one(&self.pool)
+    .bind(member_key)
+    .bind(user_id as i64)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(|e| chat_conflict_error(e, &members, slug.as_deref()))?;
+
+    sqlx::query(
+      r#"
+      INSERT INTO chat_members (chat_id, user_id, role)
+      SELECT $1, id, CASE WHEN id = $3 THEN 'creator'::chat_role ELSE 'member' END
+      FROM unnest($2::bigint[]) AS id
+      "#,
+    )
+    .bind(chat.id)
+    .bind(&input.members)
+    .bind(user_id as i64)
+    .execute(&mut *tx)
     .await?;
+    tx.commit().await?;
 
+    chat.members = members;
     Ok(chat)
   }
 
-  pub async fn fetch_chats(&self, ws_id: u64) -> Result<Vec<Chat>, AppError> {
-    let chats = sqlx::query_as(
+  /// Returns the direct message or group chat with exactly these members, creating it if needed.
+  /// The flag is true if the chat was created.
+  pub async fn get_or_create_direct_chat(
+    &self,
+    ws_id: u64,
+    user_id: u64,
+    input: OpenDirectChat,
+  ) -> Result<(Chat, bool), AppError> {
+    let mut members = input.members;
+    members.push(user_id as i64);
+    members.sort_unstable();
+    members.dedup();
+
+    if let Some(chat) = self.find_direct_chat(ws_id, &members).await? {
+      return Ok((chat, false));
+    }
+
+    let input = CreateChat {
+      name: None,
+      members: members.clone(),
+      public: false,
+    };
+    match self.create_chat(input, ws_id, user_id).await {
+      Ok(chat) => Ok((chat, true)),
+      // someone else created it in the meantime
+      Err(AppError::ChatAlreadyExists(_)) => {
+        let chat = self.find_direct_chat(ws_id, &members).await?;
+        let chat = chat.ok_or_else(|| AppError::ChatAlreadyExists(format!("{:?}", members)))?;
+        Ok((chat, false))
+      }
+      Err(e) => Err(e),
+    }
+  }
+
+  async fn find_direct_chat(&self, ws_id: u64, members: &[i64]) -> Result<Option<Chat>, AppError> {
+    let chat = sqlx::query_as(
       r#"
-      SELECT id, ws_id, name, type, members, created_at
+      SELECT id, ws_id, name, type, topic, description, icon, slug, created_by,
+        member_key AS members, archived_at, created_at
       FROM chats
-      WHERE ws_id = $1
+      WHERE ws_id = $1 AND type IN ('single', 'group') AND member_key = $2
       "#,
     )
     .bind(ws_id as i64)
+    .bind(members)
+    .fetch_optional(&self.pool)
+    .await?;
+
+    Ok(chat)
+  }
+
+  /// Chats the user is a member of, most recently active first.
+  pub async fn fetch_chats(
+    &self,
+    ws_id: u64,
+    user_id: u64,
+    input: ListChats,
+  ) -> Result<Vec<ChatSummary>, AppError> {
+    let chats = sqlx::query_as(
+      r#"
+      SELECT c.id, c.ws_id, c.name, c.type, c.topic, c.description, c.icon, c.slug,
+        c.created_by, chat_members_of(c.id) AS members, c.archived_at, c.created_at,
+        me.user_id IS NOT NULL AS is_member,
+        m.id AS last_message_id,
+        m.sender_id AS last_message_sender_id,
+        left(m.content, $4) AS last_message_preview,
+        coalesce(m.created_at, c.created_at) AS last_activity_at,
+        me.last_read_id,
+        coalesce(me.muted, false) AS muted,
+        coalesce(r.unread_count, 0) AS unread_count,
+        coalesce(r.mention_count, 0) AS mention_count
+      FROM chats c
+      LEFT JOIN chat_members me ON me.chat_id = c.id AND me.user_id = $2
+      LEFT JOIN LATERAL (
+        SELECT id, sender_id, content, created_at
+        FROM messages
+        WHERE chat_id = c.id AND (parent_id IS NULL OR also_in_chat) AND deleted_at IS NULL
+        ORDER BY created_at DESC, id DESC
+        LIMIT 1
+      ) m ON TRUE
+      LEFT JOIN LATERAL (
+        SELECT count(*) AS unread_count,
+          count(*) FILTER (
+            WHERE c.type = 'single' OR EXISTS (
+              SELECT 1 FROM message_mentions mm
+              WHERE mm.message_id = u.id AND (mm.user_id = $2 OR mm.kind <> 'user')
+            )
+          ) AS mention_count
+        FROM messages u
+        WHERE u.chat_id = c.id AND u.sender_id <> $2
+        AND (u.parent_id IS NULL OR u.also_in_chat) AND u.deleted_at IS NULL
+        AND u.id > coalesce(me.last_read_id, 0)
+        -- without a read marker, history from before joining isn't unread
+        AND (me.last_read_id IS NOT NULL OR u.created_at >= me.joined_at)
+      ) r ON me.user_id IS NOT NULL
+      WHERE c.ws_id = $1
+      AND (me.user_id IS NOT NULL OR ($3 AND c.type = 'public_channel'))
+      AND ($5 OR c.archived_at IS NULL)
+      ORDER BY last_activity_at DESC, c.id DESC
+      "#,
+    )
+    .bind(ws_id as i64)
+    .bind(user_id as i64)
+    .bind(input.include_public)
+    .bind(PREVIEW_LENGTH)
+    .bind(input.include_archived)
     .fetch_all(&self.pool)
     .await?;
 
@@ -87,7 +272,8 @@ impl AppState {
   pub async fn get_chat_by_id(&self, id: u64) -> Result<Option<Chat>, AppError> {
     let chat = sqlx::query_as(
       r#"
-      SELECT id, ws_id, name, type, members, created_at
+      SELECT id, ws_id, name, type, topic, description, icon, slug, created_by,
+        chat_members_of(id) AS members, archived_at, created_at
       FROM chats
       WHERE id = $1
       "#,
@@ -103,8 +289,8 @@ impl AppState {
     let is_member = sqlx::query(
       r#"
       SELECT 1
-      FROM chats
-      WHERE id = $1 AND $2 = ANY(members)
+      FROM chat_members
+      WHERE chat_id = $1 AND user_id = $2
       "#,
     )
     .bind(chat_id as i64)
@@ -116,16 +302,31 @@ impl AppState {
   }
 
   // update chat info
-  pub async fn update_chat(&self, id: u64, input: UpdateChat) -> Result<Chat, AppError> {
+  pub async fn update_chat(
+    &self,
+    id: u64,
+    user_id: u64,
+    input: UpdateChat,
+  ) -> Result<Chat, AppError> {
     let mut chat = self.get_chat_by_id(id).await?;
     info!("{:?}", chat);
     let mut chat = match chat {
       Some(chat) => chat,
-      None => return Err(AppError::NotFound("Chat: {id} not found".to_string())),
+      None => return Err(AppError::NotFound(format!("Chat: {} not found", id))),
     };
+    if chat.archived_at.is_some() {
+      return Err(AppError::ChatArchived(id));
+    }
+    let role = self.get_chat_role(id, user_id).await?;
+    check_update_permission(&chat, role, user_id as _, &input)?;
+    let old = chat.clone();
 
     if let Some(name) = input.name {
+      let slug = channel_slug(&name).ok_or_else(|| {
+        AppError::UpdateChatError("Channel name must contain letters or digits".to_string())
+      })?;
       chat.name = Some(name);
+      chat.slug = Some(slug);
     }
 
     if let Some(members) = input.members {
@@ -146,31 +347,511 @@ impl AppState {
       };
     }
 
+    // empty strings clear the optional fields
+    let clear_empty = |v: String| if v.is_empty() { None } else { Some(v) };
+    if let Some(topic) = input.topic {
+      chat.topic = clear_empty(topic);
+    }
+    if let Some(description) = input.description {
+      chat.description = clear_empty(description);
+    }
+    if let Some(icon) = input.icon {
+      chat.icon = clear_empty(icon);
+    }
+    let lengths = [
+      ("topic", &chat.topic, MAX_TOPIC_LENGTH),
+      ("description", &chat.description, MAX_DESCRIPTION_LENGTH),
+      ("icon", &chat.icon, MAX_ICON_LENGTH),
+    ];
+    for (field, value, max) in lengths {
+      if value.as_ref().is_some_and(|v| v.chars().count() > max) {
+        return Err(AppError::UpdateChatError(format!(
+          "{} must be at most {} characters",
+          field, max
+        )));
+      }
+    }
+
+    let mut tx = self.pool.begin().await?;
+    sqlx::query(
+      r#"
+      UPDATE chats
+      SET name = $1, slug = $2, type = $3, topic = $4, description = $5, icon = $6
+      WHERE id = $7
+      "#,
+    )
+    .bind(&chat.name)
+    .bind(&chat.slug)
+    .bind(&chat.r#type)
+    .bind(&chat.topic)
+    .bind(&chat.description)
+    .bind(&chat.icon)
+    .bind(id as i64)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| chat_conflict_error(e, &chat.members, chat.slug.as_deref()))?;
+
+    let (fields, (old_values, new_values)): (Vec<_>, (Vec<_>, Vec<_>)) =
+      metadata_changes(&old, &chat)
+        .into_iter()
+        .map(|(field, old, new)| (field, (old, new)))
+        .unzip();
+    if !fields.is_empty() {
+      sqlx::query(
+        r#"
+        INSERT INTO chat_changes (chat_id, changed_by, field, old_value, new_value)
+        SELECT $1, $2, field, old_value, new_value
+        FROM unnest($3::text[], $4::text[], $5::text[]) AS t(field, old_value, new_value)
+        "#,
+      )
+      .bind(id as i64)
+      .bind(user_id as i64)
+      .bind(&fields)
+      .bind(&old_values)
+      .bind(&new_values)
+      .execute(&mut *tx)
+      .await?;
+    }
+
+    // keep the metadata of members who stay in the chat
+    sqlx::query(
+      r#"
+      DELETE FROM chat_members
+      WHERE chat_id = $1 AND user_id <> ALL($2)
+      "#,
+    )
+    .bind(id as i64)
+    .bind(&chat.members)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+      r#"
+      INSERT INTO chat_members (chat_id, user_id)
+      SELECT $1, unnest($2::bigint[])
+      ON CONFLICT DO NOTHING
+      "#,
+    )
+    .bind(id as i64)
+    .bind(&chat.members)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    chat.members.sort_unstable();
+    Ok(chat)
+  }
+
+  pub async fn fetch_chat_members(&self, chat_id: u64) -> Result<Vec<ChatMember>, AppError> {
+    let members = sqlx::query_as(
+      r#"
+      SELECT chat_id, user_id, role, last_read_id, muted, joined_at
+      FROM chat_members
+      WHERE chat_id = $1
+      ORDER BY user_id
+      "#,
+    )
+    .bind(chat_id as i64)
+    .fetch_all(&self.pool)
+    .await?;
+
+    Ok(members)
+  }
+
+  /// Moves the user's read marker forward to the given message, or the latest one.
+  /// Markers never move backwards.
+  pub async fn mark_chat_read(
+    &self,
+    chat_id: u64,
+    user_id: u64,
+    input: MarkRead,
+  ) -> Result<ReadMarker, AppError> {
+    let target: (Option<i64>,) = sqlx::query_as(
+      r#"
+      SELECT max(id)
+      FROM messages
+      WHERE chat_id = $1 AND ($2::bigint IS NULL OR id = $2)
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(input.message_id.map(|id| id as i64))
+    .fetch_one(&self.pool)
+    .await?;
+    if let (Some(id), None) = (input.message_id, target.0) {
+      return Err(AppError::NotFound(format!(
+        "Message {} not found in chat {}",
+        id, chat_id
+      )));
+    }
+
+    let marker = sqlx::query_as(
+      r#"
+      UPDATE chat_members
+      SET last_read_id = GREATEST(last_read_id, $3)
+      WHERE chat_id = $1 AND user_id = $2
+      RETURNING chat_id, user_id, last_read_id
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(user_id as i64)
+    .bind(target.0)
+    .fetch_optional(&self.pool)
+    .await?;
+
+    marker.ok_or_else(|| {
+      AppError::NotFound(format!(
+        "User {} is not a member of chat {}",
+        user_id, chat_id
+      ))
+    })
+  }
+
+  /// Mutes or unmutes a chat for the user. Muting only stores the preference: events are still
+  /// delivered and clients stay quiet for muted chats, except on `Mentioned`.
+  pub async fn set_chat_muted(
+    &self,
+    chat_id: u64,
+    user_id: u64,
+    muted: bool,
+  ) -> Result<ChatMember, AppError> {
+    let member = sqlx::query_as(
+      r#"
+      UPDATE chat_members
+      SET muted = $3
+      WHERE chat_id = $1 AND user_id = $2
+      RETURNING chat_id, user_id, role, last_read_id, muted, joined_at
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(user_id as i64)
+    .bind(muted)
+    .fetch_optional(&self.pool)
+    .await?;
+
+    member.ok_or_else(|| {
+      AppError::NotFound(format!(
+        "User {} is not a member of chat {}",
+        user_id, chat_id
+      ))
+    })
+  }
+
+  /// Metadata change history of a chat, most recent first.
+  pub async fn fetch_chat_changes(&self, chat_id: u64) -> Result<Vec<ChatChange>, AppError> {
+    let changes = sqlx::query_as(
+      r#"
+      SELECT id, chat_id, changed_by, field, old_value, new_value, created_at
+      FROM chat_changes
+      WHERE chat_id = $1
+      ORDER BY id DESC
+      "#,
+    )
+    .bind(chat_id as i64)
+    .fetch_all(&self.pool)
+    .await?;
+
+    Ok(changes)
+  }
+
+  /// Looks up a channel by its `#slug`. Private channels are only visible to their members.
+  pub async fn get_channel_by_slug(
+    &self,
+    ws_id: u64,
+    user_id: u64,
+    slug: &str,
+  ) -> Result<Chat, AppError> {
+    let slug = channel_slug(slug.trim_start_matches('#')).unwrap_or_default();
+    let chat = sqlx::query_as(
+      r#"
+      SELECT id, ws_id, name, type, topic, description, icon, slug, created_by,
+        chat_members_of(id) AS members, archived_at, created_at
+      FROM chats c
+      WHERE ws_id = $1 AND slug = $2
+      AND (type = 'public_channel'
+        OR EXISTS (SELECT 1 FROM chat_members WHERE chat_id = c.id AND user_id = $3))
+      "#,
+    )
+    .bind(ws_id as i64)
+    .bind(&slug)
+    .bind(user_id as i64)
+    .fetch_optional(&self.pool)
+    .await?;
+
+    chat.ok_or_else(|| AppError::NotFound(format!("Channel: #{} not found", slug)))
+  }
+
+  /// Public channels of the workspace with their member counts, for the channel browser.
+  pub async fn fetch_public_channels(
+    &self,
+    ws_id: u64,
+    user_id: u64,
+    input: ListChannels,
+  ) -> Result<Vec<ChannelSummary>, AppError> {
+    let prefix = input
+      .q
+      .map(|q| q.trim().to_string())
+      .filter(|q| !q.is_empty())
+      .map(|q| format!("{}%", escape_like(&q)));
+    let channels = sqlx::query_as(
+      r#"
+      SELECT c.id, c.ws_id, c.name, c.slug, c.topic, c.description, c.icon,
+        count(m.user_id) AS member_count,
+        coalesce(bool_or(m.user_id = $2), FALSE) AS is_member, c.created_at
+      FROM chats c
+      LEFT JOIN chat_members m ON m.chat_id = c.id
+      WHERE c.ws_id = $1 AND c.type = 'public_channel' AND c.archived_at IS NULL
+      AND ($3::text IS NULL OR c.name ILIKE $3)
+      GROUP BY c.id
+      ORDER BY c.name, c.id
+      "#,
+    )
+    .bind(ws_id as i64)
+    .bind(user_id as i64)
+    .bind(prefix)
+    .fetch_all(&self.pool)
+    .await?;
+
+    Ok(channels)
+  }
+
+  pub async fn join_chat(&self, id: u64, user_id: u64, ws_id: u64) -> Result<Chat, AppError> {
+    let chat = match self.get_chat_by_id(id).await? {
+      Some(chat) if chat.ws_id == ws_id as i64 => chat,
+      _ => return Err(AppError::NotFound(format!("Chat: {} not found", id))),
+    };
+    if chat.r#type != ChatType::PublicChannel {
+      return Err(AppError::ChatMemberError(
+        "Only public channels can be joined".to_string(),
+      ));
+    }
+    if chat.archived_at.is_some() {
+      return Err(AppError::ChatArchived(id));
+    }
+
+    sqlx::query(
+      r#"
+      INSERT INTO chat_members (chat_id, user_id)
+      VALUES ($1, $2)
+      ON CONFLICT DO NOTHING
+      "#,
+    )
+    .bind(id as i64)
+    .bind(user_id as i64)
+    .execute(&self.pool)
+    .await?;
+
+    let chat = self.get_chat_by_id(id).await?;
+    chat.ok_or_else(|| AppError::NotFound(format!("Chat: {} not found", id)))
+  }
+
+  /// Leaves a chat. A channel left without admins gets its longest-standing member promoted.
+  pub async fn leave_chat(&self, id: u64, user_id: u64) -> Result<(), AppError> {
+    let Some(chat) = self.get_chat_by_id(id).await? else {
+      return Err(AppError::NotFound(format!("Chat: {} not found", id)));
+    };
+    if chat.r#type == ChatType::Single {
+      return Err(AppError::ChatMemberError(
+        "Direct messages can't be left".to_string(),
+      ));
+    }
+
+    let mut tx = self.pool.begin().await?;
+    // admins leaving at the same time wait for each other, so one of them sees no admin left
+    sqlx::query(
+      r#"
+      SELECT user_id
+      FROM chat_members
+      WHERE chat_id = $1 AND role <> 'member'
+      FOR UPDATE
+      "#,
+    )
+    .bind(id as i64)
+    .execute(&mut *tx)
+    .await?;
+
+    let role: Option<(ChatRole,)> = sqlx::query_as(
+      r#"
+      DELETE FROM chat_members
+      WHERE chat_id = $1 AND user_id = $2
+      RETURNING role
+      "#,
+    )
+    .bind(id as i64)
+    .bind(user_id as i64)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some((role,)) = role else {
+      return Err(AppError::ChatMemberError(format!(
+        "User {} is not a member of chat {}",
+        user_id, id
+      )));
+    };
+
+    if is_channel(&chat.r#type) && role >= ChatRole::Admin {
+      sqlx::query(
+        r#"
+        UPDATE chat_members
+        SET role = 'admin'
+        WHERE chat_id = $1 AND user_id = (
+          SELECT user_id
+          FROM chat_members
+          WHERE chat_id = $1
+          ORDER BY joined_at, user_id
+          LIMIT 1
+        )
+        AND NOT EXISTS (
+          SELECT 1 FROM chat_members WHERE chat_id = $1 AND role <> 'member'
+        )
+        "#,
+      )
+      .bind(id as i64)
+      .execute(&mut *tx)
+      .await?;
+    }
+    tx.commit().await?;
+
+    Ok(())
+  }
+
+  pub async fn get_chat_role(
+    &self,
+    chat_id: u64,
+    user_id: u64,
+  ) -> Result<Option<ChatRole>, AppError> {
+    let role: Option<(ChatRole,)> = sqlx::query_as(
+      r#"
+      SELECT role
+      FROM chat_members
+      WHERE chat_id = $1 AND user_id = $2
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(user_id as i64)
+    .fetch_optional(&self.pool)
+    .await?;
+
+    Ok(role.map(|(role,)| role))
+  }
+
+  /// Promotes a member to channel admin or demotes an admin back to member. Workspace admins
+  /// can do so in channels they aren't an admin of, e.g. to recover one without admins.
+  pub async fn update_member_role(
+    &self,
+    chat_id: u64,
+    user_id: u64,
+    member_id: u64,
+    role: ChatRole,
+  ) -> Result<ChatMember, AppError> {
+    let Some(chat) = self.get_chat_by_id(chat_id).await? else {
+      return Err(AppError::NotFound(format!("Chat: {} not found", chat_id)));
+    };
+    if !is_channel(&chat.r#type) {
+      return Err(AppError::PermissionDenied(
+        "Only channels have admins".to_string(),
+      ));
+    }
+    let is_chat_admin = self.get_chat_role(chat_id, user_id).await? >= Some(ChatRole::Admin);
+    if !is_chat_admin && !self.is_workspace_admin(chat.ws_id as _, user_id).await? {
+      return Err(AppError::PermissionDenied(
+        "Only channel admins and workspace admins can change member roles".to_string(),
+      ));
+    }
+    if role == ChatRole::Creator {
+      return Err(AppError::PermissionDenied(
+        "The creator role can't be assigned".to_string(),
+      ));
+    }
+
+    let member = sqlx::query_as(
+      r#"
+      UPDATE chat_members
+      SET role = $3
+      WHERE chat_id = $1 AND user_id = $2 AND role <> 'creator'
+      RETURNING chat_id, user_id, role, last_read_id, muted, joined_at
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(member_id as i64)
+    .bind(role)
+    .fetch_optional(&self.pool)
+    .await?;
+
+    member.ok_or_else(|| {
+      AppError::NotFound(format!(
+        "Member {} of chat {} not found or is the creator",
+        member_id, chat_id
+      ))
+    })
+  }
+
+  pub async fn is_chat_archived(&self, id: u64) -> Result<bool, AppError> {
+    let archived: Option<(bool,)> = sqlx::query_as(
+      r#"
+      SELECT archived_at IS NOT NULL
+      FROM chats
+      WHERE id = $1
+      "#,
+    )
+    .bind(id as i64)
+    .fetch_optional(&self.pool)
+    .await?;
+
+    Ok(archived.map(|(archived,)| archived).unwrap_or_default())
+  }
+
+  /// Archives or unarchives a chat. Archived chats keep their history but are read-only.
+  pub async fn set_chat_archived(
+    &self,
+    id: u64,
+    user_id: u64,
+    archived: bool,
+  ) -> Result<Chat, AppError> {
+    let Some(chat) = self.get_chat_by_id(id).await? else {
+      return Err(AppError::NotFound(format!("Chat: {} not found", id)));
+    };
+
+    let is_chat_admin =
+      is_channel(&chat.r#type) && self.get_chat_role(id, user_id).await? >= Some(ChatRole::Admin);
+    if !is_chat_admin && !self.is_workspace_admin(chat.ws_id as _, user_id).await? {
+      return Err(AppError::PermissionDenied(
+        "Only channel admins and workspace admins can archive a chat".to_string(),
+      ));
+    }
+
     let chat = sqlx::query_as(
       r#"
       UPDATE chats
-      SET name = $1, members = $2, type = $3
-      WHERE id = $4
-      RETURNING id, ws_id, name, type, members, created_at
+      SET archived_at = CASE WHEN $2 THEN coalesce(archived_at, now()) END
+      WHERE id = $1
+      RETURNING id, ws_id, name, type, topic, description, icon, slug, created_by,
+        chat_members_of(id) AS members, archived_at, created_at
       "#,
     )
-    .bind(chat.name)
-    .bind(chat.members)
-    .bind(chat.r#type)
     .bind(id as i64)
+    .bind(archived)
     .fetch_one(&self.pool)
     .await?;
 
     Ok(chat)
   }
 
-  pub async fn delete_chat(&self, id: u64) -> Result<(), AppError> {
+  /// Permanently deletes a chat with its messages. Their files are left to the retention job,
+  /// which removes them once nothing refers to them and the grace period has passed.
+  pub async fn delete_chat(&self, id: u64, user_id: u64) -> Result<(), AppError> {
     let chat = self.get_chat_by_id(id).await?;
     let chat = match chat {
       Some(chat) => chat,
       None => return Err(AppError::NotFound(format!("Chat: {} not found", id))),
     };
 
+    let is_chat_admin =
+      is_channel(&chat.r#type) && self.get_chat_role(id, user_id).await? >= Some(ChatRole::Admin);
+    if !is_chat_admin && !self.is_workspace_admin(chat.ws_id as _, user_id).await? {
+      return Err(AppError::PermissionDenied(
+        "Only channel admins and workspace admins can delete a chat".to_string(),
+      ));
+    }
+
     sqlx::query(
       r#"
       DELETE FROM chats
@@ -185,6 +866,120 @@ impl AppState {
   }
 }
 
+pub(super) fn is_channel(chat_type: &ChatType) -> bool {
+  matches!(
+    chat_type,
+    ChatType::PublicChannel | ChatType::PrivateChannel
+  )
+}
+
+const MAX_SLUG_LENGTH: usize = 60;
+
+/// Normalizes a channel name into its slug, e.g. `Product Launch!` into `product-launch`.
+/// Returns `None` if the name has no letters or digits. Only ASCII is lowercased or replaced,
+/// non-ASCII characters are kept as they are, like the `channel_slug` function of the database.
+fn channel_slug(name: &str) -> Option<String> {
+  let mut slug = String::new();
+  for c in name.chars() {
+    if c.is_ascii_alphanumeric() || !c.is_ascii() {
+      slug.push(c.to_ascii_lowercase());
+    } else if !slug.is_empty() && !slug.ends_with('-') {
+      slug.push('-');
+    }
+  }
+  let slug: String = slug.chars().take(MAX_SLUG_LENGTH).collect();
+  let slug = slug.trim_end_matches('-');
+  (!slug.is_empty()).then(|| slug.to_string())
+}
+
+fn chat_conflict_error(e: sqlx::Error, members: &[i64], slug: Option<&str>) -> AppError {
+  match e {
+    sqlx::Error::Database(e) => match e.constraint() {
+      Some("chats_member_key_index") => AppError::ChatAlreadyExists(format!("{:?}", members)),
+      Some("chats_slug_index") => {
+        AppError::ChatAlreadyExists(format!("#{}", slug.unwrap_or_default()))
+      }
+      _ => sqlx::Error::Database(e).into(),
+    },
+    e => e.into(),
+  }
+}
+
+/// The metadata fields that differ between the two versions of a chat, as (field, old, new).
+fn metadata_changes(old: &Chat, new: &Chat) -> Vec<(String, Option<String>, Option<String>)> {
+  let fields = [
+    ("name", &old.name, &new.name),
+    ("topic", &old.topic, &new.topic),
+    ("description", &old.description, &new.description),
+    ("icon", &old.icon, &new.icon),
+  ];
+  let mut changes: Vec<_> = fields
+    .into_iter()
+    .filter(|(_, old, new)| old != new)
+    .map(|(field, old, new)| (field.to_string(), old.clone(), new.clone()))
+    .collect();
+  if old.r#type != new.r#type {
+    let visibility = |t: &ChatType| {
+      let v = if *t == ChatType::PublicChannel {
+        "public"
+      } else {
+        "private"
+      };
+      Some(v.to_string())
+    };
+    changes.push((
+      "visibility".to_string(),
+      visibility(&old.r#type),
+      visibility(&new.r#type),
+    ));
+  }
+  changes
+}
+
+fn check_update_permission(
+  chat: &Chat,
+  role: Option<ChatRole>,
+  user_id: i64,
+  input: &UpdateChat,
+) -> Result<(), AppError> {
+  let removed = input
+    .members
+    .as_ref()
+    .map(|members| chat.members.iter().any(|m| !members.contains(m)))
+    .unwrap_or_default();
+  let is_admin = role >= Some(ChatRole::Admin);
+
+  let denied = match chat.r#type {
+    ChatType::Single => Some("Direct messages can't be changed"),
+    ChatType::Group if input.name.is_some() || input.public.is_some() => {
+      Some("Group chats can't be renamed or made into channels")
+    }
+    ChatType::Group if input.description.is_some() || input.icon.is_some() => {
+      Some("Only channels have a description and an icon")
+    }
+    // group members can invite others, but only remove themselves
+    ChatType::Group if removed => {
+      let members = input.members.as_deref().unwrap_or_default();
+      let others_removed = chat
+        .members
+        .iter()
+        .any(|m| *m != user_id && !members.contains(m));
+      others_removed.then_some("Group members can only remove themselves")
+    }
+    ChatType::Group => None,
+    _ if !is_admin && (input.name.is_some() || input.public.is_some() || input.icon.is_some()) => {
+      Some("Only channel admins can rename a channel, change its icon or its visibility")
+    }
+    _ if !is_admin && removed => Some("Only channel admins can remove members"),
+    _ => None,
+  };
+
+  match denied {
+    Some(msg) => Err(AppError::PermissionDenied(msg.to_string())),
+    None => Ok(()),
+  }
+}
+
 #[cfg(test)]
 impl CreateChat {
   pub fn new(name: &str, members: &[i64], public: bool) -> Self {
@@ -204,15 +999,16 @@ impl CreateChat {
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::{ChatFile, CreateMessage, ScheduleMessage};
   use anyhow::Result;
 
   #[tokio::test]
   async fn create_single_chat_should_work() -> Result<()> {
     let (_tdb, state) = AppState::new_for_test().await?;
-    let input = CreateChat::new("", &[1, 2], false);
+    let input = CreateChat::new("", &[1, 5], false);
 
     let chat = state
-      .create_chat(input, 1)
+      .create_chat(input, 1, 1)
       .await
       .expect("create chat failed");
     assert_eq!(chat.ws_id, 1);
@@ -224,9 +1020,9 @@ mod tests {
   #[tokio::test]
   async fn create_public_named_chat_should_work() -> Result<()> {
     let (_tdb, state) = AppState::new_for_test().await?;
-    let input = CreateChat::new("general", &[1, 2, 3], true);
+    let input = CreateChat::new("random", &[1, 2, 3], true);
     let chat = state
-      .create_chat(input, 1)
+      .create_chat(input, 1, 1)
       .await
       .expect("create chat failed");
     assert_eq!(chat.ws_id, 1);
@@ -253,8 +1049,58 @@ mod tests {
   #[tokio::test]
   async fn chat_fetch_all_should_work() -> Result<()> {
     let (_tdb, state) = AppState::new_for_test().await?;
-    let chats = state.fetch_chats(1).await.expect("fetch all chats failed");
+    let chats = state
+      .fetch_chats(1, 1, ListChats::default())
+      .await
+      .expect("fetch all chats failed");
     assert_eq!(chats.len(), 4);
+    let general = chats.iter().find(|c| c.chat.id == 1).unwrap();
+    assert_eq!(general.last_message_id, Some(10));
+    assert_eq!(
+      general.last_message_preview.as_deref(),
+      Some("Hello, world!")
+    );
+
+    // posting a message moves the chat to the top
+    let input = CreateMessage {
+      content: "hi".to_string(),
+      files: vec![],
+      ..Default::default()
+    };
+    state.create_message(input, 3, 1).await?;
+    let chats = state.fetch_chats(1, 1, ListChats::default()).await?;
+    assert_eq!(chats[0].chat.id, 3);
+    assert_eq!(chats[0].last_message_preview.as_deref(), Some("hi"));
+
+    // deleted messages aren't previewed
+    let message_id = chats[0].last_message_id.unwrap();
+    state.delete_message(3, message_id as _, 1).await?;
+    let chats = state.fetch_chats(1, 1, ListChats::default()).await?;
+    let chat = chats.iter().find(|c| c.chat.id == 3).unwrap();
+    assert_ne!(chat.last_message_id, Some(message_id));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn chat_fetch_should_only_return_visible_chats() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let chats = state.fetch_chats(1, 5, ListChats::default()).await?;
+    assert_eq!(chats.iter().map(|c| c.chat.id).collect::<Vec<_>>(), [1]);
+
+    let input = CreateChat::new("random", &[1, 2], true);
+    let random = state.create_chat(input, 1, 1).await?;
+    let input = CreateChat::new("secret", &[1, 2], false);
+    state.create_chat(input, 1, 1).await?;
+
+    let input = ListChats {
+      include_public: true,
+      ..Default::default()
+    };
+    let chats = state.fetch_chats(1, 5, input).await?;
+    assert_eq!(chats.len(), 2);
+    assert_eq!(chats[0].chat.id, random.id);
+    assert!(!chats[0].is_member);
+    assert!(chats[1].is_member);
     Ok(())
   }
 
@@ -275,4 +1121,525 @@ mod tests {
 
     Ok(())
   }
+
+  #[tokio::test]
+  async fn update_chat_members_should_keep_existing_members() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let before = state.fetch_chat_members(2).await?;
+    assert_eq!(before.len(), 3);
+
+    let input = UpdateChat {
+      members: Some(vec![3, 1, 5]),
+      ..Default::default()
+    };
+    let chat = state.update_chat(2, 1, input).await?;
+    assert_eq!(chat.members, [1, 3, 5]);
+
+    let after = state.fetch_chat_members(2).await?;
+    assert_eq!(
+      after.iter().map(|m| m.user_id).collect::<Vec<_>>(),
+      [1, 3, 5]
+    );
+    assert_eq!(after[0], before[0]);
+    assert!(state.is_chat_member(2, 5).await?);
+    assert!(!state.is_chat_member(2, 2).await?);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn update_chat_topic_should_work() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let input = UpdateChat {
+      topic: Some("release planning".to_string()),
+      ..Default::default()
+    };
+    let chat = state.update_chat(1, 1, input).await?;
+    assert_eq!(chat.topic.as_deref(), Some("release planning"));
+
+    let input = UpdateChat {
+      topic: Some("a".repeat(MAX_TOPIC_LENGTH + 1)),
+      ..Default::default()
+    };
+    let err = state.update_chat(1, 1, input).await.unwrap_err();
+    assert!(matches!(err, AppError::UpdateChatError(_)));
+
+    // empty clears the topic
+    let input = UpdateChat {
+      topic: Some(String::new()),
+      ..Default::default()
+    };
+    let chat = state.update_chat(1, 1, input).await?;
+    assert_eq!(chat.topic, None);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn join_and_leave_chat_should_work() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let input = CreateChat::new("random", &[1, 2], true);
+    let random = state.create_chat(input, 1, 1).await?;
+
+    let channels = state
+      .fetch_public_channels(1, 5, ListChannels::default())
+      .await?;
+    assert_eq!(channels.len(), 2);
+    assert_eq!(channels[1].name.as_deref(), Some("random"));
+    assert_eq!(channels[1].member_count, 2);
+    assert!(!channels[1].is_member);
+
+    let chat = state.join_chat(random.id as _, 5, 1).await?;
+    assert_eq!(chat.members, [1, 2, 5]);
+    // joining twice is a no-op
+    state.join_chat(random.id as _, 5, 1).await?;
+    assert_eq!(state.fetch_chat_members(random.id as _).await?.len(), 3);
+
+    state.leave_chat(random.id as _, 5).await?;
+    assert!(!state.is_chat_member(random.id as _, 5).await?);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn join_and_leave_restricted_chat_should_fail() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    // private channel
+    let err = state.join_chat(2, 5, 1).await.unwrap_err();
+    assert!(matches!(err, AppError::ChatMemberError(_)));
+    // public channel of another workspace
+    let err = state.join_chat(1, 5, 2).await.unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    // direct message
+    let err = state.leave_chat(3, 1).await.unwrap_err();
+    assert!(matches!(err, AppError::ChatMemberError(_)));
+
+    // group chat
+    state.leave_chat(4, 4).await?;
+    assert!(!state.is_chat_member(4, 4).await?);
+    // leaving twice isn't a no-op
+    let err = state.leave_chat(4, 4).await.unwrap_err();
+    assert!(matches!(err, AppError::ChatMemberError(_)));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn leaving_the_last_admin_should_promote_a_member() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    // hal is the creator and only admin of the private channel
+    state.leave_chat(2, 1).await?;
+    assert_eq!(state.get_chat_role(2, 2).await?, Some(ChatRole::Admin));
+    assert_eq!(state.get_chat_role(2, 3).await?, Some(ChatRole::Member));
+
+    // another admin is left, nobody is promoted
+    state.update_member_role(2, 2, 3, ChatRole::Admin).await?;
+    state.leave_chat(2, 2).await?;
+    let members = state.fetch_chat_members(2).await?;
+    assert_eq!(members.len(), 1);
+    assert_eq!(members[0].role, ChatRole::Admin);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn update_chat_should_check_member_role() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let rename = || UpdateChat {
+      name: Some("announcements".to_string()),
+      ..Default::default()
+    };
+    // plain channel members can't rename or remove others
+    let err = state.update_chat(1, 2, rename()).await.unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+    let input = UpdateChat {
+      members: Some(vec![1, 2, 3, 4]),
+      ..Default::default()
+    };
+    let err = state.update_chat(1, 2, input).await.unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+
+    // but can once promoted
+    let member = state.update_member_role(1, 1, 2, ChatRole::Admin).await?;
+    assert_eq!(member.role, ChatRole::Admin);
+    let chat = state.update_chat(1, 2, rename()).await?;
+    assert_eq!(chat.name.as_deref(), Some("announcements"));
+
+    // admins can't take over the creator
+    let err = state
+      .update_member_role(1, 2, 1, ChatRole::Member)
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    let err = state
+      .update_member_role(1, 2, 3, ChatRole::Creator)
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+
+    // workspace admins can assign roles in any channel
+    let err = state
+      .update_member_role(1, 5, 4, ChatRole::Admin)
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+    sqlx::query("UPDATE users SET is_admin = TRUE WHERE id = 5")
+      .execute(&state.pool)
+      .await?;
+    let member = state.update_member_role(1, 5, 4, ChatRole::Admin).await?;
+    assert_eq!(member.role, ChatRole::Admin);
+    sqlx::query("UPDATE users SET is_admin = FALSE WHERE id = 5")
+      .execute(&state.pool)
+      .await?;
+
+    // direct messages can't be changed at all
+    let err = state.update_chat(3, 1, rename()).await.unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+
+    // group members can add people and remove themselves, but not rename
+    let err = state.update_chat(4, 3, rename()).await.unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+    let input = UpdateChat {
+      members: Some(vec![1, 4, 5]),
+      ..Default::default()
+    };
+    let chat = state.update_chat(4, 3, input).await?;
+    assert_eq!(chat.members, [1, 4, 5]);
+    let input = UpdateChat {
+      members: Some(vec![1, 5]),
+      ..Default::default()
+    };
+    let err = state.update_chat(4, 5, input).await.unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn archived_chat_should_be_read_only_and_hidden() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let err = state.set_chat_archived(1, 2, true).await.unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+    // not even the members of a group chat
+    let err = state.set_chat_archived(4, 1, true).await.unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+
+    let chat = state.set_chat_archived(1, 1, true).await?;
+    assert!(chat.archived_at.is_some());
+
+    let input = CreateMessage {
+      content: "hello".to_string(),
+      files: vec![],
+      ..Default::default()
+    };
+    let err = state.create_message(input.clone(), 1, 2).await.unwrap_err();
+    assert!(matches!(err, AppError::ChatArchived(1)));
+    let err = state
+      .update_chat(1, 1, UpdateChat::default())
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::ChatArchived(1)));
+
+    let chats = state.fetch_chats(1, 2, ListChats::default()).await?;
+    assert!(chats.iter().all(|c| c.chat.id != 1));
+    let input_all = ListChats {
+      include_archived: true,
+      ..Default::default()
+    };
+    let chats = state.fetch_chats(1, 2, input_all).await?;
+    assert!(chats.iter().any(|c| c.chat.id == 1));
+    let channels = state
+      .fetch_public_channels(1, 2, ListChannels::default())
+      .await?;
+    assert!(channels.is_empty());
+
+    let chat = state.set_chat_archived(1, 1, false).await?;
+    assert!(chat.archived_at.is_none());
+    state.create_message(input, 1, 2).await?;
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn delete_chat_should_check_member_role() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let err = state.delete_chat(1, 2).await.unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+    // not even the members of a group chat
+    let err = state.delete_chat(4, 1).await.unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+
+    state.delete_chat(2, 1).await?;
+    assert!(state.get_chat_by_id(2).await?.is_none());
+
+    // workspace admins can delete any chat of their workspace
+    sqlx::query("UPDATE users SET is_admin = TRUE WHERE id = 5")
+      .execute(&state.pool)
+      .await?;
+    state.delete_chat(4, 5).await?;
+    assert!(state.get_chat_by_id(4).await?.is_none());
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn delete_chat_should_leave_files_to_the_retention_job() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let write = |content: &[u8]| -> Result<ChatFile> {
+      let file = ChatFile::new(1, "test.txt", content);
+      let path = file.path(&state.config.server.base_dir);
+      std::fs::create_dir_all(path.parent().expect("file path parent should exists"))?;
+      std::fs::write(&path, content)?;
+      Ok(file)
+    };
+    let file = write(b"delete me")?;
+    let kept = write(b"keep me")?;
+    let input = CreateMessage {
+      content: "hello".to_string(),
+      files: vec![file.url(), kept.url()],
+      ..Default::default()
+    };
+    state.create_message(input, 1, 1).await?;
+    // a message scheduled in another chat still needs the file
+    let input = ScheduleMessage {
+      message: CreateMessage {
+        content: "later".to_string(),
+        files: vec![kept.url()],
+        ..Default::default()
+      },
+      send_at: chrono::Utc::now() + chrono::Duration::hours(1),
+    };
+    state.schedule_message(input, 2, 1).await?;
+
+    state.delete_chat(1, 1).await?;
+    assert!(state.get_chat_by_id(1).await?.is_none());
+    // the files stay for now, only the one nothing refers to will be removed
+    let base_dir = &state.config.server.base_dir;
+    assert!(file.path(base_dir).exists());
+    assert!(kept.path(base_dir).exists());
+    let orphans = state
+      .unreferenced_files(&[&file.url(), &kept.url()])
+      .await?;
+    assert_eq!(orphans, [file.url()]);
+    let err = state.delete_chat(1, 1).await.unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn get_or_create_direct_chat_should_reuse_existing_chat() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let dm = |members: &[i64]| OpenDirectChat {
+      members: members.to_vec(),
+    };
+    let (chat, created) = state.get_or_create_direct_chat(1, 2, dm(&[1])).await?;
+    assert!(!created);
+    assert_eq!(chat.id, 3);
+    let (chat, created) = state.get_or_create_direct_chat(1, 1, dm(&[4, 3])).await?;
+    assert!(!created);
+    assert_eq!(chat.id, 4);
+
+    // concurrent requests end up with the same chat
+    let (a, b) = tokio::join!(
+      state.get_or_create_direct_chat(1, 2, dm(&[5])),
+      state.get_or_create_direct_chat(1, 5, dm(&[2])),
+    );
+    let (a, b) = (a?, b?);
+    assert_eq!(a.0.id, b.0.id);
+    assert_eq!(a.0.r#type, ChatType::Single);
+    assert_eq!(a.0.members, [2, 5]);
+    assert!(a.1 ^ b.1);
+
+    let input = CreateChat::new("", &[5, 2], false);
+    let err = state.create_chat(input, 1, 2).await.unwrap_err();
+    assert!(matches!(err, AppError::ChatAlreadyExists(_)));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn group_member_changes_should_update_member_key() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let input = UpdateChat {
+      members: Some(vec![1, 3, 4, 5]),
+      ..Default::default()
+    };
+    state.update_chat(4, 1, input).await?;
+
+    let input = OpenDirectChat {
+      members: vec![3, 4, 5],
+    };
+    let (chat, created) = state.get_or_create_direct_chat(1, 1, input).await?;
+    assert!(!created);
+    assert_eq!(chat.id, 4);
+
+    // the old member set is free again
+    let input = OpenDirectChat {
+      members: vec![3, 4],
+    };
+    let (chat, created) = state.get_or_create_direct_chat(1, 1, input).await?;
+    assert!(created);
+    assert_eq!(chat.members, [1, 3, 4]);
+    Ok(())
+  }
+
+  #[test]
+  fn channel_slug_should_normalize_names() {
+    assert_eq!(channel_slug("General").as_deref(), Some("general"));
+    assert_eq!(
+      channel_slug("  Product Launch!! 2024 ").as_deref(),
+      Some("product-launch-2024")
+    );
+    assert_eq!(channel_slug("#Café_Talk").as_deref(), Some("café-talk"));
+    assert_eq!(channel_slug("!!!"), None);
+    assert_eq!(channel_slug(&"a".repeat(100)).map(|s| s.len()), Some(60));
+  }
+
+  #[tokio::test]
+  async fn channel_slug_should_match_the_database() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let long = format!("{} tail", "a".repeat(59));
+    let names = [
+      "General",
+      "  Product Launch!! 2024 ",
+      "#Café_Talk",
+      "ÉQUIPE Ünïcode",
+      "产品 发布",
+      "!!!",
+      long.as_str(),
+    ];
+    for name in names {
+      let (slug,): (Option<String>,) = sqlx::query_as("SELECT channel_slug($1)")
+        .bind(name)
+        .fetch_one(&state.pool)
+        .await?;
+      assert_eq!(slug, channel_slug(name), "slug of {:?}", name);
+    }
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn channel_names_should_be_unique_by_slug() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let chat = state.get_channel_by_slug(1, 5, "#general").await?;
+    assert_eq!(chat.id, 1);
+    assert_eq!(chat.created_by, Some(1));
+    // private channels are only found by members
+    let err = state
+      .get_channel_by_slug(1, 5, "private")
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    assert_eq!(state.get_channel_by_slug(1, 2, "private").await?.id, 2);
+
+    let input = CreateChat::new("GENERAL!", &[1, 2], true);
+    let err = state.create_chat(input, 1, 1).await.unwrap_err();
+    assert!(matches!(err, AppError::ChatAlreadyExists(_)));
+    // other workspaces have their own names
+    let input = CreateChat::new("General", &[1, 2], true);
+    let chat = state.create_chat(input, 2, 1).await?;
+    assert_eq!(chat.slug.as_deref(), Some("general"));
+
+    let input = UpdateChat {
+      name: Some("General".to_string()),
+      ..Default::default()
+    };
+    let err = state.update_chat(2, 1, input).await.unwrap_err();
+    assert!(matches!(err, AppError::ChatAlreadyExists(_)));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn update_chat_metadata_should_record_changes() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let input = UpdateChat {
+      name: Some("Town Square".to_string()),
+      topic: Some("Company wide news".to_string()),
+      description: Some("Announcements for everyone".to_string()),
+      icon: Some("📣".to_string()),
+      public: Some(true),
+      ..Default::default()
+    };
+    let chat = state.update_chat(1, 1, input).await?;
+    assert_eq!(chat.slug.as_deref(), Some("town-square"));
+    assert_eq!(chat.icon.as_deref(), Some("📣"));
+
+    // members can change the topic and description, but not the icon
+    let input = UpdateChat {
+      topic: Some("".to_string()),
+      ..Default::default()
+    };
+    let chat = state.update_chat(1, 2, input).await?;
+    assert_eq!(chat.topic, None);
+    let input = UpdateChat {
+      icon: Some("🎉".to_string()),
+      ..Default::default()
+    };
+    let err = state.update_chat(1, 2, input).await.unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+
+    let changes = state.fetch_chat_changes(1).await?;
+    let fields: Vec<_> = changes.iter().map(|c| c.field.as_str()).collect();
+    assert_eq!(fields, ["topic", "icon", "description", "topic", "name"]);
+    assert_eq!(changes[0].changed_by, 2);
+    assert_eq!(changes[0].old_value.as_deref(), Some("Company wide news"));
+    assert_eq!(changes[0].new_value, None);
+    assert_eq!(changes[4].old_value.as_deref(), Some("general"));
+
+    let input = UpdateChat {
+      description: Some("x".repeat(501)),
+      ..Default::default()
+    };
+    let err = state.update_chat(1, 1, input).await.unwrap_err();
+    assert!(matches!(err, AppError::UpdateChatError(_)));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn mark_chat_read_should_update_unread_counts() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let summary = |chats: Vec<ChatSummary>, id: i64| {
+      chats
+        .into_iter()
+        .find(|c| c.chat.id == id)
+        .expect("chat should be listed")
+    };
+    let chats = state.fetch_chats(1, 5, ListChats::default()).await?;
+    let general = summary(chats, 1);
+    // daisy's own message doesn't count
+    assert_eq!(general.unread_count, 9);
+    assert_eq!(general.mention_count, 0);
+
+    let input = MarkRead {
+      message_id: Some(5),
+    };
+    let marker = state.mark_chat_read(1, 5, input).await?;
+    assert_eq!(marker.last_read_id, Some(5));
+    // markers don't move backwards
+    let input = MarkRead {
+      message_id: Some(3),
+    };
+    let marker = state.mark_chat_read(1, 5, input).await?;
+    assert_eq!(marker.last_read_id, Some(5));
+
+    let input = CreateMessage {
+      content: "hey <@5>, have a look".to_string(),
+      files: vec![],
+      ..Default::default()
+    };
+    state.create_message(input.clone(), 1, 1).await?;
+    state.create_message(input, 3, 1).await?;
+    let chats = state.fetch_chats(1, 5, ListChats::default()).await?;
+    let general = summary(chats, 1);
+    assert_eq!(general.last_read_id, Some(5));
+    assert_eq!(general.unread_count, 6);
+    assert_eq!(general.mention_count, 1);
+    // every direct message counts as a mention
+    let chats = state.fetch_chats(1, 2, ListChats::default()).await?;
+    assert_eq!(summary(chats, 3).mention_count, 1);
+
+    let marker = state.mark_chat_read(1, 5, MarkRead::default()).await?;
+    assert_eq!(marker.last_read_id, Some(11));
+    let chats = state.fetch_chats(1, 5, ListChats::default()).await?;
+    assert_eq!(summary(chats, 1).unread_count, 0);
+
+    // messages of other chats can't be used as marker
+    let input = MarkRead {
+      message_id: Some(12),
+    };
+    let err = state.mark_chat_read(1, 5, input).await.unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    Ok(())
+  }
 }
@@ -0,0 +1,777 @@
+use crate::{AppError, AppState, CreateMessage, ScheduleMessage, UpdateChat};
+use anyhow::{bail, Context};
+use chat_core::{ChatUser, CommandResponse, SlashCommand, User};
+use chrono::{TimeDelta, Utc};
+use reqwest::{
+  header::{CONTENT_TYPE, USER_AGENT},
+  Url,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::warn;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CreateSlashCommand {
+  pub name: String,
+  /// called with a POST for every use of the command
+  pub url: String,
+  #[serde(default)]
+  pub description: String,
+}
+
+/// A message of the form `/name text`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CommandInvocation {
+  pub name: String,
+  pub text: String,
+}
+
+/// Posted to the webhook of a custom command.
+#[derive(Debug, Serialize)]
+struct WebhookRequest<'a> {
+  command: &'a str,
+  text: &'a str,
+  ws_id: i64,
+  chat_id: u64,
+  user_id: i64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct WebhookResponse {
+  #[serde(default)]
+  text: String,
+  #[serde(default)]
+  response_type: ResponseType,
+}
+
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum ResponseType {
+  /// only the user who ran the command sees the text
+  #[default]
+  Ephemeral,
+  /// the text is posted in the chat as the user
+  InChannel,
+}
+
+/// The built-in commands with their usage, custom commands can't take these names.
+const BUILTIN_COMMANDS: [(&str, &str); 3] = [
+  ("invite", "/invite @user [@user ...]"),
+  ("remind", "/remind <delay like 30m or 1h30m> <message>"),
+  ("topic", "/topic [new topic]"),
+];
+const MAX_COMMAND_NAME_LENGTH: usize = 32;
+const MAX_COMMAND_DESCRIPTION_LENGTH: usize = 250;
+const MAX_REMIND_DELAY_SECS: i64 = 365 * 24 * 3600;
+const MAX_RESPONSE_LENGTH: usize = 4000;
+const MAX_WEBHOOK_RESPONSE_BYTES: usize = 64 * 1024;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(3);
+const WEBHOOK_USER_AGENT: &str = "chat_server webhook";
+/// unix seconds of the call, signed along with the body
+const TIMESTAMP_HEADER: &str = "x-chat-timestamp";
+/// `sha256=<hex>`, the HMAC-SHA256 of `<timestamp>.<body>` keyed with the command secret
+const SIGNATURE_HEADER: &str = "x-chat-signature";
+
+impl AppState {
+  /// Runs a built-in or custom command sent to the chat in place of a message, `None` if the
+  /// workspace has no such command.
+  pub(crate) async fn run_command(
+    &self,
+    command: CommandInvocation,
+    input: &CreateMessage,
+    chat_id: u64,
+    user: &User,
+  ) -> Result<Option<CommandResponse>, AppError> {
+    if self.is_chat_archived(chat_id).await? {
+      return Err(AppError::ChatArchived(chat_id));
+    }
+    let text = command.text.as_str();
+    let (text, message) = match command.name.as_str() {
+      "invite" => (self.invite_command(text, chat_id, user).await?, None),
+      "remind" => (self.remind_command(text, input, chat_id, user).await?, None),
+      "topic" => (self.topic_command(text, chat_id, user).await?, None),
+      _ => match self.custom_command(&command, input, chat_id, user).await? {
+        Some(reply) => reply,
+        None => return Ok(None),
+      },
+    };
+
+    Ok(Some(CommandResponse {
+      command: command.name,
+      text,
+      message,
+    }))
+  }
+
+  pub async fn create_slash_command(
+    &self,
+    ws_id: u64,
+    user_id: u64,
+    input: CreateSlashCommand,
+  ) -> Result<SlashCommand, AppError> {
+    if !self.is_workspace_admin(ws_id, user_id).await? {
+      return Err(AppError::PermissionDenied(
+        "Only workspace admins can add commands".to_string(),
+      ));
+    }
+    let name = input.name.trim_start_matches('/').to_ascii_lowercase();
+    if !is_command_name(&name) || name.len() > MAX_COMMAND_NAME_LENGTH {
+      return Err(AppError::CommandError(format!(
+        "command names are 1 to {} letters, digits, - or _",
+        MAX_COMMAND_NAME_LENGTH
+      )));
+    }
+    if BUILTIN_COMMANDS.iter().any(|(builtin, _)| *builtin == name) {
+      return Err(AppError::CommandError(format!(
+        "/{} is a built-in command",
+        name
+      )));
+    }
+    let is_web = Url::parse(&input.url).is_ok_and(|url| matches!(url.scheme(), "http" | "https"));
+    if !is_web {
+      return Err(AppError::CommandError(
+        "url must be an http or https url".to_string(),
+      ));
+    }
+    if input.description.chars().count() > MAX_COMMAND_DESCRIPTION_LENGTH {
+      return Err(AppError::CommandError(format!(
+        "description must be at most {} characters",
+        MAX_COMMAND_DESCRIPTION_LENGTH
+      )));
+    }
+
+    let secret = hex::encode(rand::random::<[u8; 32]>());
+    let command = sqlx::query_as(
+      r#"
+      INSERT INTO slash_commands (ws_id, name, description, url, secret, created_by)
+      VALUES ($1, $2, $3, $4, $5, $6)
+      RETURNING id, ws_id, name, description, url, secret, created_by, created_at
+      "#,
+    )
+    .bind(ws_id as i64)
+    .bind(&name)
+    .bind(&input.description)
+    .bind(&input.url)
+    .bind(&secret)
+    .bind(user_id as i64)
+    .fetch_one(&self.pool)
+    .await
+    .map_err(|e| match e {
+      sqlx::Error::Database(e) if e.is_unique_violation() => {
+        AppError::CommandError(format!("/{} already exists", name))
+      }
+      e => e.into(),
+    })?;
+
+    Ok(command)
+  }
+
+  /// Custom commands of the workspace, by name.
+  pub async fn list_slash_commands(&self, ws_id: u64) -> Result<Vec<SlashCommand>, AppError> {
+    let commands = sqlx::query_as(
+      r#"
+      SELECT id, ws_id, name, description, url, created_by, created_at
+      FROM slash_commands
+      WHERE ws_id = $1
+      ORDER BY name
+      "#,
+    )
+    .bind(ws_id as i64)
+    .fetch_all(&self.pool)
+    .await?;
+
+    Ok(commands)
+  }
+
+  pub async fn delete_slash_command(
+    &self,
+    ws_id: u64,
+    id: u64,
+    user_id: u64,
+  ) -> Result<(), AppError> {
+    if !self.is_workspace_admin(ws_id, user_id).await? {
+      return Err(AppError::PermissionDenied(
+        "Only workspace admins can remove commands".to_string(),
+      ));
+    }
+    let ret = sqlx::query(
+      r#"
+      DELETE FROM slash_commands
+      WHERE id = $1 AND ws_id = $2
+      "#,
+    )
+    .bind(id as i64)
+    .bind(ws_id as i64)
+    .execute(&self.pool)
+    .await?;
+
+    if ret.rows_affected() == 0 {
+      return Err(AppError::NotFound(format!("Command {} not found", id)));
+    }
+    Ok(())
+  }
+
+  /// Adds the mentioned workspace users to the chat.
+  async fn invite_command(
+    &self,
+    text: &str,
+    chat_id: u64,
+    user: &User,
+  ) -> Result<String, AppError> {
+    let mut handles = Vec::new();
+    let mut user_ids = Vec::new();
+    for token in text.split_whitespace() {
+      let user_id = token
+        .strip_prefix("<@")
+        .and_then(|rest| rest.strip_suffix('>'))
+        .and_then(|id| id.parse::<i64>().ok());
+      match (user_id, token.strip_prefix('@')) {
+        (Some(id), _) => user_ids.push(id),
+        (None, Some(handle)) if !handle.is_empty() => handles.push(handle.to_lowercase()),
+        _ => return Err(usage_error("invite")),
+      }
+    }
+    if handles.is_empty() && user_ids.is_empty() {
+      return Err(usage_error("invite"));
+    }
+
+    let users: Vec<ChatUser> = sqlx::query_as(
+      r#"
+      SELECT id, fullname, email, is_bot, is_active, display_name, title, timezone,
+        avatar_url, status_text, status_emoji, status_expires_at, pronouns
+      FROM chat_users
+      WHERE ws_id = $1 AND is_active
+      AND (id = ANY($2)
+        OR lower(split_part(email, '@', 1)) = ANY($3)
+        OR lower(display_name) = ANY($3))
+      "#,
+    )
+    .bind(user.ws_id)
+    .bind(&user_ids)
+    .bind(&handles)
+    .fetch_all(&self.pool)
+    .await?;
+    // same matching as the query above
+    let has_handle = |u: &ChatUser, handle: &str| {
+      let local = u.email.split('@').next().unwrap_or_default();
+      let name = u.display_name.as_deref().unwrap_or_default();
+      local.to_lowercase() == handle || name.to_lowercase() == handle
+    };
+    let mut unknown: Vec<String> = handles
+      .iter()
+      .filter(|handle| !users.iter().any(|u| has_handle(u, handle)))
+      .map(|handle| format!("@{}", handle))
+      .collect();
+    unknown.extend(
+      user_ids
+        .iter()
+        .filter(|id| !users.iter().any(|u| u.id == **id))
+        .map(|id| format!("<@{}>", id)),
+    );
+    if !unknown.is_empty() {
+      return Err(AppError::CommandError(format!(
+        "unknown users: {}",
+        unknown.join(", ")
+      )));
+    }
+
+    let chat = self
+      .get_chat_by_id(chat_id)
+      .await?
+      .ok_or_else(|| AppError::NotFound(format!("Chat: {} not found", chat_id)))?;
+    let invited: Vec<&ChatUser> = users
+      .iter()
+      .filter(|u| !chat.members.contains(&u.id))
+      .collect();
+    if invited.is_empty() {
+      return Ok("Everyone is already in the chat".to_string());
+    }
+    let mut members = chat.members;
+    members.extend(invited.iter().map(|u| u.id));
+    let input = UpdateChat {
+      members: Some(members),
+      ..Default::default()
+    };
+    self.update_chat(chat_id, user.id as _, input).await?;
+
+    let names: Vec<&str> = invited.iter().map(|u| u.fullname.as_str()).collect();
+    Ok(format!("Invited {}", names.join(", ")))
+  }
+
+  /// Schedules the message to be posted in the chat after the delay.
+  async fn remind_command(
+    &self,
+    text: &str,
+    input: &CreateMessage,
+    chat_id: u64,
+    user: &User,
+  ) -> Result<String, AppError> {
+    let (delay, content) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let content = content.trim();
+    let delay = parse_delay(delay)
+      .filter(|_| !content.is_empty())
+      .ok_or_else(|| usage_error("remind"))?;
+
+    let input = ScheduleMessage {
+      message: CreateMessage {
+        content: content.to_string(),
+        files: vec![],
+        ..input.clone()
+      },
+      send_at: Utc::now() + delay,
+    };
+    let scheduled = self.schedule_message(input, chat_id, user.id as _).await?;
+    Ok(format!(
+      "Reminder set for {}",
+      scheduled.send_at.format("%Y-%m-%d %H:%M UTC")
+    ))
+  }
+
+  /// Shows the topic of the chat, or changes it to the text.
+  async fn topic_command(&self, text: &str, chat_id: u64, user: &User) -> Result<String, AppError> {
+    if text.is_empty() {
+      let chat = self
+        .get_chat_by_id(chat_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Chat: {} not found", chat_id)))?;
+      return Ok(match chat.topic {
+        Some(topic) => format!("The topic is: {}", topic),
+        None => "No topic is set".to_string(),
+      });
+    }
+    let input = UpdateChat {
+      topic: Some(text.to_string()),
+      ..Default::default()
+    };
+    self.update_chat(chat_id, user.id as _, input).await?;
+    Ok(format!("Topic set to: {}", text))
+  }
+
+  /// Calls the webhook of a custom command, `None` if there is no such command. A webhook that
+  /// fails only tells the user.
+  async fn custom_command(
+    &self,
+    command: &CommandInvocation,
+    input: &CreateMessage,
+    chat_id: u64,
+    user: &User,
+  ) -> Result<Option<(String, Option<chat_core::Message>)>, AppError> {
+    let custom: Option<SlashCommand> = sqlx::query_as(
+      r#"
+      SELECT id, ws_id, name, description, url, secret, created_by, created_at
+      FROM slash_commands
+      WHERE ws_id = $1 AND name = $2
+      "#,
+    )
+    .bind(user.ws_id)
+    .bind(&command.name)
+    .fetch_optional(&self.pool)
+    .await?;
+    let Some(custom) = custom else {
+      return Ok(None);
+    };
+
+    let request = WebhookRequest {
+      command: &command.name,
+      text: &command.text,
+      ws_id: user.ws_id,
+      chat_id,
+      user_id: user.id,
+    };
+    let secret = custom.secret.unwrap_or_default();
+    let response = match self.call_webhook(&custom.url, &secret, &request).await {
+      Ok(response) => response,
+      Err(e) => {
+        warn!("Webhook of /{} failed: {:#}", command.name, e);
+        return Ok(Some((format!("/{} didn't respond", command.name), None)));
+      }
+    };
+    let text: String = response
+      .text
+      .trim()
+      .chars()
+      .take(MAX_RESPONSE_LENGTH)
+      .collect();
+    if response.response_type == ResponseType::Ephemeral || text.is_empty() {
+      return Ok(Some((text, None)));
+    }
+
+    let input = CreateMessage {
+      content: text.clone(),
+      files: vec![],
+      ..input.clone()
+    };
+    let message = self.create_message(input, chat_id, user.id as _).await?;
+    Ok(Some((text, Some(message))))
+  }
+
+  /// Posts the request to the webhook, signed with the command secret so the webhook can tell
+  /// it comes from this server.
+  async fn call_webhook(
+    &self,
+    url: &str,
+    secret: &str,
+    request: &WebhookRequest<'_>,
+  ) -> anyhow::Result<WebhookResponse> {
+    let url = Url::parse(url)?;
+    let body = serde_json::to_vec(request)?;
+    let timestamp = Utc::now().timestamp().to_string();
+    let signature = sign_webhook(secret, &timestamp, &body);
+    let call = async {
+      let mut res = self
+        .unfurler
+        .client_for(&url)
+        .await?
+        .post(url.clone())
+        .header(CONTENT_TYPE, "application/json")
+        .header(USER_AGENT, WEBHOOK_USER_AGENT)
+        .header(TIMESTAMP_HEADER, &timestamp)
+        .header(SIGNATURE_HEADER, signature)
+        .body(body)
+        .send()
+        .await?;
+      if !res.status().is_success() {
+        bail!("unexpected status {}", res.status());
+      }
+      let mut body = Vec::new();
+      while let Some(chunk) = res.chunk().await? {
+        body.extend_from_slice(&chunk);
+        if body.len() > MAX_WEBHOOK_RESPONSE_BYTES {
+          bail!("response too large");
+        }
+      }
+      // an empty response acknowledges the command without a reply
+      if body.is_empty() {
+        return Ok(WebhookResponse::default());
+      }
+      Ok(serde_json::from_slice(&body)?)
+    };
+    tokio::time::timeout(WEBHOOK_TIMEOUT, call)
+      .await
+      .context("timed out")?
+  }
+}
+
+/// The command in the message content, if it starts with `/name`. Other text starting with a
+/// slash, like a path, is a normal message.
+pub(crate) fn parse_command(content: &str) -> Option<CommandInvocation> {
+  let rest = content.trim_start().strip_prefix('/')?;
+  let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+  let name = rest[..end].to_ascii_lowercase();
+  is_command_name(&name).then(|| CommandInvocation {
+    name,
+    text: rest[end..].trim().to_string(),
+  })
+}
+
+/// The signature header of a webhook call.
+fn sign_webhook(secret: &str, timestamp: &str, body: &[u8]) -> String {
+  let mut mac = hmac_sha256::HMAC::new(secret.as_bytes());
+  mac.update(timestamp.as_bytes());
+  mac.update(b".");
+  mac.update(body);
+  format!("sha256={}", hex::encode(mac.finalize()))
+}
+
+fn is_command_name(name: &str) -> bool {
+  !name.is_empty()
+    && name
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+}
+
+/// Delays like `45s`, `30m`, `2h` or `1d12h`.
+fn parse_delay(s: &str) -> Option<TimeDelta> {
+  let mut secs: i64 = 0;
+  let mut number = String::new();
+  for c in s.chars() {
+    if c.is_ascii_digit() {
+      number.push(c);
+      continue;
+    }
+    let unit = match c {
+      's' => 1,
+      'm' => 60,
+      'h' => 3600,
+      'd' => 24 * 3600,
+      _ => return None,
+    };
+    let n: i64 = number.parse().ok()?;
+    number.clear();
+    secs = secs.checked_add(n.checked_mul(unit)?)?;
+  }
+  let valid = number.is_empty() && secs > 0 && secs <= MAX_REMIND_DELAY_SECS;
+  valid.then(|| TimeDelta::seconds(secs))
+}
+
+fn usage_error(name: &str) -> AppError {
+  let usage = BUILTIN_COMMANDS
+    .iter()
+    .find(|(builtin, _)| *builtin == name)
+    .map_or("", |(_, usage)| usage);
+  AppError::CommandError(format!("usage: {}", usage))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use anyhow::Result;
+  use axum::{routing::post, Json, Router};
+  use tokio::net::TcpListener;
+
+  fn invocation(content: &str) -> CommandInvocation {
+    parse_command(content).expect("command")
+  }
+
+  async fn get_user(state: &AppState, id: u64) -> Result<User> {
+    Ok(state.find_user_by_id(id as _).await?.expect("user"))
+  }
+
+  /// Serves webhooks that answer in the chat, only to the user, or not at all.
+  async fn webhook_server() -> Result<String> {
+    let app = Router::new()
+      .route(
+        "/echo",
+        post(|Json(body): Json<serde_json::Value>| async move {
+          Json(serde_json::json!({
+            "response_type": "in_channel",
+            "text": format!("{} says {}", body["user_id"], body["text"].as_str().unwrap_or_default()),
+          }))
+        }),
+      )
+      .route(
+        "/headers",
+        post(|headers: axum::http::HeaderMap, body: String| async move {
+          let header = |name| headers[name].to_str().unwrap_or_default().to_string();
+          let text = [header(TIMESTAMP_HEADER), header(SIGNATURE_HEADER), body].join("\n");
+          Json(serde_json::json!({ "text": text }))
+        }),
+      )
+      .route(
+        "/private",
+        post(|| async { Json(serde_json::json!({ "text": "just for you" })) }),
+      )
+      .route(
+        "/broken",
+        post(|| async { (axum::http::StatusCode::INTERNAL_SERVER_ERROR, "oops") }),
+      );
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    Ok(format!("http://{}", addr))
+  }
+
+  #[test]
+  fn parse_command_should_work() {
+    assert_eq!(
+      parse_command("/Topic  Release  planning "),
+      Some(CommandInvocation {
+        name: "topic".to_string(),
+        text: "Release  planning".to_string(),
+      })
+    );
+    assert_eq!(invocation("/invite").text, "");
+    assert_eq!(parse_command("hello /topic"), None);
+    assert_eq!(parse_command("/usr/bin is missing"), None);
+    assert_eq!(parse_command("//topic"), None);
+    assert_eq!(parse_command("/ topic"), None);
+  }
+
+  #[test]
+  fn parse_delay_should_work() {
+    assert_eq!(parse_delay("45s"), Some(TimeDelta::seconds(45)));
+    assert_eq!(parse_delay("1h30m"), Some(TimeDelta::minutes(90)));
+    assert_eq!(parse_delay("2d"), Some(TimeDelta::days(2)));
+    for delay in [
+      "",
+      "0m",
+      "10",
+      "m",
+      "5w",
+      "-5m",
+      "400d",
+      "99999999999999999999s",
+    ] {
+      assert_eq!(parse_delay(delay), None, "{}", delay);
+    }
+  }
+
+  #[tokio::test]
+  async fn builtin_commands_should_work() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let hal = get_user(&state, 1).await?;
+
+    let res = state
+      .run_command(invocation("/topic"), &Default::default(), 2, &hal)
+      .await?
+      .expect("known command");
+    assert_eq!(res.text, "No topic is set");
+    let res = state
+      .run_command(invocation("/topic Q3 launch"), &Default::default(), 2, &hal)
+      .await?
+      .expect("known command");
+    assert_eq!(res.text, "Topic set to: Q3 launch");
+    let chat = state.get_chat_by_id(2).await?.expect("chat");
+    assert_eq!(chat.topic.as_deref(), Some("Q3 launch"));
+
+    let res = state
+      .run_command(
+        invocation("/invite @daisy @Alice"),
+        &Default::default(),
+        2,
+        &hal,
+      )
+      .await?
+      .expect("known command");
+    assert_eq!(res.text, "Invited Daisy Chen");
+    let chat = state.get_chat_by_id(2).await?.expect("chat");
+    assert_eq!(chat.members, [1, 2, 3, 5]);
+    let res = state
+      .run_command(invocation("/invite <@5>"), &Default::default(), 2, &hal)
+      .await?
+      .expect("known command");
+    assert_eq!(res.text, "Everyone is already in the chat");
+    let err = state
+      .run_command(invocation("/invite @nobody"), &Default::default(), 2, &hal)
+      .await
+      .unwrap_err();
+    assert_eq!(err.to_string(), "command error: unknown users: @nobody");
+    let err = state
+      .run_command(invocation("/invite daisy"), &Default::default(), 2, &hal)
+      .await
+      .unwrap_err();
+    assert!(err.to_string().contains("usage: /invite"));
+
+    let res = state
+      .run_command(
+        invocation("/remind 1h stand-up"),
+        &Default::default(),
+        1,
+        &hal,
+      )
+      .await?
+      .expect("known command");
+    assert!(res.text.starts_with("Reminder set for "));
+    let scheduled = state.list_scheduled_messages(1, Default::default()).await?;
+    assert_eq!(scheduled.len(), 1);
+    assert_eq!(scheduled[0].content, "stand-up");
+    let err = state
+      .run_command(
+        invocation("/remind soon stand-up"),
+        &Default::default(),
+        1,
+        &hal,
+      )
+      .await
+      .unwrap_err();
+    assert!(err.to_string().contains("usage: /remind"));
+
+    // unknown commands are sent as messages
+    let res = state
+      .run_command(invocation("/deploy"), &Default::default(), 1, &hal)
+      .await?;
+    assert!(res.is_none());
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn custom_commands_should_call_webhooks() -> Result<()> {
+    let base = webhook_server().await?;
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let hal = get_user(&state, 1).await?;
+    let command = |name: &str, path: &str| CreateSlashCommand {
+      name: name.to_string(),
+      url: format!("{}{}", base, path),
+      description: String::new(),
+    };
+
+    let err = state
+      .create_slash_command(1, 1, command("echo", "/echo"))
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+    sqlx::query("UPDATE users SET is_admin = TRUE WHERE id = 1")
+      .execute(&state.pool)
+      .await?;
+    let mut secrets = Vec::new();
+    for (name, path) in [
+      ("echo", "/echo"),
+      ("headers", "/headers"),
+      ("private", "/private"),
+      ("broken", "/broken"),
+    ] {
+      let created = state
+        .create_slash_command(1, 1, command(name, path))
+        .await?;
+      secrets.push(created.secret.expect("secret of a new command"));
+    }
+    assert_ne!(secrets[0], secrets[1]);
+    let err = state
+      .create_slash_command(1, 1, command("/Echo", "/echo"))
+      .await
+      .unwrap_err();
+    assert_eq!(err.to_string(), "command error: /echo already exists");
+    let err = state
+      .create_slash_command(1, 1, command("topic", "/echo"))
+      .await
+      .unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "command error: /topic is a built-in command"
+    );
+    let commands = state.list_slash_commands(1).await?;
+    assert_eq!(commands.len(), 4);
+    assert_eq!(commands[0].name, "broken");
+    // only the admin who adds a command sees its secret
+    assert!(commands.iter().all(|c| c.secret.is_none()));
+
+    let res = state
+      .run_command(invocation("/echo hi all"), &Default::default(), 1, &hal)
+      .await?
+      .expect("known command");
+    assert_eq!(res.text, "1 says hi all");
+    let message = res.message.expect("posted message");
+    assert_eq!(message.content, "1 says hi all");
+    assert_eq!(message.sender_id, 1);
+
+    // webhooks can check the call is signed with the secret
+    let res = state
+      .run_command(invocation("/headers"), &Default::default(), 1, &hal)
+      .await?
+      .expect("known command");
+    let mut lines = res.text.lines();
+    let (timestamp, signature, body) = (
+      lines.next().unwrap_or_default(),
+      lines.next().unwrap_or_default(),
+      lines.next().unwrap_or_default(),
+    );
+    assert!(body.contains(r#""command":"headers""#));
+    assert_eq!(
+      signature,
+      sign_webhook(&secrets[1], timestamp, body.as_bytes())
+    );
+    assert_ne!(
+      signature,
+      sign_webhook(&secrets[0], timestamp, body.as_bytes())
+    );
+
+    let res = state
+      .run_command(invocation("/private"), &Default::default(), 1, &hal)
+      .await?
+      .expect("known command");
+    assert_eq!(res.text, "just for you");
+    assert!(res.message.is_none());
+
+    let res = state
+      .run_command(invocation("/broken"), &Default::default(), 1, &hal)
+      .await?
+      .expect("known command");
+    assert_eq!(res.text, "/broken didn't respond");
+
+    state
+      .delete_slash_command(1, commands[0].id as _, 1)
+      .await?;
+    assert_eq!(state.list_slash_commands(1).await?.len(), 3);
+    Ok(())
+  }
+}
@@ -0,0 +1,686 @@
+use crate::{AppError, AppState};
+use anyhow::{bail, Context, Result};
+use chat_core::{Inline, LinkPreview, Message};
+use reqwest::{
+  header::{ACCEPT, CONTENT_TYPE, LOCATION},
+  redirect, Client, Url,
+};
+use sqlx::types::Json;
+use std::{
+  net::{IpAddr, Ipv4Addr, SocketAddr},
+  sync::Arc,
+  time::Duration,
+};
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+const MAX_PREVIEWS: usize = 3;
+const MAX_REDIRECTS: usize = 3;
+const MAX_TITLE_LENGTH: usize = 300;
+const MAX_DESCRIPTION_LENGTH: usize = 1000;
+const USER_AGENT: &str = "chat_server link preview";
+/// messages unfurled at once, links in messages sent beyond that get no preview
+const MAX_CONCURRENT_UNFURLS: usize = 16;
+
+/// Fetches the OpenGraph metadata of linked pages. Only public addresses are fetched, every
+/// redirect is checked again and pages are read up to `max_bytes`.
+#[derive(Debug, Clone)]
+pub(crate) struct Unfurler {
+  /// for the whole fetch, redirects included
+  pub timeout: Duration,
+  pub max_bytes: usize,
+  /// fetch loopback and private addresses too, only for tests against a local server
+  pub allow_private: bool,
+  /// permits of the background unfurls
+  pub jobs: Arc<Semaphore>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct PageMeta {
+  title: Option<String>,
+  description: Option<String>,
+  image: Option<String>,
+}
+
+impl AppState {
+  /// Fetches the previews of the links in the message in the background.
+  pub(crate) fn spawn_unfurl(&self, message: &Message) {
+    let missing = preview_urls(message)
+      .iter()
+      .any(|url| !message.link_previews.iter().any(|p| &p.url == url));
+    if !missing {
+      return;
+    }
+    let Ok(permit) = self.unfurler.jobs.clone().try_acquire_owned() else {
+      warn!("Too many unfurls running, skipped message {}", message.id);
+      return;
+    };
+    let state = self.clone();
+    let message = message.clone();
+    tokio::spawn(async move {
+      let _permit = permit;
+      if let Err(e) = state.unfurl_message(&message).await {
+        warn!(
+          "Failed to store link previews of message {}: {}",
+          message.id, e
+        );
+      }
+    });
+  }
+
+  /// Fetches the previews of the links in the message and stores them, unless the message was
+  /// edited or deleted in the meantime. Links that already have a preview aren't fetched again.
+  pub(crate) async fn unfurl_message(
+    &self,
+    message: &Message,
+  ) -> Result<Vec<LinkPreview>, AppError> {
+    let mut previews = Vec::new();
+    for url in preview_urls(message) {
+      if let Some(preview) = message.link_previews.iter().find(|p| p.url == url) {
+        previews.push(preview.clone());
+        continue;
+      }
+      match self.unfurler.unfurl(&url).await {
+        Ok(Some(preview)) => previews.push(preview),
+        Ok(None) => {}
+        Err(e) => warn!("Failed to unfurl {}: {:#}", url, e),
+      }
+    }
+    if previews == message.link_previews {
+      return Ok(previews);
+    }
+
+    sqlx::query(
+      r#"
+      UPDATE messages
+      SET link_previews = $3
+      WHERE id = $1 AND content = $2 AND deleted_at IS NULL
+      "#,
+    )
+    .bind(message.id)
+    .bind(&message.content)
+    .bind(Json(&previews))
+    .execute(&self.pool)
+    .await?;
+
+    Ok(previews)
+  }
+}
+
+impl Default for Unfurler {
+  fn default() -> Self {
+    Self {
+      timeout: Duration::from_secs(5),
+      max_bytes: 512 * 1024,
+      allow_private: false,
+      jobs: Arc::new(Semaphore::new(MAX_CONCURRENT_UNFURLS)),
+    }
+  }
+}
+
+impl Unfurler {
+  /// The preview of an html page, `None` for other content or pages without metadata.
+  pub async fn unfurl(&self, url: &str) -> Result<Option<LinkPreview>> {
+    let meta = tokio::time::timeout(self.timeout, self.fetch(url))
+      .await
+      .context("timed out")??;
+    Ok(meta.map(|meta| LinkPreview {
+      url: url.to_string(),
+      title: meta.title,
+      description: meta.description,
+      image: meta.image,
+    }))
+  }
+
+  async fn fetch(&self, url: &str) -> Result<Option<PageMeta>> {
+    let mut url = Url::parse(url)?;
+    for _ in 0..=MAX_REDIRECTS {
+      let mut res = self
+        .client_for(&url)
+        .await?
+        .get(url.clone())
+        .header(ACCEPT, "text/html")
+        .send()
+        .await?;
+
+      if res.status().is_redirection() {
+        let location = res
+          .headers()
+          .get(LOCATION)
+          .context("redirect without a location")?
+          .to_str()?;
+        url = url.join(location)?;
+        continue;
+      }
+      if !res.status().is_success() {
+        bail!("unexpected status {}", res.status());
+      }
+      let is_html = res
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.to_ascii_lowercase().starts_with("text/html"));
+      if !is_html {
+        return Ok(None);
+      }
+
+      // the metadata is in the head, the rest of a large page can be dropped
+      let mut body = Vec::new();
+      while let Some(chunk) = res.chunk().await? {
+        let room = self.max_bytes - body.len();
+        body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+        if body.len() >= self.max_bytes {
+          break;
+        }
+      }
+      let meta = parse_page(&url, &String::from_utf8_lossy(&body));
+      return Ok((meta != PageMeta::default()).then_some(meta));
+    }
+    bail!("too many redirects")
+  }
+
+  /// A client for requests to the url that only connects to its checked addresses and doesn't
+  /// follow redirects. Command webhooks are called with it as well.
+  pub async fn client_for(&self, url: &Url) -> Result<Client> {
+    let addrs = self.resolve(url).await?;
+    // a proxy from the environment would resolve the name itself and undo the pinning below
+    let mut client = Client::builder()
+      .redirect(redirect::Policy::none())
+      .no_proxy()
+      .user_agent(USER_AGENT);
+    // connect to the addresses that were checked, not whatever the name resolves to next
+    if let Some(url::Host::Domain(domain)) = url.host() {
+      client = client.resolve_to_addrs(domain, &addrs);
+    }
+    Ok(client.build()?)
+  }
+
+  async fn resolve(&self, url: &Url) -> Result<Vec<SocketAddr>> {
+    if !matches!(url.scheme(), "http" | "https") {
+      bail!("unsupported scheme {}", url.scheme());
+    }
+    let port = url.port_or_known_default().context("missing port")?;
+    let addrs: Vec<SocketAddr> = match url.host().context("missing host")? {
+      url::Host::Domain(domain) => tokio::net::lookup_host((domain, port)).await?.collect(),
+      url::Host::Ipv4(ip) => vec![SocketAddr::new(ip.into(), port)],
+      url::Host::Ipv6(ip) => vec![SocketAddr::new(ip.into(), port)],
+    };
+    if addrs.is_empty() {
+      bail!("{} has no addresses", url);
+    }
+    if !self.allow_private && addrs.iter().any(|addr| !is_public(addr.ip())) {
+      bail!("{} resolves to a private address", url);
+    }
+    Ok(addrs)
+  }
+}
+
+/// Http and https links of the message, in order and at most `MAX_PREVIEWS` of them.
+fn preview_urls(message: &Message) -> Vec<String> {
+  let mut urls: Vec<String> = Vec::new();
+  let Some(rich_text) = &message.rich_text else {
+    return urls;
+  };
+  for inline in rich_text.inlines() {
+    let Inline::Link { url, .. } = inline else {
+      continue;
+    };
+    let lower = url.to_ascii_lowercase();
+    let is_web = lower.starts_with("http://") || lower.starts_with("https://");
+    if is_web && !urls.contains(url) {
+      urls.push(url.clone());
+    }
+  }
+  urls.truncate(MAX_PREVIEWS);
+  urls
+}
+
+fn is_public(ip: IpAddr) -> bool {
+  match ip {
+    IpAddr::V4(ip) => {
+      let [a, b, ..] = ip.octets();
+      let shared = a == 100 && (64..128).contains(&b);
+      let benchmarking = a == 198 && (b == 18 || b == 19);
+      !(ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || a == 0
+        || a >= 240
+        || shared
+        || benchmarking)
+    }
+    IpAddr::V6(ip) => {
+      let segments = ip.segments();
+      // mapped, compatible, NAT64 and 6to4 addresses reach the ipv4 address they embed
+      let embedded = |hi: u16, lo: u16| Ipv4Addr::from((hi as u32) << 16 | lo as u32);
+      if let Some(ip) = ip.to_ipv4() {
+        return is_public(ip.into());
+      }
+      if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return is_public(embedded(segments[6], segments[7]).into());
+      }
+      if segments[0] == 0x2002 {
+        return is_public(embedded(segments[1], segments[2]).into());
+      }
+      let first = segments[0];
+      let unique_local = first & 0xfe00 == 0xfc00;
+      let link_local = first & 0xffc0 == 0xfe80;
+      let site_local = first & 0xffc0 == 0xfec0;
+      // local NAT64 and Teredo, whose ipv4 address is obfuscated
+      let translated = segments[..2] == [0x64, 0xff9b] || segments[..2] == [0x2001, 0];
+      !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || unique_local
+        || link_local
+        || site_local
+        || translated)
+    }
+  }
+}
+
+/// OpenGraph metadata from the head of the page, falling back to the title and description
+/// tags.
+fn parse_page(page: &Url, html: &str) -> PageMeta {
+  // lowercasing ascii keeps the byte offsets, so positions found in it index into the html
+  let lower = html.to_ascii_lowercase();
+  let mut og = PageMeta::default();
+  let mut fallback = PageMeta::default();
+  let mut pos = 0;
+  while let Some(start) = lower[pos..].find('<').map(|i| pos + i) {
+    let tag = &lower[start + 1..];
+    if tag.starts_with("/head") || tag.starts_with("body") {
+      break;
+    }
+    let Some(end) = lower[start..].find('>').map(|i| start + i) else {
+      break;
+    };
+    pos = end + 1;
+
+    if let Some(name) = ["script", "style", "title"]
+      .into_iter()
+      .find(|name| is_tag(tag, name))
+    {
+      let close = lower[pos..]
+        .find(&format!("</{}", name))
+        .map_or(lower.len(), |i| pos + i);
+      if name == "title" {
+        fallback.title = clean_text(&html[pos..close], MAX_TITLE_LENGTH);
+      }
+      pos = close;
+    } else if is_tag(tag, "meta") {
+      let attrs = parse_attributes(&html[start + 5..end]);
+      let attr = |name: &str| {
+        attrs
+          .iter()
+          .find(|(n, _)| n == name)
+          .map(|(_, v)| v.as_str())
+      };
+      let key = attr("property")
+        .or_else(|| attr("name"))
+        .unwrap_or_default();
+      let content = attr("content").unwrap_or_default();
+      match key.to_ascii_lowercase().as_str() {
+        "og:title" => og.title = clean_text(content, MAX_TITLE_LENGTH),
+        "og:description" => og.description = clean_text(content, MAX_DESCRIPTION_LENGTH),
+        "og:image" => og.image = image_url(page, content),
+        "description" => fallback.description = clean_text(content, MAX_DESCRIPTION_LENGTH),
+        _ => {}
+      }
+    }
+  }
+  PageMeta {
+    title: og.title.or(fallback.title),
+    description: og.description.or(fallback.description),
+    image: og.image,
+  }
+}
+
+fn is_tag(tag: &str, name: &str) -> bool {
+  tag
+    .strip_prefix(name)
+    .and_then(|rest| rest.chars().next())
+    .is_some_and(|c| c.is_whitespace() || c == '>' || c == '/')
+}
+
+/// Attributes of a tag as lowercased names and decoded values.
+fn parse_attributes(s: &str) -> Vec<(String, String)> {
+  let mut attrs = Vec::new();
+  let mut rest = s;
+  loop {
+    rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+    let name_end = rest
+      .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+      .unwrap_or(rest.len());
+    if rest.is_empty() {
+      break;
+    }
+    if name_end == 0 {
+      // a stray `=`
+      rest = &rest[1..];
+      continue;
+    }
+    let name = rest[..name_end].to_ascii_lowercase();
+    rest = rest[name_end..].trim_start();
+    let mut value = "";
+    if let Some(after) = rest.strip_prefix('=') {
+      let after = after.trim_start();
+      (value, rest) = match after.chars().next() {
+        Some(quote @ ('"' | '\'')) => {
+          let body = &after[1..];
+          let end = body.find(quote).unwrap_or(body.len());
+          (&body[..end], body.get(end + 1..).unwrap_or_default())
+        }
+        _ => after.split_at(after.find(char::is_whitespace).unwrap_or(after.len())),
+      };
+    }
+    attrs.push((name, decode_entities(value)));
+  }
+  attrs
+}
+
+fn decode_entities(s: &str) -> String {
+  let mut decoded = String::with_capacity(s.len());
+  let mut rest = s;
+  while let Some(i) = rest.find('&') {
+    decoded.push_str(&rest[..i]);
+    rest = &rest[i..];
+    let entity = rest
+      .find(';')
+      .filter(|end| *end <= 10)
+      .and_then(|end| Some((decode_entity(&rest[1..end])?, end)));
+    match entity {
+      Some((c, end)) => {
+        decoded.push(c);
+        rest = &rest[end + 1..];
+      }
+      None => {
+        decoded.push('&');
+        rest = &rest[1..];
+      }
+    }
+  }
+  decoded.push_str(rest);
+  decoded
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+  match name {
+    "amp" => Some('&'),
+    "lt" => Some('<'),
+    "gt" => Some('>'),
+    "quot" => Some('"'),
+    "apos" => Some('\''),
+    "nbsp" => Some(' '),
+    _ => {
+      let code = name.strip_prefix('#')?;
+      let code = match code.strip_prefix(['x', 'X']) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => code.parse().ok()?,
+      };
+      char::from_u32(code)
+    }
+  }
+}
+
+/// Decoded text on a single line, without control characters and cut to `max` characters.
+fn clean_text(s: &str, max: usize) -> Option<String> {
+  let text = decode_entities(s);
+  let words: Vec<&str> = text
+    .split(|c: char| c.is_whitespace() || c.is_control())
+    .filter(|w| !w.is_empty())
+    .collect();
+  let text: String = words.join(" ").chars().take(max).collect();
+  (!text.is_empty()).then_some(text)
+}
+
+fn image_url(page: &Url, image: &str) -> Option<String> {
+  let url = page.join(image.trim()).ok()?;
+  matches!(url.scheme(), "http" | "https").then(|| url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::CreateMessage;
+  use axum::{
+    http::header,
+    response::{IntoResponse, Redirect},
+    routing::get,
+    Router,
+  };
+  use tokio::net::TcpListener;
+
+  const PAGE: &str = r#"<!doctype html>
+<html><head>
+  <title>Fallback title</title>
+  <meta name="description" content="Fallback description">
+  <meta property="og:title" content="Rust &amp; &lt;friends&gt;">
+  <meta property='og:description' content="A &quot;quoted&quot;
+    description">
+  <meta property="og:image" content="/static/cover.png">
+  <script>document.write("<meta property='og:title' content='fake'>")</script>
+</head><body><meta property="og:title" content="too late"></body></html>"#;
+
+  fn html(body: impl Into<String>) -> impl IntoResponse {
+    (
+      [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+      body.into(),
+    )
+  }
+
+  /// Serves the fixture pages on a local port.
+  async fn fixture_server() -> Result<String> {
+    let app = Router::new()
+      .route("/page", get(|| async { html(PAGE) }))
+      .route(
+        "/plain",
+        get(|| async { html("<html><head><title> Just a\n title </title></head></html>") }),
+      )
+      .route(
+        "/empty",
+        get(|| async { html("<html><body>hi</body></html>") }),
+      )
+      .route(
+        "/image",
+        get(|| async { ([(header::CONTENT_TYPE, "image/png")], "png") }),
+      )
+      .route("/redirect", get(|| async { Redirect::temporary("/page") }))
+      .route(
+        "/ftp-redirect",
+        get(|| async { Redirect::temporary("ftp://example.com/page") }),
+      )
+      .route(
+        "/large",
+        get(|| async {
+          let padding = "<meta name=\"x\" content=\"padding\">".repeat(1000);
+          html(format!(
+            "<html><head>{}<meta property=\"og:title\" content=\"hidden\"></head></html>",
+            padding
+          ))
+        }),
+      )
+      .route(
+        "/slow",
+        get(|| async {
+          tokio::time::sleep(Duration::from_secs(5)).await;
+          html(PAGE)
+        }),
+      );
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    Ok(format!("http://{}", addr))
+  }
+
+  fn test_unfurler() -> Unfurler {
+    Unfurler {
+      timeout: Duration::from_millis(500),
+      allow_private: true,
+      ..Default::default()
+    }
+  }
+
+  #[tokio::test]
+  async fn unfurl_should_read_open_graph_metadata() -> Result<()> {
+    let base = fixture_server().await?;
+    let unfurler = test_unfurler();
+
+    let url = format!("{}/page", base);
+    let preview = unfurler.unfurl(&url).await?.expect("preview");
+    assert_eq!(preview.url, url);
+    assert_eq!(preview.title.as_deref(), Some("Rust & <friends>"));
+    assert_eq!(
+      preview.description.as_deref(),
+      Some("A \"quoted\" description")
+    );
+    assert_eq!(preview.image, Some(format!("{}/static/cover.png", base)));
+
+    // redirects are followed, the preview keeps the linked url
+    let url = format!("{}/redirect", base);
+    let preview = unfurler.unfurl(&url).await?.expect("preview");
+    assert_eq!(preview.url, url);
+    assert_eq!(preview.title.as_deref(), Some("Rust & <friends>"));
+
+    let preview = unfurler
+      .unfurl(&format!("{}/plain", base))
+      .await?
+      .expect("preview");
+    assert_eq!(preview.title.as_deref(), Some("Just a title"));
+    assert_eq!(preview.description, None);
+
+    assert!(unfurler.unfurl(&format!("{}/empty", base)).await?.is_none());
+    assert!(unfurler.unfurl(&format!("{}/image", base)).await?.is_none());
+    assert!(unfurler.unfurl(&format!("{}/missing", base)).await.is_err());
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn unfurl_should_enforce_limits() -> Result<()> {
+    let base = fixture_server().await?;
+    let unfurler = test_unfurler();
+
+    let err = unfurler
+      .unfurl(&format!("{}/slow", base))
+      .await
+      .unwrap_err();
+    assert_eq!(err.to_string(), "timed out");
+
+    // the title comes after the first 1KB of the page
+    let small = Unfurler {
+      max_bytes: 1024,
+      ..test_unfurler()
+    };
+    let url = format!("{}/large", base);
+    assert!(small.unfurl(&url).await?.is_none());
+    assert!(unfurler.unfurl(&url).await?.is_some());
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn unfurl_should_block_private_addresses() -> Result<()> {
+    let base = fixture_server().await?;
+    let unfurler = Unfurler::default();
+
+    let err = unfurler
+      .unfurl(&format!("{}/page", base))
+      .await
+      .unwrap_err();
+    assert!(err.to_string().contains("private address"));
+    for url in [
+      "http://10.1.2.3/",
+      "http://192.168.0.1/",
+      "http://169.254.169.254/latest/meta-data",
+      "http://[::1]/",
+      "http://[::ffff:127.0.0.1]/",
+      "http://[fd00::1]/",
+      "http://localhost/",
+      "ftp://example.com/",
+    ] {
+      assert!(unfurler.unfurl(url).await.is_err(), "{}", url);
+    }
+
+    // every redirect is checked again
+    let err = test_unfurler()
+      .unfurl(&format!("{}/ftp-redirect", base))
+      .await
+      .unwrap_err();
+    assert_eq!(err.to_string(), "unsupported scheme ftp");
+    Ok(())
+  }
+
+  #[test]
+  fn is_public_should_reject_reserved_ranges() {
+    for ip in [
+      "8.8.8.8",
+      "1.1.1.1",
+      "2606:4700::1111",
+      "64:ff9b::808:808",
+      "2002:808:808::1",
+    ] {
+      assert!(is_public(ip.parse().unwrap()), "{}", ip);
+    }
+    for ip in [
+      "127.0.0.1",
+      "10.0.0.1",
+      "172.16.0.1",
+      "100.64.0.1",
+      "0.0.0.0",
+      "255.255.255.255",
+      "fe80::1",
+      "fc00::1",
+      "::",
+      "::1",
+      "::ffff:127.0.0.1",
+      "::10.0.0.1",
+      "64:ff9b::7f00:1",
+      "64:ff9b:1::a00:1",
+      "2002:a00:1::1",
+      "2002:7f00:1::1",
+      "2001:0:4136:e378::1",
+      "fec0::1",
+    ] {
+      assert!(!is_public(ip.parse().unwrap()), "{}", ip);
+    }
+  }
+
+  #[tokio::test]
+  async fn unfurl_message_should_store_previews() -> Result<()> {
+    let base = fixture_server().await?;
+    let (_tdb, state) = AppState::new_for_test().await?;
+
+    let content = format!(
+      "see {base}/page and [this]({base}/plain), not `{base}/empty` or mailto:a@b.c",
+      base = base
+    );
+    let input = CreateMessage {
+      content,
+      ..Default::default()
+    };
+    let message = state.create_message(input, 1, 1).await?;
+    let previews = state.unfurl_message(&message).await?;
+    assert_eq!(previews.len(), 2);
+    assert_eq!(previews[0].url, format!("{}/page", base));
+    assert_eq!(previews[1].title.as_deref(), Some("Just a title"));
+
+    let stored = state.get_live_message(1, message.id as _).await?;
+    assert_eq!(stored.link_previews, previews);
+
+    // an edit keeps the previews of the links still in the message
+    let input = crate::UpdateMessage {
+      content: format!("only {}/plain now", base),
+    };
+    let edited = state.update_message(input, 1, message.id as _, 1).await?;
+    assert_eq!(edited.link_previews, previews[1..]);
+
+    // previews fetched for an outdated version of the message are dropped
+    state.unfurl_message(&message).await?;
+    let stored = state.get_live_message(1, message.id as _).await?;
+    assert_eq!(stored.link_previews, previews[1..]);
+    Ok(())
+  }
+}
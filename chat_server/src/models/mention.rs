@@ -0,0 +1,221 @@
+use crate::{AppError, AppState};
+use chat_core::{Inline, Message, RichText};
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListMentions {
+  pub last_id: Option<u64>,
+  pub limit: Option<u64>,
+}
+
+/// References found in message content: `@alice` or `<@2>` for users, `@channel` and `@here`.
+#[derive(Debug, Default, PartialEq)]
+struct ParsedMentions {
+  /// lowercased handles, matched against display names and email local parts
+  handles: Vec<String>,
+  user_ids: Vec<i64>,
+  channel: bool,
+  here: bool,
+}
+
+const DEFAULT_MENTION_PAGE_SIZE: u64 = 20;
+const MAX_MENTION_PAGE_SIZE: u64 = 100;
+
+impl AppState {
+  /// Messages mentioning the user, directly or with `@channel` and `@here`, newest first.
+  pub async fn fetch_mentions(
+    &self,
+    user_id: u64,
+    input: ListMentions,
+  ) -> Result<Vec<Message>, AppError> {
+    let messages = sqlx::query_as(
+      r#"
+      SELECT id, chat_id, sender_id, content, files, parent_id, also_in_chat, reply_count,
+        last_reply_at, edited_at, deleted_at, rich_text, link_previews, created_at
+      FROM messages
+      WHERE id IN (
+        SELECT mm.message_id
+        FROM message_mentions mm
+        JOIN chat_members cm ON cm.chat_id = mm.chat_id AND cm.user_id = $1
+        WHERE mm.user_id = $1 OR mm.kind <> 'user'
+      )
+      AND sender_id <> $1 AND deleted_at IS NULL
+      AND id < $2
+      ORDER BY id DESC
+      LIMIT $3
+      "#,
+    )
+    .bind(user_id as i64)
+    .bind(input.last_id.unwrap_or(i64::MAX as _) as i64)
+    .bind(
+      input
+        .limit
+        .unwrap_or(DEFAULT_MENTION_PAGE_SIZE)
+        .clamp(1, MAX_MENTION_PAGE_SIZE) as i64,
+    )
+    .fetch_all(&self.pool)
+    .await?;
+
+    Ok(messages)
+  }
+}
+
+/// Replaces the mention records of a message with the mentions in its content. Only the
+/// mentions that weren't recorded before are inserted, so edits don't notify twice.
+pub(crate) async fn sync_mentions(
+  conn: &mut PgConnection,
+  message: &Message,
+) -> Result<(), AppError> {
+  let parsed = parse_mentions(&message.content);
+  // only members of the chat can be mentioned, and not by themselves
+  let user_ids: Vec<(i64,)> = sqlx::query_as(
+    r#"
+    SELECT u.id
+    FROM chat_members m JOIN users u ON u.id = m.user_id
+    WHERE m.chat_id = $1 AND u.id <> $2
+    AND (u.id = ANY($3)
+      OR lower(split_part(u.email, '@', 1)) = ANY($4)
+      OR lower(u.display_name) = ANY($4))
+    "#,
+  )
+  .bind(message.chat_id)
+  .bind(message.sender_id)
+  .bind(&parsed.user_ids)
+  .bind(&parsed.handles)
+  .fetch_all(&mut *conn)
+  .await?;
+  let user_ids: Vec<i64> = user_ids.into_iter().map(|(id,)| id).collect();
+
+  sqlx::query(
+    r#"
+    DELETE FROM message_mentions
+    WHERE message_id = $1
+    AND NOT (kind = 'user' AND user_id = ANY($2))
+    AND NOT (kind = 'channel' AND $3)
+    AND NOT (kind = 'here' AND $4)
+    "#,
+  )
+  .bind(message.id)
+  .bind(&user_ids)
+  .bind(parsed.channel)
+  .bind(parsed.here)
+  .execute(&mut *conn)
+  .await?;
+
+  if user_ids.is_empty() && !parsed.channel && !parsed.here {
+    return Ok(());
+  }
+  sqlx::query(
+    r#"
+    INSERT INTO message_mentions (message_id, chat_id, kind, user_id)
+    SELECT $1, $2, 'user'::mention_kind, unnest($3::bigint[])
+    UNION ALL
+    SELECT $1, $2, 'channel'::mention_kind, NULL WHERE $4
+    UNION ALL
+    SELECT $1, $2, 'here'::mention_kind, NULL WHERE $5
+    ON CONFLICT (message_id, kind, coalesce(user_id, 0)) DO NOTHING
+    "#,
+  )
+  .bind(message.id)
+  .bind(message.chat_id)
+  .bind(&user_ids)
+  .bind(parsed.channel)
+  .bind(parsed.here)
+  .execute(&mut *conn)
+  .await?;
+
+  Ok(())
+}
+
+/// Mentions in code spans and code blocks don't count.
+fn parse_mentions(content: &str) -> ParsedMentions {
+  let mut parsed = ParsedMentions::default();
+  for inline in RichText::parse(content).inlines() {
+    match inline {
+      Inline::UserMention { user_id } => parsed.user_ids.push(*user_id),
+      Inline::Mention { handle } => match handle.as_str() {
+        "channel" => parsed.channel = true,
+        "here" => parsed.here = true,
+        _ => parsed.handles.push(handle.clone()),
+      },
+      _ => {}
+    }
+  }
+  parsed.handles.sort();
+  parsed.handles.dedup();
+  parsed.user_ids.sort_unstable();
+  parsed.user_ids.dedup();
+  parsed
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{CreateMessage, MarkRead, UpdateMessage};
+  use anyhow::Result;
+
+  #[test]
+  fn parse_mentions_should_work() {
+    let parsed = parse_mentions("hi @Alice and <@3>, see mail to hal@acme.org. @bob.");
+    assert_eq!(parsed.handles, ["alice", "bob"]);
+    assert_eq!(parsed.user_ids, [3]);
+    assert!(!parsed.channel && !parsed.here);
+
+    let parsed = parse_mentions("@channel, @here! @ and @. `@carol`\n```\n<@4>\n```");
+    assert!(parsed.channel && parsed.here);
+    assert!(parsed.handles.is_empty() && parsed.user_ids.is_empty());
+  }
+
+  #[tokio::test]
+  async fn mentions_should_be_recorded_and_listed() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let message = |content: &str| CreateMessage {
+      content: content.to_string(),
+      ..Default::default()
+    };
+    // daisy isn't in the private channel, hal can't mention themselves
+    let direct = state
+      .create_message(message("@alice @daisy @hal look"), 2, 1)
+      .await?;
+    let broadcast = state
+      .create_message(message("@channel lunch?"), 1, 3)
+      .await?;
+    state.create_message(message("no one"), 1, 1).await?;
+
+    let feed = |user_id| state.fetch_mentions(user_id, ListMentions::default());
+    let ids: Vec<_> = feed(2).await?.iter().map(|m| m.id).collect();
+    assert_eq!(ids, [broadcast.id, direct.id]);
+    let input = ListMentions {
+      last_id: None,
+      limit: Some(1),
+    };
+    let ids: Vec<_> = state
+      .fetch_mentions(2, input)
+      .await?
+      .iter()
+      .map(|m| m.id)
+      .collect();
+    assert_eq!(ids, [broadcast.id]);
+    let ids: Vec<_> = feed(5).await?.iter().map(|m| m.id).collect();
+    assert_eq!(ids, [broadcast.id]);
+    // own messages aren't mentions
+    assert!(feed(3).await?.is_empty());
+
+    // mentions removed by an edit are dropped
+    let input = UpdateMessage {
+      content: "never mind".to_string(),
+    };
+    state.update_message(input, 2, direct.id as _, 1).await?;
+    let ids: Vec<_> = feed(2).await?.iter().map(|m| m.id).collect();
+    assert_eq!(ids, [broadcast.id]);
+
+    // and they count in the chat list
+    state.mark_chat_read(1, 4, MarkRead::default()).await?;
+    state.create_message(message("@charlie ping"), 1, 1).await?;
+    let chats = state.fetch_chats(1, 4, Default::default()).await?;
+    let general = chats.iter().find(|c| c.chat.id == 1).expect("general");
+    assert_eq!((general.unread_count, general.mention_count), (1, 1));
+    Ok(())
+  }
+}
@@ -1,20 +1,80 @@
-use crate::{AppError, AppState, ChatFile};
-use chat_core::Message;
+use super::chat::is_channel;
+use crate::{sync_mentions, AppError, AppState, ChatFile};
+use chat_core::{ChatRole, Inline, LinkPreview, Message, MessagePage, MessageRevision, RichText};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, PgConnection};
 use std::str::FromStr;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CreateMessage {
   pub content: String,
   pub files: Vec<String>,
+  /// reply in the thread of this message
+  #[serde(default)]
+  pub parent_id: Option<u64>,
+  /// also show the reply in the chat
+  #[serde(default)]
+  pub also_in_chat: bool,
+  /// client generated id, a retried request with the same nonce returns the first message
+  #[serde(default)]
+  pub nonce: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateMessage {
+  pub content: String,
+}
+
+/// Pages through chat messages, newest first. At most one cursor can be set, without one
+/// the latest messages are listed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ListMessages {
+  /// messages before this one
   pub last_id: Option<u64>,
-  pub limit: u64,
+  /// messages after this one
+  pub after_id: Option<u64>,
+  /// this message with the messages right before and after it
+  pub around_id: Option<u64>,
+  /// messages sent after this time, for clients catching up after a reconnect
+  pub since: Option<DateTime<Utc>>,
+  pub limit: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListReplies {
+  /// replies are listed oldest first, starting after this one
+  pub after_id: Option<u64>,
+  pub limit: Option<u64>,
 }
 
+const DEFAULT_REPLIES_PAGE_SIZE: u64 = 50;
+const MAX_REPLIES_LIMIT: u64 = 100;
+/// in characters, longer texts belong in a file
+const MAX_CONTENT_LENGTH: usize = 4000;
+const MAX_NONCE_LENGTH: usize = 64;
+/// how long a nonce returns the message it was first sent with
+const NONCE_WINDOW_HOURS: i32 = 24;
+const DEFAULT_MESSAGE_PAGE_SIZE: u64 = 50;
+const MAX_MESSAGE_PAGE_SIZE: u64 = 200;
+
+/// Message columns selected from `messages m`, with reactions aggregated for the viewer in `$1`.
+const MESSAGE_COLUMNS: &str = r#"
+  id, chat_id, sender_id, content, files, parent_id, also_in_chat, reply_count, last_reply_at,
+  coalesce((
+    SELECT json_agg(json_build_object('emoji', emoji, 'count', count, 'reacted', reacted)
+      ORDER BY first_at)
+    FROM (
+      SELECT emoji, count(*) AS count, bool_or(user_id = $1) AS reacted,
+        min(created_at) AS first_at
+      FROM message_reactions
+      WHERE message_id = m.id
+      GROUP BY emoji
+    ) r
+  ), '[]') AS reactions,
+  edited_at, deleted_at, rich_text, link_previews, created_at
+"#;
+
 #[allow(dead_code)]
 impl AppState {
   pub async fn create_message(
@@ -23,10 +83,470 @@ impl AppState {
     chat_id: u64,
     user_id: u64,
   ) -> Result<Message, AppError> {
+    if let Some(nonce) = &input.nonce {
+      if nonce.is_empty() || nonce.len() > MAX_NONCE_LENGTH {
+        return Err(AppError::CreateMessageError(format!(
+          "nonce must be 1 to {} bytes",
+          MAX_NONCE_LENGTH
+        )));
+      }
+      // a retry returns the message even if it couldn't be sent anymore
+      if let Some(message) = self.find_message_by_nonce(chat_id, user_id, nonce).await? {
+        return Ok(message);
+      }
+    }
+    self.verify_new_message(&input, chat_id).await?;
+
+    // create message
+    let mut tx = self.pool.begin().await?;
+    let message = insert_message(&mut tx, &input, chat_id, user_id).await?;
+
+    if let Some(nonce) = &input.nonce {
+      // an expired nonce can be used again, a live one means a concurrent retry got it first
+      let claimed: Option<(i64,)> = sqlx::query_as(
+        r#"
+        INSERT INTO message_nonces (chat_id, sender_id, nonce, message_id)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (chat_id, sender_id, nonce) DO UPDATE
+        SET message_id = EXCLUDED.message_id, created_at = now()
+        WHERE message_nonces.created_at <= now() - make_interval(hours => $5)
+        RETURNING message_id
+        "#,
+      )
+      .bind(chat_id as i64)
+      .bind(user_id as i64)
+      .bind(nonce)
+      .bind(message.id)
+      .bind(NONCE_WINDOW_HOURS)
+      .fetch_optional(&mut *tx)
+      .await?;
+      if claimed.is_none() {
+        tx.rollback().await?;
+        let message = self.find_message_by_nonce(chat_id, user_id, nonce).await?;
+        return message.ok_or_else(|| {
+          AppError::CreateMessageError(format!("nonce {} is already in use", nonce))
+        });
+      }
+    }
+    tx.commit().await?;
+    self.spawn_unfurl(&message);
+
+    Ok(message)
+  }
+
+  /// Forgets the nonces that can no longer be retried, returns how many.
+  pub(crate) async fn delete_expired_nonces(&self) -> Result<u64, AppError> {
+    let ret = sqlx::query(
+      r#"
+      DELETE FROM message_nonces
+      WHERE created_at <= now() - make_interval(hours => $1)
+      "#,
+    )
+    .bind(NONCE_WINDOW_HOURS)
+    .execute(&self.pool)
+    .await?;
+
+    Ok(ret.rows_affected())
+  }
+
+  /// The message the user sent to the chat with the nonce, while the nonce hasn't expired.
+  async fn find_message_by_nonce(
+    &self,
+    chat_id: u64,
+    user_id: u64,
+    nonce: &str,
+  ) -> Result<Option<Message>, AppError> {
+    let message = sqlx::query_as(
+      r#"
+      SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.parent_id, m.also_in_chat,
+        m.reply_count, m.last_reply_at, m.edited_at, m.deleted_at, m.rich_text,
+        m.link_previews, m.nonce, m.created_at
+      FROM message_nonces n JOIN messages m ON m.id = n.message_id
+      WHERE n.chat_id = $1 AND n.sender_id = $2 AND n.nonce = $3
+      AND n.created_at > now() - make_interval(hours => $4)
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(user_id as i64)
+    .bind(nonce)
+    .bind(NONCE_WINDOW_HOURS)
+    .fetch_optional(&self.pool)
+    .await?;
+
+    Ok(message)
+  }
+
+  /// Messages of the chat, newest first, with reactions as seen by the user.
+  pub async fn list_messages(
+    &self,
+    input: ListMessages,
+    chat_id: u64,
+    user_id: u64,
+  ) -> Result<MessagePage, AppError> {
+    let limit = input
+      .limit
+      .unwrap_or(DEFAULT_MESSAGE_PAGE_SIZE)
+      .clamp(1, MAX_MESSAGE_PAGE_SIZE) as usize;
+    let cursors = [
+      input.last_id.is_some(),
+      input.after_id.is_some(),
+      input.around_id.is_some(),
+      input.since.is_some(),
+    ];
+    if cursors.iter().filter(|set| **set).count() > 1 {
+      return Err(AppError::InvalidPagination(
+        "only one of last_id, after_id, around_id and since can be set".to_string(),
+      ));
+    }
+
+    // pages fetch one extra message to tell if there are more
+    let page = if let Some(id) = input.around_id {
+      // the message itself opens the newer half
+      let half = limit / 2;
+      let mut older = self
+        .fetch_older_messages(chat_id, user_id, Some(id as _), half + 1)
+        .await?;
+      let mut newer = self
+        .fetch_newer_messages(chat_id, user_id, Some(id as _), None, limit - half + 1)
+        .await?;
+      let has_more_before = older.len() > half;
+      let has_more_after = newer.len() > limit - half;
+      older.truncate(half);
+      newer.truncate(limit - half);
+      newer.reverse();
+      newer.append(&mut older);
+      MessagePage {
+        messages: newer,
+        has_more_before,
+        has_more_after,
+      }
+    } else if input.after_id.is_some() || input.since.is_some() {
+      let from_id = input.after_id.map(|id| id as i64 + 1);
+      let mut messages = self
+        .fetch_newer_messages(chat_id, user_id, from_id, input.since, limit + 1)
+        .await?;
+      let has_more_after = messages.len() > limit;
+      messages.truncate(limit);
+      // anything before the page, or anything at all if nothing is newer
+      let before_id = messages.first().map(|m| m.id);
+      let has_more_before = !self
+        .fetch_older_messages(chat_id, user_id, before_id, 1)
+        .await?
+        .is_empty();
+      messages.reverse();
+      MessagePage {
+        messages,
+        has_more_before,
+        has_more_after,
+      }
+    } else {
+      let before_id = input.last_id.map(|id| id as _);
+      let mut messages = self
+        .fetch_older_messages(chat_id, user_id, before_id, limit + 1)
+        .await?;
+      let has_more_before = messages.len() > limit;
+      messages.truncate(limit);
+      let has_more_after = match input.last_id {
+        Some(id) => !self
+          .fetch_newer_messages(chat_id, user_id, Some(id as _), None, 1)
+          .await?
+          .is_empty(),
+        None => false,
+      };
+      MessagePage {
+        messages,
+        has_more_before,
+        has_more_after,
+      }
+    };
+
+    Ok(page)
+  }
+
+  /// Messages shown in the chat before `before_id`, newest first.
+  async fn fetch_older_messages(
+    &self,
+    chat_id: u64,
+    user_id: u64,
+    before_id: Option<i64>,
+    limit: usize,
+  ) -> Result<Vec<Message>, AppError> {
+    let sql = format!(
+      r#"
+      SELECT {MESSAGE_COLUMNS}
+      FROM messages m
+      WHERE chat_id = $2
+      AND (parent_id IS NULL OR also_in_chat)
+      AND ($3::bigint IS NULL OR id < $3)
+      ORDER BY id DESC
+      LIMIT $4
+      "#
+    );
+    let messages = sqlx::query_as(&sql)
+      .bind(user_id as i64)
+      .bind(chat_id as i64)
+      .bind(before_id)
+      .bind(limit as i64)
+      .fetch_all(&self.pool)
+      .await?;
+
+    Ok(messages)
+  }
+
+  /// Messages shown in the chat from `from_id` on and after `since`, oldest first.
+  async fn fetch_newer_messages(
+    &self,
+    chat_id: u64,
+    user_id: u64,
+    from_id: Option<i64>,
+    since: Option<DateTime<Utc>>,
+    limit: usize,
+  ) -> Result<Vec<Message>, AppError> {
+    let sql = format!(
+      r#"
+      SELECT {MESSAGE_COLUMNS}
+      FROM messages m
+      WHERE chat_id = $2
+      AND (parent_id IS NULL OR also_in_chat)
+      AND ($3::bigint IS NULL OR id >= $3)
+      AND ($4::timestamptz IS NULL OR created_at > $4)
+      ORDER BY id
+      LIMIT $5
+      "#
+    );
+    let messages = sqlx::query_as(&sql)
+      .bind(user_id as i64)
+      .bind(chat_id as i64)
+      .bind(from_id)
+      .bind(since)
+      .bind(limit as i64)
+      .fetch_all(&self.pool)
+      .await?;
+
+    Ok(messages)
+  }
+
+  /// Replies in the thread of a top-level message, oldest first.
+  pub async fn list_replies(
+    &self,
+    input: ListReplies,
+    chat_id: u64,
+    parent_id: u64,
+    user_id: u64,
+  ) -> Result<Vec<Message>, AppError> {
+    self.verify_thread_parent(chat_id, parent_id).await?;
+    let sql = format!(
+      r#"
+      SELECT {MESSAGE_COLUMNS}
+      FROM messages m
+      WHERE parent_id = $2
+      AND id > $3
+      ORDER BY id
+      LIMIT $4
+      "#
+    );
+    let messages = sqlx::query_as(&sql)
+      .bind(user_id as i64)
+      .bind(parent_id as i64)
+      .bind(input.after_id.unwrap_or_default() as i64)
+      .bind(
+        input
+          .limit
+          .unwrap_or(DEFAULT_REPLIES_PAGE_SIZE)
+          .clamp(1, MAX_REPLIES_LIMIT) as i64,
+      )
+      .fetch_all(&self.pool)
+      .await?;
+
+    Ok(messages)
+  }
+
+  /// Edits the content of the user's own message, keeping the previous content as a revision.
+  pub async fn update_message(
+    &self,
+    input: UpdateMessage,
+    chat_id: u64,
+    message_id: u64,
+    user_id: u64,
+  ) -> Result<Message, AppError> {
+    verify_content(&input.content).map_err(AppError::UpdateMessageError)?;
+    let message = self.get_live_message(chat_id, message_id).await?;
+    if message.sender_id != user_id as i64 {
+      return Err(AppError::PermissionDenied(
+        "Only the author can edit a message".to_string(),
+      ));
+    }
+    if message.content == input.content {
+      return Ok(message);
+    }
+
+    // previews of links that are still in the message are kept
+    let rich_text = RichText::parse(&input.content);
+    let inlines = rich_text.inlines();
+    let link_previews: Vec<LinkPreview> = message
+      .link_previews
+      .into_iter()
+      .filter(|p| {
+        inlines
+          .iter()
+          .any(|inline| matches!(inline, Inline::Link { url, .. } if *url == p.url))
+      })
+      .collect();
+
+    let mut tx = self.pool.begin().await?;
+    sqlx::query(
+      r#"
+      INSERT INTO message_revisions (message_id, content)
+      VALUES ($1, $2)
+      "#,
+    )
+    .bind(message_id as i64)
+    .bind(&message.content)
+    .execute(&mut *tx)
+    .await?;
+
+    let message: Message = sqlx::query_as(
+      r#"
+      UPDATE messages
+      SET content = $2, rich_text = $3, link_previews = $4, edited_at = now()
+      WHERE id = $1
+      RETURNING id, chat_id, sender_id, content, files, parent_id, also_in_chat, reply_count,
+        last_reply_at, edited_at, deleted_at, rich_text, link_previews, created_at
+      "#,
+    )
+    .bind(message_id as i64)
+    .bind(&input.content)
+    .bind(Json(&rich_text))
+    .bind(Json(&link_previews))
+    .fetch_one(&mut *tx)
+    .await?;
+    sync_mentions(&mut tx, &message).await?;
+    tx.commit().await?;
+    self.spawn_unfurl(&message);
+
+    Ok(message)
+  }
+
+  /// Deletes a message by turning it into a tombstone, its thread is kept but its revisions are not.
+  /// Authors can delete their own messages, channel admins any message in the channel.
+  pub async fn delete_message(
+    &self,
+    chat_id: u64,
+    message_id: u64,
+    user_id: u64,
+  ) -> Result<Message, AppError> {
+    let message = self.get_live_message(chat_id, message_id).await?;
+    if message.sender_id != user_id as i64 {
+      let is_channel_admin = match self.get_chat_by_id(chat_id).await? {
+        Some(chat) if is_channel(&chat.r#type) => {
+          self.get_chat_role(chat_id, user_id).await? >= Some(ChatRole::Admin)
+        }
+        _ => false,
+      };
+      if !is_channel_admin {
+        return Err(AppError::PermissionDenied(
+          "Only the author or a channel admin can delete a message".to_string(),
+        ));
+      }
+    }
+
+    let mut tx = self.pool.begin().await?;
+    // earlier versions would bring the deleted content back
+    sqlx::query(
+      r#"
+      DELETE FROM message_revisions
+      WHERE message_id = $1
+      "#,
+    )
+    .bind(message_id as i64)
+    .execute(&mut *tx)
+    .await?;
+
+    // tombstones aren't pinned
+    sqlx::query(
+      r#"
+      DELETE FROM message_pins
+      WHERE message_id = $1
+      "#,
+    )
+    .bind(message_id as i64)
+    .execute(&mut *tx)
+    .await?;
+
+    // nor reacted to, mentioned from or bookmarked
+    sqlx::query(
+      r#"
+      WITH reactions AS (
+        DELETE FROM message_reactions WHERE message_id = $1
+      ), mentions AS (
+        DELETE FROM message_mentions WHERE message_id = $1
+      )
+      DELETE FROM bookmarks
+      WHERE message_id = $1
+      "#,
+    )
+    .bind(message_id as i64)
+    .execute(&mut *tx)
+    .await?;
+
+    let message = sqlx::query_as(
+      r#"
+      UPDATE messages
+      SET content = '', files = '{}', rich_text = 'null', link_previews = '[]',
+        deleted_at = now()
+      WHERE id = $1
+      RETURNING id, chat_id, sender_id, content, files, parent_id, also_in_chat, reply_count,
+        last_reply_at, edited_at, deleted_at, rich_text, link_previews, created_at
+      "#,
+    )
+    .bind(message_id as i64)
+    .fetch_one(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(message)
+  }
+
+  /// Previous versions of a message, oldest first. Deleted messages have none.
+  pub async fn list_message_revisions(
+    &self,
+    chat_id: u64,
+    message_id: u64,
+  ) -> Result<Vec<MessageRevision>, AppError> {
+    let revisions = sqlx::query_as(
+      r#"
+      SELECT r.id, r.message_id, r.content, r.created_at
+      FROM message_revisions r JOIN messages m ON m.id = r.message_id
+      WHERE r.message_id = $1 AND m.chat_id = $2 AND m.deleted_at IS NULL
+      ORDER BY r.id
+      "#,
+    )
+    .bind(message_id as i64)
+    .bind(chat_id as i64)
+    .fetch_all(&self.pool)
+    .await?;
+
+    Ok(revisions)
+  }
+
+  /// Checks a message can be sent to the chat, now or when it is scheduled.
+  pub(crate) async fn verify_new_message(
+    &self,
+    input: &CreateMessage,
+    chat_id: u64,
+  ) -> Result<(), AppError> {
     let base_dir = &self.config.server.base_dir;
-    if input.content.is_empty() {
+    verify_content(&input.content).map_err(AppError::CreateMessageError)?;
+
+    if self.is_chat_archived(chat_id).await? {
+      return Err(AppError::ChatArchived(chat_id));
+    }
+
+    if let Some(parent_id) = input.parent_id {
+      self.verify_thread_parent(chat_id, parent_id).await?;
+    } else if input.also_in_chat {
       return Err(AppError::CreateMessageError(
-        "content cannot be empty".to_string(),
+        "Only thread replies can also be shown in the chat".to_string(),
       ));
     }
 
@@ -40,62 +560,182 @@ impl AppState {
         )));
       }
     }
+    Ok(())
+  }
 
-    // create message
-    let message: Message = sqlx::query_as(
+  /// A message of the chat that can still be changed.
+  pub(crate) async fn get_live_message(
+    &self,
+    chat_id: u64,
+    message_id: u64,
+  ) -> Result<Message, AppError> {
+    if self.is_chat_archived(chat_id).await? {
+      return Err(AppError::ChatArchived(chat_id));
+    }
+    let message: Option<Message> = sqlx::query_as(
       r#"
-      INSERT INTO messages (chat_id, sender_id, content, files)
-      VALUES ($1, $2, $3, $4)
-      RETURNING id, chat_id, sender_id, content, files, created_at
+      SELECT id, chat_id, sender_id, content, files, parent_id, also_in_chat, reply_count,
+        last_reply_at, edited_at, deleted_at, rich_text, link_previews, created_at
+      FROM messages
+      WHERE id = $1 AND chat_id = $2
       "#,
     )
+    .bind(message_id as i64)
     .bind(chat_id as i64)
-    .bind(user_id as i64)
-    .bind(input.content)
-    .bind(&input.files)
-    .fetch_one(&self.pool)
+    .fetch_optional(&self.pool)
     .await?;
 
-    Ok(message)
+    match message {
+      Some(message) if message.deleted_at.is_none() => Ok(message),
+      _ => Err(AppError::NotFound(format!(
+        "Message {} not found in chat {}",
+        message_id, chat_id
+      ))),
+    }
   }
 
-  pub async fn list_messages(
-    &self,
-    input: ListMessages,
-    chat_id: u64,
-  ) -> Result<Vec<Message>, AppError> {
-    let last_id = input.last_id.unwrap_or(i64::MAX as _);
-    let messages: Vec<Message> = sqlx::query_as(
+  /// Threads start at top-level messages of the same chat, replies can't have replies.
+  async fn verify_thread_parent(&self, chat_id: u64, parent_id: u64) -> Result<(), AppError> {
+    let parent: Option<(Option<i64>,)> = sqlx::query_as(
       r#"
-      SELECT id, chat_id, sender_id, content, files, created_at
+      SELECT parent_id
       FROM messages
-      WHERE chat_id = $1
-      AND id < $2
-      ORDER BY id DESC
-      LIMIT $3
+      WHERE id = $1 AND chat_id = $2 AND deleted_at IS NULL
       "#,
     )
+    .bind(parent_id as i64)
     .bind(chat_id as i64)
-    .bind(last_id as i64)
-    .bind(input.limit as i64)
-    .fetch_all(&self.pool)
+    .fetch_optional(&self.pool)
     .await?;
 
-    Ok(messages)
+    match parent {
+      None => Err(AppError::NotFound(format!(
+        "Message {} not found in chat {}",
+        parent_id, chat_id
+      ))),
+      Some((Some(_),)) => Err(AppError::CreateMessageError(
+        "Replies can't have replies".to_string(),
+      )),
+      Some((None,)) => Ok(()),
+    }
   }
 }
 
+pub(super) fn verify_content(content: &str) -> Result<(), String> {
+  if content.is_empty() {
+    return Err("content cannot be empty".to_string());
+  }
+  if content.chars().count() > MAX_CONTENT_LENGTH {
+    return Err(format!(
+      "content can be at most {} characters",
+      MAX_CONTENT_LENGTH
+    ));
+  }
+  Ok(())
+}
+
+/// Inserts a verified message with its mentions, the caller commits and unfurls it.
+pub(crate) async fn insert_message(
+  conn: &mut PgConnection,
+  input: &CreateMessage,
+  chat_id: u64,
+  user_id: u64,
+) -> Result<Message, AppError> {
+  let message: Message = sqlx::query_as(
+    r#"
+    INSERT INTO messages (chat_id, sender_id, content, files, parent_id, also_in_chat,
+      rich_text, nonce)
+    VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+    RETURNING id, chat_id, sender_id, content, files, parent_id, also_in_chat, reply_count,
+      last_reply_at, edited_at, deleted_at, rich_text, link_previews, nonce, created_at
+    "#,
+  )
+  .bind(chat_id as i64)
+  .bind(user_id as i64)
+  .bind(&input.content)
+  .bind(&input.files)
+  .bind(input.parent_id.map(|id| id as i64))
+  .bind(input.also_in_chat)
+  .bind(Json(RichText::parse(&input.content)))
+  .bind(&input.nonce)
+  .fetch_one(&mut *conn)
+  .await?;
+  sync_mentions(conn, &message).await?;
+
+  Ok(message)
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
   use anyhow::Result;
 
+  #[tokio::test]
+  async fn create_message_with_nonce_should_be_idempotent() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let input = |content: &str, nonce: &str| CreateMessage {
+      content: content.to_string(),
+      nonce: Some(nonce.to_string()),
+      ..Default::default()
+    };
+    let count = |chat_id: i64| {
+      let pool = state.pool.clone();
+      async move {
+        let count: (i64,) = sqlx::query_as("SELECT count(*) FROM messages WHERE chat_id = $1")
+          .bind(chat_id)
+          .fetch_one(&pool)
+          .await?;
+        anyhow::Ok(count.0)
+      }
+    };
+
+    let before = count(1).await?;
+    let message = state.create_message(input("hello", "n-1"), 1, 1).await?;
+    assert_eq!(message.nonce.as_deref(), Some("n-1"));
+    let retried = state.create_message(input("hello", "n-1"), 1, 1).await?;
+    assert_eq!(retried, message);
+    assert_eq!(count(1).await?, before + 1);
+
+    // concurrent retries get the same message
+    let (a, b) = tokio::join!(
+      state.create_message(input("race", "n-2"), 1, 1),
+      state.create_message(input("race", "n-2"), 1, 1),
+    );
+    assert_eq!(a?.id, b?.id);
+    assert_eq!(count(1).await?, before + 2);
+
+    // nonces are per sender and chat
+    let other = state.create_message(input("hello", "n-1"), 1, 2).await?;
+    assert_ne!(other.id, message.id);
+    let other = state.create_message(input("hello", "n-1"), 2, 1).await?;
+    assert_ne!(other.id, message.id);
+
+    // and can be used again once they expire
+    sqlx::query("UPDATE message_nonces SET created_at = now() - interval '25 hours'")
+      .execute(&state.pool)
+      .await?;
+    let again = state.create_message(input("hello", "n-1"), 1, 1).await?;
+    assert_ne!(again.id, message.id);
+    assert_eq!(state.delete_expired_nonces().await?, 3);
+
+    let err = state
+      .create_message(input("hello", &"n".repeat(65)), 1, 1)
+      .await
+      .unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "create message error: nonce must be 1 to 64 bytes"
+    );
+    Ok(())
+  }
+
   #[tokio::test]
   async fn create_message_should_work() -> Result<()> {
     let (_tdb, state) = AppState::new_for_test().await?;
     let input = CreateMessage {
       content: "hello".to_string(),
       files: vec![],
+      ..Default::default()
     };
     let message = state
       .create_message(input, 1, 1)
@@ -107,6 +747,7 @@ mod tests {
     let input = CreateMessage {
       content: "hello".to_string(),
       files: vec!["1".to_string()],
+      ..Default::default()
     };
 
     let err = state.create_message(input, 1, 1).await.unwrap_err();
@@ -117,6 +758,7 @@ mod tests {
     let input = CreateMessage {
       content: "hello".to_string(),
       files: vec![url],
+      ..Default::default()
     };
 
     let message = state
@@ -126,6 +768,27 @@ mod tests {
     assert_eq!(message.content, "hello");
     assert_eq!(message.files.len(), 1);
 
+    // content is capped in characters, not bytes
+    let input = CreateMessage {
+      content: "é".repeat(MAX_CONTENT_LENGTH),
+      ..Default::default()
+    };
+    let message = state.create_message(input, 1, 1).await?;
+    let input = CreateMessage {
+      content: "x".repeat(MAX_CONTENT_LENGTH + 1),
+      ..Default::default()
+    };
+    let err = state.create_message(input, 1, 1).await.unwrap_err();
+    assert!(matches!(err, AppError::CreateMessageError(_)));
+    let input = UpdateMessage {
+      content: "x".repeat(MAX_CONTENT_LENGTH + 1),
+    };
+    let err = state
+      .update_message(input, 1, message.id as _, 1)
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::UpdateMessageError(_)));
+
     Ok(())
   }
 
@@ -133,22 +796,272 @@ mod tests {
   async fn list_messages_should_work() -> Result<()> {
     let (_tdb, state) = AppState::new_for_test().await?;
     let input = ListMessages {
-      last_id: None,
-      limit: 6,
+      limit: Some(6),
+      ..Default::default()
     };
 
-    let messages = state.list_messages(input, 1).await?;
-    assert_eq!(messages.len(), 6);
+    let page = state.list_messages(input, 1, 1).await?;
+    assert_eq!(page.messages.len(), 6);
+    assert!(page.has_more_before && !page.has_more_after);
 
-    let last_id = messages.last().expect("last message should exists").id;
+    let last_id = page.messages.last().expect("last message should exists").id;
     let input = ListMessages {
       last_id: Some(last_id as _),
-      limit: 6,
+      limit: Some(6),
+      ..Default::default()
+    };
+
+    let page = state.list_messages(input, 1, 1).await?;
+    assert_eq!(page.messages.len(), 4);
+    assert!(!page.has_more_before && page.has_more_after);
+
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn list_messages_should_page_both_ways() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let ids = |page: &MessagePage| page.messages.iter().map(|m| m.id).collect::<Vec<_>>();
+    let input = ListMessages {
+      around_id: Some(5),
+      limit: Some(4),
+      ..Default::default()
+    };
+    let page = state.list_messages(input, 1, 1).await?;
+    assert_eq!(ids(&page), [6, 5, 4, 3]);
+    assert!(page.has_more_before && page.has_more_after);
+
+    let input = ListMessages {
+      after_id: Some(7),
+      limit: Some(2),
+      ..Default::default()
+    };
+    let page = state.list_messages(input, 1, 1).await?;
+    assert_eq!(ids(&page), [9, 8]);
+    assert!(page.has_more_before && page.has_more_after);
+
+    let input = ListMessages {
+      after_id: Some(10),
+      ..Default::default()
+    };
+    let page = state.list_messages(input, 1, 1).await?;
+    assert!(page.messages.is_empty());
+    assert!(page.has_more_before && !page.has_more_after);
+
+    let since = Utc::now();
+    let message = state
+      .create_message(
+        CreateMessage {
+          content: "catch up".to_string(),
+          ..Default::default()
+        },
+        1,
+        2,
+      )
+      .await?;
+    let input = ListMessages {
+      since: Some(since),
+      ..Default::default()
     };
+    let page = state.list_messages(input, 1, 1).await?;
+    assert_eq!(ids(&page), [message.id]);
+    assert!(page.has_more_before && !page.has_more_after);
 
-    let messages = state.list_messages(input, 1).await?;
-    assert_eq!(messages.len(), 4);
+    let input = ListMessages {
+      last_id: Some(5),
+      after_id: Some(1),
+      ..Default::default()
+    };
+    let err = state.list_messages(input, 1, 1).await.unwrap_err();
+    assert!(matches!(err, AppError::InvalidPagination(_)));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn thread_replies_should_work() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let reply = |content: &str, parent_id: u64, also_in_chat: bool| CreateMessage {
+      content: content.to_string(),
+      parent_id: Some(parent_id),
+      also_in_chat,
+      ..Default::default()
+    };
+    let first = state.create_message(reply("first", 1, false), 1, 2).await?;
+    assert_eq!(first.parent_id, Some(1));
+    let second = state.create_message(reply("second", 1, true), 1, 3).await?;
+    state.create_message(reply("third", 1, false), 1, 4).await?;
+
+    // replies of replies and threads across chats are rejected
+    let err = state
+      .create_message(reply("nested", first.id as _, false), 1, 2)
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::CreateMessageError(_)));
+    let err = state
+      .create_message(reply("elsewhere", 1, false), 3, 1)
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+
+    // only the reply shown in the chat is listed there
+    let input = ListMessages {
+      limit: Some(20),
+      ..Default::default()
+    };
+    let messages = state.list_messages(input, 1, 1).await?.messages;
+    assert_eq!(messages.len(), 11);
+    assert_eq!(messages[0].id, second.id);
+    let parent = messages.last().expect("parent should be listed");
+    assert_eq!(parent.reply_count, 3);
+    assert!(parent.last_reply_at.is_some());
+
+    let input = ListReplies {
+      after_id: None,
+      limit: Some(2),
+    };
+    let replies = state.list_replies(input, 1, 1, 1).await?;
+    let contents: Vec<_> = replies.iter().map(|m| m.content.as_str()).collect();
+    assert_eq!(contents, ["first", "second"]);
+    let input = ListReplies {
+      after_id: Some(replies[1].id as _),
+      limit: Some(2),
+    };
+    let replies = state.list_replies(input, 1, 1, 1).await?;
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0].content, "third");
+    let replies = state.list_replies(ListReplies::default(), 1, 1, 1).await?;
+    assert_eq!(replies.len(), 3);
+
+    // deleted messages can't be replied to
+    state.delete_message(1, 1, 1).await?;
+    let err = state
+      .create_message(reply("late", 1, false), 1, 2)
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    Ok(())
+  }
 
+  #[tokio::test]
+  async fn removed_replies_should_not_count() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let reply = |content: &str| CreateMessage {
+      content: content.to_string(),
+      parent_id: Some(1),
+      ..Default::default()
+    };
+    let first = state.create_message(reply("first"), 1, 2).await?;
+    let second = state.create_message(reply("second"), 1, 2).await?;
+    let stats = || async {
+      let stats: (i32, Option<DateTime<Utc>>) =
+        sqlx::query_as("SELECT reply_count, last_reply_at FROM messages WHERE id = 1")
+          .fetch_one(&state.pool)
+          .await?;
+      anyhow::Ok(stats)
+    };
+    assert_eq!(stats().await?, (2, Some(second.created_at)));
+
+    // tombstones and deleted rows are both left out
+    state.delete_message(1, second.id as _, 2).await?;
+    assert_eq!(stats().await?, (1, Some(first.created_at)));
+    sqlx::query("DELETE FROM messages WHERE id = $1")
+      .bind(first.id)
+      .execute(&state.pool)
+      .await?;
+    assert_eq!(stats().await?, (0, None));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn edit_message_should_keep_revisions() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let edit = |content: &str| UpdateMessage {
+      content: content.to_string(),
+    };
+    // message 2 is alice's
+    let err = state
+      .update_message(edit("hijacked"), 1, 2, 1)
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+
+    let message = state.update_message(edit("Hi, there."), 1, 2, 2).await?;
+    assert_eq!(message.content, "Hi, there.");
+    assert!(message.edited_at.is_some());
+    state.update_message(edit("Hi, all!"), 1, 2, 2).await?;
+
+    let revisions = state.list_message_revisions(1, 2).await?;
+    let contents: Vec<_> = revisions.iter().map(|r| r.content.as_str()).collect();
+    assert_eq!(contents, ["Hi, there!", "Hi, there."]);
+
+    let err = state.update_message(edit(""), 1, 2, 2).await.unwrap_err();
+    assert!(matches!(err, AppError::UpdateMessageError(_)));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn delete_message_should_leave_tombstone() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    // plain members can only delete their own messages
+    let err = state.delete_message(1, 1, 2).await.unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+
+    let message = state.delete_message(1, 2, 2).await?;
+    assert!(message.deleted_at.is_some());
+    assert!(message.content.is_empty());
+    // channel admins can delete any message
+    state.delete_message(1, 3, 1).await?;
+
+    let input = ListMessages {
+      last_id: Some(4),
+      limit: Some(3),
+      ..Default::default()
+    };
+    let messages = state.list_messages(input, 1, 1).await?.messages;
+    assert_eq!(messages.len(), 3);
+    assert!(messages[0].deleted_at.is_some());
+    assert!(messages[1].deleted_at.is_some());
+    assert!(messages[2].deleted_at.is_none());
+
+    // tombstones can't be edited or deleted again
+    let input = UpdateMessage {
+      content: "back".to_string(),
+    };
+    let err = state.update_message(input, 1, 2, 2).await.unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    let err = state.delete_message(1, 2, 2).await.unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn delete_message_should_purge_related_rows() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let input = UpdateMessage {
+      content: "edited @hal".to_string(),
+    };
+    state.update_message(input, 1, 2, 2).await?;
+    assert_eq!(state.list_message_revisions(1, 2).await?.len(), 1);
+    state.add_reaction(1, 2, 1, "👍").await?;
+    state
+      .save_bookmark(1, 2, 1, crate::SaveBookmark::default())
+      .await?;
+
+    state.delete_message(1, 2, 2).await?;
+    assert!(state.list_message_revisions(1, 2).await?.is_empty());
+    for table in [
+      "message_revisions",
+      "message_reactions",
+      "message_mentions",
+      "bookmarks",
+    ] {
+      let count: i64 = sqlx::query_scalar(&format!(
+        "SELECT count(*) FROM {table} WHERE message_id = 2"
+      ))
+      .fetch_one(&state.pool)
+      .await?;
+      assert_eq!(count, 0, "{table}");
+    }
     Ok(())
   }
 
@@ -1,13 +1,38 @@
+mod bookmark;
 mod chat;
+mod command;
 mod file;
+mod link_preview;
+mod mention;
 mod message;
+mod pin;
+mod reaction;
+mod retention;
+mod scheduled;
+mod search;
 mod user;
 mod workspace;
 
-pub use chat::{CreateChat, UpdateChat};
-pub use message::{CreateMessage, ListMessages};
+pub use bookmark::{ListBookmarks, SaveBookmark};
+pub use chat::{
+  CreateChat, ListChannels, ListChats, MarkRead, OpenDirectChat, UpdateChat, UpdateMemberRole,
+};
+pub(crate) use command::parse_command;
+pub use command::CreateSlashCommand;
+pub(crate) use link_preview::Unfurler;
+pub(crate) use mention::sync_mentions;
+pub use mention::ListMentions;
+pub(crate) use message::insert_message;
+pub use message::{CreateMessage, ListMessages, ListReplies, UpdateMessage};
+pub use pin::ListPins;
+pub(crate) use retention::spawn_retention_job;
+pub use retention::UpdateRetention;
+pub(crate) use scheduled::spawn_scheduled_dispatcher;
+pub use scheduled::{ListScheduledMessages, ScheduleMessage, UpdateScheduledMessage};
+pub use search::SearchMessages;
 use serde::{Deserialize, Serialize};
-pub use user::{CreateUser, SigninUser};
+pub(crate) use user::escape_like;
+pub use user::{ChangePassword, CreateUser, ListUsers, SigninUser, UpdateProfile};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatFile {
@@ -0,0 +1,170 @@
+use super::chat::is_channel;
+use crate::{AppError, AppState};
+use chat_core::{ChatRole, Pin, PinnedMessage};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListPins {
+  /// pins made before this one
+  pub last_id: Option<u64>,
+  pub limit: Option<u64>,
+}
+
+const DEFAULT_PIN_PAGE_SIZE: u64 = 20;
+const MAX_PIN_PAGE_SIZE: u64 = 100;
+
+impl AppState {
+  /// Pins a message to its chat, pinning it again is a no-op.
+  pub async fn pin_message(
+    &self,
+    chat_id: u64,
+    message_id: u64,
+    user_id: u64,
+  ) -> Result<Pin, AppError> {
+    self.check_pin_permission(chat_id, user_id).await?;
+    self.get_live_message(chat_id, message_id).await?;
+
+    let pin = sqlx::query_as(
+      r#"
+      WITH inserted AS (
+        INSERT INTO message_pins (message_id, chat_id, pinned_by)
+        VALUES ($2, $1, $3)
+        ON CONFLICT (message_id) DO NOTHING
+        RETURNING id, message_id, chat_id, pinned_by, created_at
+      )
+      SELECT id, message_id, chat_id, pinned_by, created_at FROM inserted
+      UNION ALL
+      SELECT id, message_id, chat_id, pinned_by, created_at
+      FROM message_pins
+      WHERE message_id = $2
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(message_id as i64)
+    .bind(user_id as i64)
+    .fetch_one(&self.pool)
+    .await?;
+
+    Ok(pin)
+  }
+
+  pub async fn unpin_message(
+    &self,
+    chat_id: u64,
+    message_id: u64,
+    user_id: u64,
+  ) -> Result<(), AppError> {
+    if self.is_chat_archived(chat_id).await? {
+      return Err(AppError::ChatArchived(chat_id));
+    }
+    self.check_pin_permission(chat_id, user_id).await?;
+
+    let ret = sqlx::query(
+      r#"
+      DELETE FROM message_pins
+      WHERE chat_id = $1 AND message_id = $2
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(message_id as i64)
+    .execute(&self.pool)
+    .await?;
+
+    if ret.rows_affected() == 0 {
+      return Err(AppError::NotFound(format!(
+        "Message {} is not pinned in chat {}",
+        message_id, chat_id
+      )));
+    }
+    Ok(())
+  }
+
+  /// Pinned messages of a chat, most recently pinned first.
+  pub async fn list_pins(
+    &self,
+    chat_id: u64,
+    input: ListPins,
+  ) -> Result<Vec<PinnedMessage>, AppError> {
+    let pins = sqlx::query_as(
+      r#"
+      SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.parent_id, m.also_in_chat,
+        m.reply_count, m.last_reply_at, m.edited_at, m.deleted_at, m.rich_text, m.link_previews, m.created_at,
+        p.id AS pin_id, p.pinned_by, p.created_at AS pinned_at
+      FROM message_pins p JOIN messages m ON m.id = p.message_id
+      WHERE p.chat_id = $1 AND p.id < $2
+      ORDER BY p.id DESC
+      LIMIT $3
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(input.last_id.unwrap_or(i64::MAX as _) as i64)
+    .bind(
+      input
+        .limit
+        .unwrap_or(DEFAULT_PIN_PAGE_SIZE)
+        .clamp(1, MAX_PIN_PAGE_SIZE) as i64,
+    )
+    .fetch_all(&self.pool)
+    .await?;
+
+    Ok(pins)
+  }
+
+  /// Channel pins are managed by channel admins, any member can pin in other chats.
+  async fn check_pin_permission(&self, chat_id: u64, user_id: u64) -> Result<(), AppError> {
+    match self.get_chat_by_id(chat_id).await? {
+      Some(chat) if is_channel(&chat.r#type) => {
+        if self.get_chat_role(chat_id, user_id).await? < Some(ChatRole::Admin) {
+          return Err(AppError::PermissionDenied(
+            "Only channel admins can pin messages".to_string(),
+          ));
+        }
+        Ok(())
+      }
+      Some(_) => Ok(()),
+      None => Err(AppError::NotFound(format!("Chat {} not found", chat_id))),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use anyhow::Result;
+
+  #[tokio::test]
+  async fn pins_should_be_managed_by_channel_admins() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let err = state.pin_message(1, 2, 2).await.unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+
+    let pin = state.pin_message(1, 2, 1).await?;
+    assert_eq!((pin.message_id, pin.pinned_by), (2, 1));
+    // pinning again keeps the first pin
+    assert_eq!(state.pin_message(1, 2, 1).await?, pin);
+    state.pin_message(1, 5, 1).await?;
+    let err = state.pin_message(2, 5, 1).await.unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+
+    let input = ListPins {
+      limit: Some(1),
+      ..Default::default()
+    };
+    let pins = state.list_pins(1, input).await?;
+    assert_eq!(pins[0].message.id, 5);
+    let input = ListPins {
+      last_id: Some(pins[0].pin_id as _),
+      limit: Some(1),
+    };
+    let pins = state.list_pins(1, input).await?;
+    assert_eq!(pins[0].message.id, 2);
+
+    // deleting a message drops its pin
+    state.delete_message(1, 2, 2).await?;
+    assert_eq!(state.list_pins(1, ListPins::default()).await?.len(), 1);
+    state.unpin_message(1, 5, 1).await?;
+    let err = state.unpin_message(1, 5, 1).await.unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    Ok(())
+  }
+}
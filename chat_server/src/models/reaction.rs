@@ -0,0 +1,225 @@
+use crate::{AppError, AppState};
+use chat_core::Reaction;
+
+const MAX_EMOJI_LENGTH: usize = 64;
+
+impl AppState {
+  /// Reacts to a message with an emoji, reacting twice with the same emoji is a no-op.
+  pub async fn add_reaction(
+    &self,
+    chat_id: u64,
+    message_id: u64,
+    user_id: u64,
+    emoji: &str,
+  ) -> Result<Reaction, AppError> {
+    let emoji = validate_emoji(emoji)?;
+    if self.is_chat_archived(chat_id).await? {
+      return Err(AppError::ChatArchived(chat_id));
+    }
+
+    let reaction = sqlx::query_as(
+      r#"
+      WITH inserted AS (
+        INSERT INTO message_reactions (message_id, user_id, emoji)
+        SELECT id, $3, $4
+        FROM messages
+        WHERE id = $2 AND chat_id = $1 AND deleted_at IS NULL
+        ON CONFLICT DO NOTHING
+        RETURNING message_id, user_id, emoji, created_at
+      )
+      SELECT message_id, $1 AS chat_id, user_id, emoji, created_at FROM inserted
+      UNION ALL
+      SELECT r.message_id, m.chat_id, r.user_id, r.emoji, r.created_at
+      FROM message_reactions r JOIN messages m ON m.id = r.message_id
+      WHERE r.message_id = $2 AND m.chat_id = $1 AND m.deleted_at IS NULL
+      AND r.user_id = $3 AND r.emoji = $4
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(message_id as i64)
+    .bind(user_id as i64)
+    .bind(emoji)
+    .fetch_optional(&self.pool)
+    .await?;
+
+    reaction.ok_or_else(|| {
+      AppError::NotFound(format!(
+        "Message {} not found in chat {}",
+        message_id, chat_id
+      ))
+    })
+  }
+
+  pub async fn remove_reaction(
+    &self,
+    chat_id: u64,
+    message_id: u64,
+    user_id: u64,
+    emoji: &str,
+  ) -> Result<(), AppError> {
+    if self.is_chat_archived(chat_id).await? {
+      return Err(AppError::ChatArchived(chat_id));
+    }
+
+    let ret = sqlx::query(
+      r#"
+      DELETE FROM message_reactions r
+      USING messages m
+      WHERE m.id = r.message_id AND m.chat_id = $1
+      AND r.message_id = $2 AND r.user_id = $3 AND r.emoji = $4
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(message_id as i64)
+    .bind(user_id as i64)
+    .bind(emoji.trim())
+    .execute(&self.pool)
+    .await?;
+
+    if ret.rows_affected() == 0 {
+      return Err(AppError::NotFound(format!(
+        "Reaction {} not found on message {}",
+        emoji, message_id
+      )));
+    }
+    Ok(())
+  }
+
+  /// Who reacted to a message with what, in the order they reacted.
+  pub async fn list_reactions(
+    &self,
+    chat_id: u64,
+    message_id: u64,
+  ) -> Result<Vec<Reaction>, AppError> {
+    let reactions = sqlx::query_as(
+      r#"
+      SELECT r.message_id, m.chat_id, r.user_id, r.emoji, r.created_at
+      FROM message_reactions r JOIN messages m ON m.id = r.message_id
+      WHERE r.message_id = $2 AND m.chat_id = $1
+      ORDER BY r.created_at, r.user_id
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(message_id as i64)
+    .fetch_all(&self.pool)
+    .await?;
+
+    Ok(reactions)
+  }
+}
+
+/// Emojis are either unicode emoji or `:shortcode:`, anything short without whitespace is accepted.
+/// A reaction is either a `:shortcode:` or a single emoji, possibly joined from several.
+fn validate_emoji(emoji: &str) -> Result<&str, AppError> {
+  let emoji = emoji.trim();
+  let valid = if let Some(code) = emoji.strip_prefix(':').and_then(|e| e.strip_suffix(':')) {
+    !code.is_empty()
+      && code
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '+' | '-'))
+  } else {
+    // digits, # and * are only emoji as keycaps, e.g. 1️⃣
+    let keycap = emoji.contains('\u{20E3}');
+    emoji.chars().any(|c| is_emoji_char(c) || keycap)
+      && emoji.chars().all(|c| {
+        is_emoji_char(c)
+          || is_emoji_modifier(c)
+          || (keycap && (c.is_ascii_digit() || c == '#' || c == '*'))
+      })
+  };
+  if !valid || emoji.chars().count() > MAX_EMOJI_LENGTH {
+    return Err(AppError::InvalidReaction(emoji.to_string()));
+  }
+  Ok(emoji)
+}
+
+fn is_emoji_char(c: char) -> bool {
+  matches!(
+    c as u32,
+    0xA9 | 0xAE
+      | 0x203C
+      | 0x2049
+      | 0x2122
+      | 0x2139
+      | 0x2190..=0x21FF
+      | 0x2300..=0x23FF
+      | 0x24C2
+      | 0x25A0..=0x27BF
+      | 0x2934..=0x2935
+      | 0x2B00..=0x2BFF
+      | 0x3030
+      | 0x303D
+      | 0x3297
+      | 0x3299
+      | 0x1F000..=0x1FAFF
+  )
+}
+
+/// joiners, variation selectors, the keycap mark and flag tags
+fn is_emoji_modifier(c: char) -> bool {
+  matches!(
+    c as u32,
+    0x200D | 0x20E3 | 0xFE0E | 0xFE0F | 0xE0020..=0xE007F
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ListMessages;
+  use anyhow::Result;
+
+  #[tokio::test]
+  async fn reactions_should_be_aggregated_per_emoji() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    state.add_reaction(1, 1, 1, "👍").await?;
+    state.add_reaction(1, 1, 2, "👍").await?;
+    // reacting twice is a no-op
+    let reaction = state.add_reaction(1, 1, 2, "👍").await?;
+    assert_eq!(reaction.chat_id, 1);
+    state.add_reaction(1, 1, 2, ":tada:").await?;
+
+    let input = ListMessages {
+      last_id: Some(2),
+      limit: Some(1),
+      ..Default::default()
+    };
+    let messages = state.list_messages(input, 1, 1).await?.messages;
+    let reactions = &messages[0].reactions;
+    assert_eq!(reactions.len(), 2);
+    assert_eq!(reactions[0].emoji, "👍");
+    assert_eq!(reactions[0].count, 2);
+    assert!(reactions[0].reacted);
+    assert_eq!(reactions[1].emoji, ":tada:");
+    assert!(!reactions[1].reacted);
+
+    state.remove_reaction(1, 1, 2, "👍").await?;
+    let reactions = state.list_reactions(1, 1).await?;
+    let users: Vec<_> = reactions.iter().map(|r| r.user_id).collect();
+    assert_eq!(users, [1, 2]);
+    let err = state.remove_reaction(1, 1, 2, "👍").await.unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn invalid_reactions_should_fail() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    for emoji in ["thumbs up", "<script>", "::", ":a b:", "a👍", "1", "é", ""] {
+      let err = state.add_reaction(1, 1, 1, emoji).await.unwrap_err();
+      assert!(matches!(err, AppError::InvalidReaction(_)), "{emoji}");
+    }
+    for emoji in [":+1:", ":thumbs_up:", "👍🏽", "👨‍👩‍👧", "🇺🇳", "1️⃣", "❤️"]
+    {
+      state.add_reaction(1, 1, 1, emoji).await?;
+    }
+    // deleted messages can't be reacted to
+    state.delete_message(1, 2, 2).await?;
+    let err = state.add_reaction(1, 2, 1, "👍").await.unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    // the message must be in the chat
+    let err = state.add_reaction(3, 1, 1, "👍").await.unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    Ok(())
+  }
+}
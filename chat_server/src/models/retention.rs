@@ -0,0 +1,419 @@
+use crate::{AppError, AppState, ChatFile};
+use chat_core::{ChatRetention, RetentionPolicy, RetentionReport};
+use serde::{Deserialize, Serialize};
+use std::{
+  io,
+  path::{Path, PathBuf},
+  time::{Duration, SystemTime},
+};
+use tokio::fs;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateRetention {
+  /// `None` keeps messages forever, or falls back to the workspace setting for a chat
+  pub retention_days: Option<i32>,
+  /// chats only, keeps their messages forever whatever the workspace setting
+  #[serde(default)]
+  pub keep_forever: bool,
+}
+
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const RETENTION_BATCH_SIZE: i64 = 1000;
+/// uploads are referenced once their message is sent, files younger than this are kept
+const ORPHAN_FILE_GRACE: Duration = Duration::from_secs(24 * 60 * 60);
+
+impl AppState {
+  pub async fn set_workspace_retention(
+    &self,
+    ws_id: u64,
+    user_id: u64,
+    input: UpdateRetention,
+  ) -> Result<RetentionPolicy, AppError> {
+    self.check_retention_admin(ws_id, user_id).await?;
+    verify_retention_days(&input)?;
+    if input.keep_forever {
+      return Err(AppError::RetentionError(
+        "keep_forever is for chats, workspaces keep messages forever without retention_days"
+          .to_string(),
+      ));
+    }
+
+    let policy = sqlx::query_as(
+      r#"
+      UPDATE workspaces
+      SET retention_days = $2
+      WHERE id = $1
+      RETURNING id AS ws_id, NULL::bigint AS chat_id, retention_days, FALSE AS keep_forever
+      "#,
+    )
+    .bind(ws_id as i64)
+    .bind(input.retention_days)
+    .fetch_one(&self.pool)
+    .await?;
+
+    Ok(policy)
+  }
+
+  pub async fn set_chat_retention(
+    &self,
+    chat_id: u64,
+    user_id: u64,
+    input: UpdateRetention,
+  ) -> Result<RetentionPolicy, AppError> {
+    let Some(chat) = self.get_chat_by_id(chat_id).await? else {
+      return Err(AppError::NotFound(format!("Chat {} not found", chat_id)));
+    };
+    self.check_retention_admin(chat.ws_id as _, user_id).await?;
+    verify_retention_days(&input)?;
+
+    let policy = sqlx::query_as(
+      r#"
+      UPDATE chats
+      SET retention_days = $2, keep_forever = $3
+      WHERE id = $1
+      RETURNING ws_id, id AS chat_id, retention_days, keep_forever
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(input.retention_days)
+    .bind(input.keep_forever)
+    .fetch_one(&self.pool)
+    .await?;
+
+    Ok(policy)
+  }
+
+  /// Dry run of the retention job for a workspace.
+  pub async fn retention_report(
+    &self,
+    ws_id: u64,
+    user_id: u64,
+  ) -> Result<RetentionReport, AppError> {
+    self.check_retention_admin(ws_id, user_id).await?;
+
+    let (retention_days,): (Option<i32>,) = sqlx::query_as(
+      r#"
+      SELECT retention_days
+      FROM workspaces
+      WHERE id = $1
+      "#,
+    )
+    .bind(ws_id as i64)
+    .fetch_one(&self.pool)
+    .await?;
+
+    let chats: Vec<ChatRetention> = sqlx::query_as(
+      r#"
+      SELECT e.chat_id, c.name, e.retention_days, count(*) AS expired_messages
+      FROM expired_messages e JOIN chats c ON c.id = e.chat_id
+      WHERE e.ws_id = $1
+      GROUP BY e.chat_id, c.name, e.retention_days
+      ORDER BY e.chat_id
+      "#,
+    )
+    .bind(ws_id as i64)
+    .fetch_all(&self.pool)
+    .await?;
+
+    let orphan_files = self
+      .find_orphan_files(ws_id)
+      .await?
+      .into_iter()
+      .map(|(url, _)| url)
+      .collect();
+
+    Ok(RetentionReport {
+      ws_id: ws_id as _,
+      retention_days,
+      chats,
+      orphan_files,
+    })
+  }
+
+  /// Deletes expired messages in batches, returns how many were deleted.
+  pub async fn delete_expired_messages(&self) -> Result<u64, AppError> {
+    let mut deleted = 0;
+    loop {
+      let ret = sqlx::query(
+        r#"
+        DELETE FROM messages
+        WHERE id IN (SELECT id FROM expired_messages LIMIT $1)
+        "#,
+      )
+      .bind(RETENTION_BATCH_SIZE)
+      .execute(&self.pool)
+      .await?;
+      deleted += ret.rows_affected();
+      if ret.rows_affected() < RETENTION_BATCH_SIZE as u64 {
+        return Ok(deleted);
+      }
+    }
+  }
+
+  /// Removes the files of a workspace nothing references any more, returns how many.
+  pub async fn remove_orphan_files(&self, ws_id: u64) -> Result<usize, AppError> {
+    let orphans = self.find_orphan_files(ws_id).await?;
+    Ok(remove_files(orphans).await)
+  }
+
+  /// Files under the workspace's directory that no message, pending scheduled message,
+  /// avatar or chat icon refers to, with their paths.
+  async fn find_orphan_files(&self, ws_id: u64) -> Result<Vec<(String, PathBuf)>, AppError> {
+    let base_dir = &self.config.server.base_dir;
+    let deadline = SystemTime::now() - ORPHAN_FILE_GRACE;
+    let mut files = Vec::new();
+    for (path, modified) in list_files(&base_dir.join(ws_id.to_string())).await? {
+      if modified > deadline {
+        continue;
+      }
+      let Some(url) = file_url(base_dir, &path) else {
+        continue;
+      };
+      files.push((url, path));
+    }
+    if files.is_empty() {
+      return Ok(files);
+    }
+
+    let urls: Vec<&str> = files.iter().map(|(url, _)| url.as_str()).collect();
+    let orphans = self.unreferenced_files(&urls).await?;
+    files.retain(|(url, _)| orphans.contains(url));
+    Ok(files)
+  }
+
+  /// The urls no message, pending scheduled message, avatar or chat icon refers to.
+  pub(crate) async fn unreferenced_files(&self, urls: &[&str]) -> Result<Vec<String>, AppError> {
+    let orphans: Vec<(String,)> = sqlx::query_as(
+      r#"
+      SELECT unnest($1::text[])
+      EXCEPT SELECT unnest(files) FROM messages WHERE files && $1::text[]
+      EXCEPT SELECT unnest(files) FROM scheduled_messages
+        WHERE status = 'pending' AND files && $1::text[]
+      EXCEPT SELECT avatar_url FROM users WHERE avatar_url IS NOT NULL
+      EXCEPT SELECT icon FROM chats WHERE icon IS NOT NULL
+      "#,
+    )
+    .bind(urls)
+    .fetch_all(&self.pool)
+    .await?;
+
+    Ok(orphans.into_iter().map(|(url,)| url).collect())
+  }
+
+  async fn check_retention_admin(&self, ws_id: u64, user_id: u64) -> Result<(), AppError> {
+    if !self.is_workspace_admin(ws_id, user_id).await? {
+      return Err(AppError::PermissionDenied(
+        "Only workspace admins can manage retention".to_string(),
+      ));
+    }
+    Ok(())
+  }
+}
+
+/// Runs the retention job in the background.
+pub(crate) fn spawn_retention_job(state: AppState) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+    loop {
+      interval.tick().await;
+      if let Err(e) = enforce_retention(&state).await {
+        warn!("Failed to enforce retention: {}", e);
+      }
+    }
+  });
+}
+
+async fn enforce_retention(state: &AppState) -> Result<(), AppError> {
+  let deleted = state.delete_expired_messages().await?;
+  let ws_ids: Vec<(i64,)> = sqlx::query_as("SELECT id FROM workspaces")
+    .fetch_all(&state.pool)
+    .await?;
+  let mut removed = 0;
+  for (ws_id,) in ws_ids {
+    removed += state.remove_orphan_files(ws_id as _).await?;
+  }
+  state.delete_expired_nonces().await?;
+  if deleted > 0 || removed > 0 {
+    info!(
+      "Retention deleted {} messages and {} orphan files",
+      deleted, removed
+    );
+  }
+  Ok(())
+}
+
+/// Removes the files with their urls, returns how many. Files already gone don't count.
+async fn remove_files(files: Vec<(String, PathBuf)>) -> usize {
+  let mut removed = 0;
+  for (url, path) in files {
+    match fs::remove_file(&path).await {
+      Ok(()) => removed += 1,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+      Err(e) => warn!("Failed to remove file {}: {}", url, e),
+    }
+  }
+  removed
+}
+
+fn verify_retention_days(input: &UpdateRetention) -> Result<(), AppError> {
+  if input.retention_days.is_some_and(|days| days <= 0) {
+    return Err(AppError::RetentionError(
+      "retention_days must be positive".to_string(),
+    ));
+  }
+  if input.keep_forever && input.retention_days.is_some() {
+    return Err(AppError::RetentionError(
+      "retention_days can't be set with keep_forever".to_string(),
+    ));
+  }
+  Ok(())
+}
+
+/// Regular files under a directory with their modification times, none if it doesn't exist.
+async fn list_files(dir: &Path) -> io::Result<Vec<(PathBuf, SystemTime)>> {
+  let mut files = Vec::new();
+  let mut dirs = vec![dir.to_path_buf()];
+  while let Some(dir) = dirs.pop() {
+    let mut entries = match fs::read_dir(&dir).await {
+      Ok(entries) => entries,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+      Err(e) => return Err(e),
+    };
+    while let Some(entry) = entries.next_entry().await? {
+      let metadata = entry.metadata().await?;
+      if metadata.is_dir() {
+        dirs.push(entry.path());
+      } else if metadata.is_file() {
+        files.push((entry.path(), metadata.modified()?));
+      }
+    }
+  }
+  Ok(files)
+}
+
+/// The url a file under `base_dir` is served at, if it is a chat file.
+fn file_url(base_dir: &Path, path: &Path) -> Option<String> {
+  let relative = path.strip_prefix(base_dir).ok()?;
+  let parts: Vec<_> = relative.iter().map(|p| p.to_str()).collect::<Option<_>>()?;
+  let url = format!("/files/{}", parts.join("/"));
+  url.parse::<ChatFile>().ok()?;
+  Some(url)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::CreateMessage;
+  use anyhow::Result;
+
+  #[tokio::test]
+  async fn retention_should_delete_expired_messages() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let days = |days| UpdateRetention {
+      retention_days: Some(days),
+      ..Default::default()
+    };
+    let err = state
+      .set_workspace_retention(1, 1, days(30))
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::PermissionDenied(_)));
+    sqlx::query("UPDATE users SET is_admin = TRUE WHERE id = 1")
+      .execute(&state.pool)
+      .await?;
+    let err = state
+      .set_workspace_retention(1, 1, days(0))
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::RetentionError(_)));
+
+    // the first five messages are 40 days old, message 2 has a fresh reply
+    sqlx::query("UPDATE messages SET created_at = now() - interval '40 days' WHERE id <= 5")
+      .execute(&state.pool)
+      .await?;
+    let input = CreateMessage {
+      content: "still relevant".to_string(),
+      parent_id: Some(2),
+      ..Default::default()
+    };
+    state.create_message(input, 1, 3).await?;
+    let policy = state.set_workspace_retention(1, 1, days(30)).await?;
+    assert_eq!((policy.chat_id, policy.retention_days), (None, Some(30)));
+    // a longer chat setting wins
+    let policy = state.set_chat_retention(1, 1, days(60)).await?;
+    assert_eq!(policy.chat_id, Some(1));
+    assert!(state.retention_report(1, 1).await?.chats.is_empty());
+    state
+      .set_chat_retention(1, 1, UpdateRetention::default())
+      .await?;
+
+    let report = state.retention_report(1, 1).await?;
+    assert_eq!(report.retention_days, Some(30));
+    let expired: Vec<_> = report
+      .chats
+      .iter()
+      .map(|c| (c.chat_id, c.retention_days, c.expired_messages))
+      .collect();
+    assert_eq!(expired, [(1, 30, 4)]);
+    // the report is a dry run
+    assert_eq!(state.retention_report(1, 1).await?, report);
+
+    // a chat can keep its messages whatever the workspace setting
+    let keep = UpdateRetention {
+      keep_forever: true,
+      ..Default::default()
+    };
+    let err = state
+      .set_workspace_retention(1, 1, keep.clone())
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::RetentionError(_)));
+    let policy = state.set_chat_retention(1, 1, keep).await?;
+    assert!(policy.keep_forever);
+    assert!(state.retention_report(1, 1).await?.chats.is_empty());
+    state
+      .set_chat_retention(1, 1, UpdateRetention::default())
+      .await?;
+
+    assert_eq!(state.delete_expired_messages().await?, 4);
+    assert!(state.retention_report(1, 1).await?.chats.is_empty());
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn orphan_files_should_be_removed_after_grace() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let ws = state.create_workspace("retention", 1).await?;
+    let base_dir = &state.config.server.base_dir;
+    let write = |name: &str, age: Duration| -> Result<String> {
+      let file = ChatFile::new(ws.id as _, name, name.as_bytes());
+      let path = file.path(base_dir);
+      std::fs::create_dir_all(path.parent().expect("file should have a parent"))?;
+      std::fs::write(&path, name)?;
+      std::fs::File::options()
+        .write(true)
+        .open(&path)?
+        .set_modified(SystemTime::now() - age)?;
+      Ok(file.url())
+    };
+    let day = Duration::from_secs(24 * 60 * 60);
+    let orphan = write("orphan.txt", 2 * day)?;
+    let avatar = write("avatar.png", 2 * day)?;
+    let fresh = write("fresh.txt", Duration::ZERO)?;
+    sqlx::query("UPDATE users SET avatar_url = $1 WHERE id = 1")
+      .bind(&avatar)
+      .execute(&state.pool)
+      .await?;
+
+    let orphans = state.find_orphan_files(ws.id as _).await?;
+    let urls: Vec<_> = orphans.iter().map(|(url, _)| url.as_str()).collect();
+    assert_eq!(urls, [orphan.as_str()]);
+    assert_eq!(state.remove_orphan_files(ws.id as _).await?, 1);
+    for (url, exists) in [(orphan, false), (avatar, true), (fresh, true)] {
+      let file: ChatFile = url.parse()?;
+      assert_eq!(file.path(base_dir).exists(), exists);
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,378 @@
+use super::message::verify_content;
+use crate::{insert_message, AppError, AppState, CreateMessage};
+use chat_core::{Message, ScheduledMessage};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Acquire, PgConnection};
+use std::time::Duration;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleMessage {
+  #[serde(flatten)]
+  pub message: CreateMessage,
+  pub send_at: DateTime<Utc>,
+}
+
+/// Fields left out are unchanged.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateScheduledMessage {
+  pub content: Option<String>,
+  pub send_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListScheduledMessages {
+  /// only list the messages scheduled in this chat
+  pub chat_id: Option<u64>,
+}
+
+const DISPATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+impl AppState {
+  pub async fn schedule_message(
+    &self,
+    input: ScheduleMessage,
+    chat_id: u64,
+    user_id: u64,
+  ) -> Result<ScheduledMessage, AppError> {
+    verify_send_at(input.send_at)?;
+    // dispatch sends each scheduled row once, a client nonce would have nothing to dedupe
+    if input.message.nonce.is_some() {
+      return Err(AppError::CreateMessageError(
+        "scheduled messages can't have a nonce".to_string(),
+      ));
+    }
+    self.verify_new_message(&input.message, chat_id).await?;
+
+    let scheduled = sqlx::query_as(
+      r#"
+      INSERT INTO scheduled_messages (chat_id, sender_id, content, files, parent_id,
+        also_in_chat, send_at)
+      VALUES ($1, $2, $3, $4, $5, $6, $7)
+      RETURNING id, chat_id, sender_id, content, files, parent_id, also_in_chat, send_at,
+        status, message_id, error, created_at
+      "#,
+    )
+    .bind(chat_id as i64)
+    .bind(user_id as i64)
+    .bind(input.message.content)
+    .bind(&input.message.files)
+    .bind(input.message.parent_id.map(|id| id as i64))
+    .bind(input.message.also_in_chat)
+    .bind(input.send_at)
+    .fetch_one(&self.pool)
+    .await?;
+
+    Ok(scheduled)
+  }
+
+  /// The user's pending scheduled messages, the next one to be sent first.
+  pub async fn list_scheduled_messages(
+    &self,
+    user_id: u64,
+    input: ListScheduledMessages,
+  ) -> Result<Vec<ScheduledMessage>, AppError> {
+    let scheduled = sqlx::query_as(
+      r#"
+      SELECT id, chat_id, sender_id, content, files, parent_id, also_in_chat, send_at,
+        status, message_id, error, created_at
+      FROM scheduled_messages
+      WHERE sender_id = $1 AND status = 'pending'
+      AND ($2::bigint IS NULL OR chat_id = $2)
+      ORDER BY send_at, id
+      "#,
+    )
+    .bind(user_id as i64)
+    .bind(input.chat_id.map(|id| id as i64))
+    .fetch_all(&self.pool)
+    .await?;
+
+    Ok(scheduled)
+  }
+
+  /// Edits a pending scheduled message of the user.
+  pub async fn update_scheduled_message(
+    &self,
+    id: u64,
+    user_id: u64,
+    input: UpdateScheduledMessage,
+  ) -> Result<ScheduledMessage, AppError> {
+    if let Some(content) = &input.content {
+      verify_content(content).map_err(AppError::UpdateMessageError)?;
+    }
+    if let Some(send_at) = input.send_at {
+      verify_send_at(send_at)?;
+    }
+
+    let scheduled = sqlx::query_as(
+      r#"
+      UPDATE scheduled_messages
+      SET content = coalesce($3, content), send_at = coalesce($4, send_at)
+      WHERE id = $1 AND sender_id = $2 AND status = 'pending'
+      RETURNING id, chat_id, sender_id, content, files, parent_id, also_in_chat, send_at,
+        status, message_id, error, created_at
+      "#,
+    )
+    .bind(id as i64)
+    .bind(user_id as i64)
+    .bind(input.content)
+    .bind(input.send_at)
+    .fetch_optional(&self.pool)
+    .await?;
+
+    scheduled.ok_or_else(|| AppError::NotFound(format!("Scheduled message {} not found", id)))
+  }
+
+  pub async fn cancel_scheduled_message(&self, id: u64, user_id: u64) -> Result<(), AppError> {
+    let ret = sqlx::query(
+      r#"
+      DELETE FROM scheduled_messages
+      WHERE id = $1 AND sender_id = $2 AND status = 'pending'
+      "#,
+    )
+    .bind(id as i64)
+    .bind(user_id as i64)
+    .execute(&self.pool)
+    .await?;
+
+    if ret.rows_affected() == 0 {
+      return Err(AppError::NotFound(format!(
+        "Scheduled message {} not found",
+        id
+      )));
+    }
+    Ok(())
+  }
+
+  /// Sends the scheduled messages that are due, returns how many were handled.
+  ///
+  /// Each message is locked while it is sent, so several servers can dispatch at once, and is
+  /// sent in the same transaction that marks it, so a crash in between can't send it twice.
+  /// Messages that can't be sent, e.g. because the sender left the chat, are marked as failed
+  /// with the error.
+  pub async fn dispatch_scheduled_messages(&self) -> Result<usize, AppError> {
+    let mut count = 0;
+    loop {
+      let mut tx = self.pool.begin().await?;
+      let scheduled: Option<ScheduledMessage> = sqlx::query_as(
+        r#"
+        SELECT id, chat_id, sender_id, content, files, parent_id, also_in_chat, send_at,
+          status, message_id, error, created_at
+        FROM scheduled_messages
+        WHERE status = 'pending' AND send_at <= now()
+        ORDER BY send_at, id
+        LIMIT 1
+        FOR UPDATE SKIP LOCKED
+        "#,
+      )
+      .fetch_optional(&mut *tx)
+      .await?;
+      let Some(scheduled) = scheduled else {
+        return Ok(count);
+      };
+
+      // a failed send only rolls back to the savepoint, the row is still marked
+      let mut sp = tx.begin().await?;
+      let (message, error) = match self.send_scheduled_message(&mut sp, &scheduled).await {
+        Ok(message) => {
+          sp.commit().await?;
+          (Some(message), None)
+        }
+        Err(e) => {
+          sp.rollback().await?;
+          warn!("Failed to send scheduled message {}: {}", scheduled.id, e);
+          (None, Some(e.to_string()))
+        }
+      };
+      sqlx::query(
+        r#"
+        UPDATE scheduled_messages
+        SET status = CASE WHEN $2::bigint IS NULL THEN 'failed' ELSE 'sent' END
+          ::scheduled_message_status,
+          message_id = $2, error = $3
+        WHERE id = $1
+        "#,
+      )
+      .bind(scheduled.id)
+      .bind(message.as_ref().map(|m| m.id))
+      .bind(error)
+      .execute(&mut *tx)
+      .await?;
+      tx.commit().await?;
+      if let Some(message) = &message {
+        self.spawn_unfurl(message);
+      }
+      count += 1;
+    }
+  }
+
+  async fn send_scheduled_message(
+    &self,
+    conn: &mut PgConnection,
+    scheduled: &ScheduledMessage,
+  ) -> Result<Message, AppError> {
+    let chat_id = scheduled.chat_id as u64;
+    let sender_id = scheduled.sender_id as u64;
+    if !self.is_chat_member(chat_id, sender_id).await? {
+      return Err(AppError::PermissionDenied(format!(
+        "User {} is no longer a member of chat {}",
+        sender_id, chat_id
+      )));
+    }
+    let input = CreateMessage {
+      content: scheduled.content.clone(),
+      files: scheduled.files.clone(),
+      parent_id: scheduled.parent_id.map(|id| id as u64),
+      also_in_chat: scheduled.also_in_chat,
+      nonce: None,
+    };
+    self.verify_new_message(&input, chat_id).await?;
+    insert_message(conn, &input, chat_id, sender_id).await
+  }
+}
+
+/// Runs the scheduled message dispatcher in the background.
+pub(crate) fn spawn_scheduled_dispatcher(state: AppState) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(DISPATCH_INTERVAL);
+    loop {
+      interval.tick().await;
+      match state.dispatch_scheduled_messages().await {
+        Ok(0) => {}
+        Ok(count) => info!("Dispatched {} scheduled messages", count),
+        Err(e) => warn!("Failed to dispatch scheduled messages: {}", e),
+      }
+    }
+  });
+}
+
+fn verify_send_at(send_at: DateTime<Utc>) -> Result<(), AppError> {
+  if send_at <= Utc::now() {
+    return Err(AppError::CreateMessageError(
+      "send_at must be in the future".to_string(),
+    ));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use anyhow::Result;
+  use chat_core::ScheduledMessageStatus;
+
+  #[tokio::test]
+  async fn scheduled_messages_should_be_dispatched_when_due() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let schedule = |content: &str| ScheduleMessage {
+      message: CreateMessage {
+        content: content.to_string(),
+        ..Default::default()
+      },
+      send_at: Utc::now() + chrono::Duration::hours(1),
+    };
+    let later = state.schedule_message(schedule("later"), 1, 2).await?;
+    let soon = state.schedule_message(schedule("soon"), 1, 2).await?;
+    let left = state.schedule_message(schedule("left"), 1, 3).await?;
+    let mut past = schedule("past");
+    past.send_at = Utc::now();
+    let err = state.schedule_message(past, 1, 2).await.unwrap_err();
+    assert!(matches!(err, AppError::CreateMessageError(_)));
+
+    // nothing is due yet
+    assert_eq!(state.dispatch_scheduled_messages().await?, 0);
+    let input = UpdateScheduledMessage {
+      content: Some("soon, edited".to_string()),
+      ..Default::default()
+    };
+    state
+      .update_scheduled_message(soon.id as _, 2, input)
+      .await?;
+    // only the sender can see and change them
+    let err = state
+      .cancel_scheduled_message(later.id as _, 3)
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    state.cancel_scheduled_message(later.id as _, 2).await?;
+
+    sqlx::query("UPDATE scheduled_messages SET send_at = now() - interval '1 second'")
+      .execute(&state.pool)
+      .await?;
+    state.leave_chat(1, 3).await?;
+    assert_eq!(state.dispatch_scheduled_messages().await?, 2);
+
+    assert!(state
+      .list_scheduled_messages(2, ListScheduledMessages::default())
+      .await?
+      .is_empty());
+    let statuses: Vec<(i64, ScheduledMessageStatus, Option<i64>)> =
+      sqlx::query_as("SELECT id, status, message_id FROM scheduled_messages ORDER BY id")
+        .fetch_all(&state.pool)
+        .await?;
+    assert_eq!(statuses[0].0, soon.id);
+    assert_eq!(statuses[0].1, ScheduledMessageStatus::Sent);
+    assert_eq!(
+      (statuses[1].0, statuses[1].1),
+      (left.id, ScheduledMessageStatus::Failed)
+    );
+    let message_id = statuses[0].2.expect("message should be sent");
+    let message = state.get_live_message(1, message_id as _).await?;
+    assert_eq!(
+      (message.content.as_str(), message.sender_id),
+      ("soon, edited", 2)
+    );
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn concurrent_dispatches_should_send_once() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let message = CreateMessage {
+      content: "once".to_string(),
+      ..Default::default()
+    };
+    let input = ScheduleMessage {
+      message: message.clone(),
+      send_at: Utc::now() + chrono::Duration::hours(1),
+    };
+    let scheduled = state.schedule_message(input, 1, 2).await?;
+    // client nonces aren't taken, the scheduled message itself is sent once
+    let input = ScheduleMessage {
+      message: CreateMessage {
+        nonce: Some("c0ffee".to_string()),
+        ..message
+      },
+      send_at: Utc::now() + chrono::Duration::hours(1),
+    };
+    let err = state.schedule_message(input, 1, 2).await.unwrap_err();
+    assert!(matches!(err, AppError::CreateMessageError(_)));
+
+    sqlx::query("UPDATE scheduled_messages SET send_at = now() - interval '1 second'")
+      .execute(&state.pool)
+      .await?;
+    let (a, b) = tokio::join!(
+      state.dispatch_scheduled_messages(),
+      state.dispatch_scheduled_messages()
+    );
+    assert_eq!(a? + b?, 1);
+    assert_eq!(state.dispatch_scheduled_messages().await?, 0);
+
+    let message_id: Option<i64> =
+      sqlx::query_scalar("SELECT message_id FROM scheduled_messages WHERE id = $1")
+        .bind(scheduled.id)
+        .fetch_one(&state.pool)
+        .await?;
+    let message = state
+      .get_live_message(1, message_id.expect("message should be sent") as _)
+      .await?;
+    assert_eq!(message.content, "once");
+    assert_eq!(message.nonce, None);
+    let count: i64 = sqlx::query_scalar("SELECT count(*) FROM messages WHERE content = 'once'")
+      .fetch_one(&state.pool)
+      .await?;
+    assert_eq!(count, 1);
+    Ok(())
+  }
+}
@@ -0,0 +1,211 @@
+use crate::{AppError, AppState};
+use chat_core::SearchHit;
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchMessages {
+  /// words to match, plus `from:@user`, `in:#channel`, `has:file`, `before:` and `after:`
+  /// filters, dates are `YYYY-MM-DD`
+  pub q: String,
+  pub offset: Option<u64>,
+  pub limit: Option<u64>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct SearchQuery {
+  text: String,
+  /// lowercased handle, matched against display names and email local parts
+  from: Option<String>,
+  /// lowercased channel slug or name
+  chat: Option<String>,
+  has_file: bool,
+  before: Option<DateTime<Utc>>,
+  after: Option<DateTime<Utc>>,
+}
+
+const DEFAULT_SEARCH_PAGE_SIZE: u64 = 20;
+const MAX_SEARCH_PAGE_SIZE: u64 = 100;
+const SNIPPET_OPTIONS: &str = "StartSel=<mark>, StopSel=</mark>, MaxWords=30, MinWords=10";
+
+impl AppState {
+  /// Searches the messages of the chats the user is a member of, best matches first.
+  pub async fn search_messages(
+    &self,
+    ws_id: u64,
+    user_id: u64,
+    input: SearchMessages,
+  ) -> Result<Vec<SearchHit>, AppError> {
+    let query = SearchQuery::parse(&input.q)?;
+    let limit = input
+      .limit
+      .unwrap_or(DEFAULT_SEARCH_PAGE_SIZE)
+      .clamp(1, MAX_SEARCH_PAGE_SIZE);
+
+    // content is escaped before highlighting so the snippet is safe to render as HTML
+    let hits = sqlx::query_as(
+      r#"
+      SELECT m.id, m.chat_id, m.sender_id, m.content, m.files, m.parent_id, m.also_in_chat,
+        m.reply_count, m.last_reply_at, m.edited_at, m.deleted_at, m.rich_text, m.link_previews, m.created_at,
+        coalesce(ts_rank(to_tsvector('english', m.content), s.q), 0) AS rank,
+        coalesce(ts_headline('english', e.content, s.q, $11), left(e.content, 200)) AS snippet
+      FROM messages m
+      JOIN chats c ON c.id = m.chat_id
+      JOIN chat_members me ON me.chat_id = m.chat_id AND me.user_id = $2
+      LEFT JOIN (SELECT websearch_to_tsquery('english', $3) AS q WHERE $3 <> '') s ON TRUE
+      CROSS JOIN LATERAL (
+        SELECT replace(replace(replace(m.content, '&', '&amp;'), '<', '&lt;'), '>', '&gt;')
+          AS content
+      ) e
+      WHERE c.ws_id = $1 AND m.deleted_at IS NULL
+      AND (s.q IS NULL OR to_tsvector('english', m.content) @@ s.q)
+      AND ($4::text IS NULL OR m.sender_id IN (
+        SELECT id FROM users
+        WHERE ws_id = $1
+        AND (lower(split_part(email, '@', 1)) = $4 OR lower(display_name) = $4)
+      ))
+      AND ($5::text IS NULL OR c.slug = $5 OR lower(c.name) = $5)
+      AND (NOT $6 OR cardinality(m.files) > 0)
+      AND ($7::timestamptz IS NULL OR m.created_at < $7)
+      AND ($8::timestamptz IS NULL OR m.created_at >= $8)
+      ORDER BY rank DESC, m.id DESC
+      OFFSET $9
+      LIMIT $10
+      "#,
+    )
+    .bind(ws_id as i64)
+    .bind(user_id as i64)
+    .bind(&query.text)
+    .bind(query.from)
+    .bind(query.chat)
+    .bind(query.has_file)
+    .bind(query.before)
+    .bind(query.after)
+    .bind(input.offset.unwrap_or(0) as i64)
+    .bind(limit as i64)
+    .bind(SNIPPET_OPTIONS)
+    .fetch_all(&self.pool)
+    .await?;
+
+    Ok(hits)
+  }
+}
+
+impl SearchQuery {
+  fn parse(q: &str) -> Result<Self, AppError> {
+    let mut query = Self::default();
+    let mut words = Vec::new();
+    for token in q.split_whitespace() {
+      let Some((key, value)) = token.split_once(':') else {
+        words.push(token);
+        continue;
+      };
+      let invalid = || AppError::InvalidSearch(format!("invalid filter: {}", token));
+      match key {
+        "from" => query.from = Some(filter_value(value, '@').ok_or_else(invalid)?),
+        "in" => query.chat = Some(filter_value(value, '#').ok_or_else(invalid)?),
+        "has" if value == "file" => query.has_file = true,
+        "has" => return Err(invalid()),
+        // before and after exclude the given day itself
+        "before" => query.before = Some(parse_day(value, 0).ok_or_else(invalid)?),
+        "after" => query.after = Some(parse_day(value, 1).ok_or_else(invalid)?),
+        // anything else, like a url, is searched as text
+        _ => words.push(token),
+      }
+    }
+    query.text = words.join(" ");
+    Ok(query)
+  }
+}
+
+fn filter_value(value: &str, sigil: char) -> Option<String> {
+  let value = value.strip_prefix(sigil).unwrap_or(value).to_lowercase();
+  (!value.is_empty()).then_some(value)
+}
+
+/// Start of the day, `days` after the given date, in UTC.
+fn parse_day(value: &str, days: u64) -> Option<DateTime<Utc>> {
+  let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+  Some(
+    date
+      .checked_add_days(Days::new(days))?
+      .and_hms_opt(0, 0, 0)?
+      .and_utc(),
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::CreateMessage;
+  use anyhow::Result;
+
+  #[test]
+  fn search_query_should_parse_filters() -> Result<()> {
+    let query = SearchQuery::parse("deploy from:@Alice in:#general has:file http://x.org")?;
+    assert_eq!(query.text, "deploy http://x.org");
+    assert_eq!(query.from.as_deref(), Some("alice"));
+    assert_eq!(query.chat.as_deref(), Some("general"));
+    assert!(query.has_file);
+
+    let query = SearchQuery::parse("before:2024-07-02 after:2024-07-01")?;
+    assert_eq!(query.before, parse_day("2024-07-02", 0));
+    assert_eq!(query.after, parse_day("2024-07-02", 0));
+    assert!(query.text.is_empty());
+
+    for q in ["from:", "has:link", "before:yesterday", "in:#"] {
+      let err = SearchQuery::parse(q).unwrap_err();
+      assert!(matches!(err, AppError::InvalidSearch(_)), "{}", q);
+    }
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn search_messages_should_rank_and_filter() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let send = |content: &str, chat_id, user_id| {
+      let input = CreateMessage {
+        content: content.to_string(),
+        ..Default::default()
+      };
+      state.create_message(input, chat_id, user_id)
+    };
+    let first = send("the deploy is done", 1, 2).await?;
+    let second = send("deploying <b>again</b>, deploy failed", 1, 1).await?;
+    // chat 2 is private, daisy isn't a member
+    let private = send("deploy notes", 2, 1).await?;
+
+    let search = |user_id, q: &str| {
+      let input = SearchMessages {
+        q: q.to_string(),
+        ..Default::default()
+      };
+      state.search_messages(1, user_id, input)
+    };
+    let hits = search(5, "deploy").await?;
+    let ids: Vec<_> = hits.iter().map(|h| h.message.id).collect();
+    assert_eq!(ids, [second.id, first.id]);
+    assert!(hits[0].rank > hits[1].rank);
+    assert_eq!(
+      hits[0].snippet,
+      "<mark>deploying</mark> &lt;b&gt;again&lt;/b&gt;, <mark>deploy</mark> failed"
+    );
+
+    let hits = search(1, "deploy from:@alice").await?;
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].message.id, first.id);
+    let hits = search(1, "deploy in:#private").await?;
+    assert_eq!(hits[0].message.id, private.id);
+    assert!(search(1, "deploy has:file").await?.is_empty());
+    assert!(search(1, "deploy before:2000-01-01").await?.is_empty());
+
+    let input = SearchMessages {
+      q: "deploy".to_string(),
+      offset: Some(2),
+      limit: Some(2),
+    };
+    let hits = state.search_messages(1, 1, input).await?;
+    assert_eq!(hits.len(), 1);
+    Ok(())
+  }
+}
@@ -1,11 +1,12 @@
-use crate::{AppError, AppState};
+use crate::{AppError, AppState, ChatFile};
 use argon2::{
   password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
   Argon2,
 };
-use chat_core::{ChatUser, User};
-use serde::{Deserialize, Serialize};
-use std::mem;
+use chat_core::{ChatType, ChatUser, User, UserPage};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::{mem, str::FromStr};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CreateUser {
@@ -21,6 +22,56 @@ pub struct SigninUser {
   pub password: String,
 }
 
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ListUsers {
+  /// prefix or fuzzy match against fullname and email
+  pub q: Option<String>,
+  /// `next_cursor` of the previous page
+  pub cursor: Option<String>,
+  pub limit: Option<u64>,
+  pub active: Option<bool>,
+  pub bot: Option<bool>,
+  /// only return members of this chat
+  pub chat_id: Option<u64>,
+}
+
+/// Fields left out are unchanged, empty strings clear the field.
+/// Setting the status text or emoji also replaces the status expiry unless one is given,
+/// `status_expires_at: null` clears the expiry of the current status.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct UpdateProfile {
+  pub fullname: Option<String>,
+  pub display_name: Option<String>,
+  pub title: Option<String>,
+  pub timezone: Option<String>,
+  pub avatar_url: Option<String>,
+  pub status_text: Option<String>,
+  pub status_emoji: Option<String>,
+  #[serde(
+    default,
+    deserialize_with = "deserialize_present",
+    skip_serializing_if = "Option::is_none"
+  )]
+  pub status_expires_at: Option<Option<DateTime<Utc>>>,
+  pub pronouns: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChangePassword {
+  pub old_password: String,
+  pub new_password: String,
+}
+
+const DEFAULT_USER_PAGE_SIZE: u64 = 50;
+const MAX_USER_PAGE_SIZE: u64 = 200;
+
+#[derive(sqlx::FromRow)]
+struct RankedUser {
+  #[sqlx(flatten)]
+  user: ChatUser,
+  rank: f32,
+}
+
 #[allow(dead_code)]
 impl AppState {
   pub async fn find_user_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
@@ -115,8 +166,9 @@ impl AppState {
   pub async fn fetch_chat_user_by_ids(&self, ids: &[i64]) -> Result<Vec<ChatUser>, AppError> {
     let users = sqlx::query_as(
       r#"
-      SELECT id, fullname, email
-      FROM users
+      SELECT id, fullname, email, is_bot, is_active, display_name, title, timezone,
+        avatar_url, status_text, status_emoji, status_expires_at, pronouns
+      FROM chat_users
       WHERE id = ANY($1)
       "#,
     )
@@ -127,20 +179,305 @@ impl AppState {
     Ok(users)
   }
 
-  pub async fn fetch_chat_users(&self, ws_id: u64) -> Result<Vec<ChatUser>, AppError> {
-    let users = sqlx::query_as(
+  /// Users of the workspace, search results are ordered by how well they match.
+  pub async fn fetch_chat_users(
+    &self,
+    ws_id: u64,
+    user_id: u64,
+    input: ListUsers,
+  ) -> Result<UserPage, AppError> {
+    if let Some(chat_id) = input.chat_id {
+      // members of private channels and direct chats are only visible to other members
+      let visible = match self.get_chat_by_id(chat_id).await? {
+        Some(chat) if chat.ws_id == ws_id as i64 => {
+          chat.r#type == ChatType::PublicChannel || self.is_chat_member(chat_id, user_id).await?
+        }
+        _ => false,
+      };
+      if !visible {
+        return Err(AppError::NotFound(format!("Chat {} not found", chat_id)));
+      }
+    }
+    let cursor = input.cursor.as_deref().map(parse_user_cursor).transpose()?;
+    let limit = input
+      .limit
+      .unwrap_or(DEFAULT_USER_PAGE_SIZE)
+      .clamp(1, MAX_USER_PAGE_SIZE) as usize;
+    let q = input
+      .q
+      .map(|q| q.trim().to_string())
+      .filter(|q| !q.is_empty());
+    let prefix = q.as_deref().map(|q| format!("{}%", escape_like(q)));
+
+    // fuzzy match uses word similarity so that "chen" matches "Alice Chen", prefix matches
+    // rank first. The cursor carries the rank and id of the last user of the previous page,
+    // so it holds even when that user no longer matches. Pages fetch one extra user to tell
+    // if there are more.
+    let mut users: Vec<RankedUser> = sqlx::query_as(
       r#"
-      SELECT id, fullname, email
-      FROM users
-      WHERE ws_id = $1
+      WITH ranked AS (
+        SELECT id, fullname, email, is_bot, is_active, display_name, title, timezone,
+          avatar_url, status_text, status_emoji, status_expires_at, pronouns,
+          CASE
+            WHEN $3::text IS NULL THEN 0
+            WHEN fullname ILIKE $4 OR email ILIKE $4 THEN 1
+            ELSE greatest(word_similarity($3, fullname), word_similarity($3, email))
+          END AS rank
+        FROM chat_users
+        WHERE ws_id = $1
+        AND ($3::text IS NULL
+          OR fullname ILIKE $4 OR email ILIKE $4
+          OR $3 <% fullname OR $3 <% email)
+        AND ($5::boolean IS NULL OR is_active = $5)
+        AND ($6::boolean IS NULL OR is_bot = $6)
+        AND ($7::bigint IS NULL OR id IN (
+          SELECT user_id FROM chat_members WHERE chat_id = $7
+        ))
+      )
+      SELECT r.id, r.fullname, r.email, r.is_bot, r.is_active, r.display_name, r.title,
+        r.timezone, r.avatar_url, r.status_text, r.status_emoji, r.status_expires_at,
+        r.pronouns, r.rank::real AS rank
+      FROM ranked r
+      WHERE $2::real IS NULL OR r.rank < $2 OR (r.rank = $2 AND r.id > $9)
+      ORDER BY r.rank DESC, r.id
+      LIMIT $8
       "#,
     )
     .bind(ws_id as i64)
+    .bind(cursor.map(|(rank, _)| rank))
+    .bind(q)
+    .bind(prefix)
+    .bind(input.active)
+    .bind(input.bot)
+    .bind(input.chat_id.map(|id| id as i64))
+    .bind(limit as i64 + 1)
+    .bind(cursor.map(|(_, id)| id))
     .fetch_all(&self.pool)
     .await?;
 
-    Ok(users)
+    let next_cursor = if users.len() > limit {
+      users.truncate(limit);
+      users.last().map(|u| format!("{}:{}", u.rank, u.user.id))
+    } else {
+      None
+    };
+    Ok(UserPage {
+      users: users.into_iter().map(|u| u.user).collect(),
+      next_cursor,
+    })
   }
+
+  pub async fn get_chat_user_by_id(&self, id: u64) -> Result<Option<ChatUser>, AppError> {
+    let user = sqlx::query_as(
+      r#"
+      SELECT id, fullname, email, is_bot, is_active, display_name, title, timezone,
+        avatar_url, status_text, status_emoji, status_expires_at, pronouns
+      FROM chat_users
+      WHERE id = $1
+      "#,
+    )
+    .bind(id as i64)
+    .fetch_optional(&self.pool)
+    .await?;
+
+    Ok(user)
+  }
+
+  pub async fn update_profile(
+    &self,
+    user_id: u64,
+    ws_id: u64,
+    input: UpdateProfile,
+  ) -> Result<ChatUser, AppError> {
+    let Some(mut user) = self.get_chat_user_by_id(user_id).await? else {
+      return Err(AppError::NotFound(format!("User: {} not found", user_id)));
+    };
+
+    let limits = [
+      ("fullname", &input.fullname, 64),
+      ("display_name", &input.display_name, 64),
+      ("title", &input.title, 128),
+      ("timezone", &input.timezone, 64),
+      ("avatar_url", &input.avatar_url, 256),
+      ("status_text", &input.status_text, 128),
+      ("status_emoji", &input.status_emoji, 32),
+      ("pronouns", &input.pronouns, 32),
+    ];
+    for (name, value, max) in limits {
+      if value.as_ref().is_some_and(|v| v.chars().count() > max) {
+        return Err(AppError::UpdateUserError(format!(
+          "{} must be at most {} characters",
+          name, max
+        )));
+      }
+    }
+
+    if let Some(fullname) = input.fullname {
+      if fullname.trim().is_empty() {
+        return Err(AppError::UpdateUserError(
+          "fullname cannot be empty".to_string(),
+        ));
+      }
+      user.fullname = fullname;
+    }
+
+    if let Some(timezone) = &input.timezone {
+      if !timezone.is_empty() && !self.is_valid_timezone(timezone).await? {
+        return Err(AppError::UpdateUserError(format!(
+          "Invalid timezone: {}",
+          timezone
+        )));
+      }
+    }
+
+    if let Some(avatar_url) = &input.avatar_url {
+      if !avatar_url.is_empty() {
+        let file = ChatFile::from_str(avatar_url)?;
+        if file.ws_id != ws_id || !file.path(&self.config.server.base_dir).exists() {
+          return Err(AppError::UpdateUserError(format!(
+            "File {} doesn't exist",
+            avatar_url
+          )));
+        }
+      }
+    }
+
+    // a new status starts without expiry unless one is given with it
+    let new_status = input.status_text.is_some() || input.status_emoji.is_some();
+    match input.status_expires_at {
+      Some(expires_at) => user.status_expires_at = expires_at,
+      None if new_status => user.status_expires_at = None,
+      None => {}
+    }
+
+    merge_field(&mut user.display_name, input.display_name);
+    merge_field(&mut user.title, input.title);
+    merge_field(&mut user.timezone, input.timezone);
+    merge_field(&mut user.avatar_url, input.avatar_url);
+    merge_field(&mut user.status_text, input.status_text);
+    merge_field(&mut user.status_emoji, input.status_emoji);
+    merge_field(&mut user.pronouns, input.pronouns);
+
+    let has_status = user.status_text.is_some() || user.status_emoji.is_some();
+    if user.status_expires_at.is_some() && !has_status {
+      return Err(AppError::UpdateUserError(
+        "status_expires_at needs a status text or emoji".to_string(),
+      ));
+    }
+
+    sqlx::query(
+      r#"
+      UPDATE users
+      SET fullname = $1, display_name = $2, title = $3, timezone = $4, avatar_url = $5,
+        status_text = $6, status_emoji = $7, status_expires_at = $8, pronouns = $9
+      WHERE id = $10
+      "#,
+    )
+    .bind(&user.fullname)
+    .bind(&user.display_name)
+    .bind(&user.title)
+    .bind(&user.timezone)
+    .bind(&user.avatar_url)
+    .bind(&user.status_text)
+    .bind(&user.status_emoji)
+    .bind(user.status_expires_at)
+    .bind(&user.pronouns)
+    .bind(user_id as i64)
+    .execute(&self.pool)
+    .await?;
+
+    Ok(user)
+  }
+
+  pub async fn change_password(
+    &self,
+    user_id: u64,
+    input: &ChangePassword,
+  ) -> Result<(), AppError> {
+    if input.new_password.is_empty() {
+      return Err(AppError::UpdateUserError(
+        "new password cannot be empty".to_string(),
+      ));
+    }
+
+    let password_hash: Option<(String,)> = sqlx::query_as(
+      r#"
+      SELECT password_hash
+      FROM users
+      WHERE id = $1
+      "#,
+    )
+    .bind(user_id as i64)
+    .fetch_optional(&self.pool)
+    .await?;
+
+    let Some((password_hash,)) = password_hash else {
+      return Err(AppError::NotFound(format!("User: {} not found", user_id)));
+    };
+    if !verify_password(&input.old_password, &password_hash)? {
+      return Err(AppError::InvalidPassword);
+    }
+
+    let password_hash = hash_password(&input.new_password)?;
+    sqlx::query(
+      r#"
+      UPDATE users
+      SET password_hash = $1
+      WHERE id = $2
+      "#,
+    )
+    .bind(password_hash)
+    .bind(user_id as i64)
+    .execute(&self.pool)
+    .await?;
+
+    Ok(())
+  }
+
+  async fn is_valid_timezone(&self, name: &str) -> Result<bool, AppError> {
+    let tz = sqlx::query(
+      r#"
+      SELECT 1
+      FROM pg_timezone_names
+      WHERE name = $1
+      "#,
+    )
+    .bind(name)
+    .fetch_optional(&self.pool)
+    .await?;
+
+    Ok(tz.is_some())
+  }
+}
+
+/// Tells a field set to null apart from a missing one, which stays `None`.
+fn deserialize_present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+  T: Deserialize<'de>,
+  D: Deserializer<'de>,
+{
+  T::deserialize(deserializer).map(Some)
+}
+
+fn merge_field(field: &mut Option<String>, value: Option<String>) {
+  if let Some(value) = value {
+    *field = if value.is_empty() { None } else { Some(value) };
+  }
+}
+
+/// The cursor is `rank:id` of the last user on the previous page.
+fn parse_user_cursor(cursor: &str) -> Result<(f32, i64), AppError> {
+  cursor
+    .split_once(':')
+    .and_then(|(rank, id)| Some((rank.parse().ok()?, id.parse().ok()?)))
+    .filter(|(rank, _): &(f32, i64)| rank.is_finite())
+    .ok_or_else(|| AppError::InvalidPagination(format!("invalid cursor: {}", cursor)))
+}
+
+pub(crate) fn escape_like(s: &str) -> String {
+  s.replace('\\', "\\\\")
+    .replace('%', "\\%")
+    .replace('_', "\\_")
 }
 
 #[allow(unused)]
@@ -215,4 +552,310 @@ mod tests {
 
     Ok(())
   }
+
+  #[tokio::test]
+  async fn fetch_chat_users_should_search_by_prefix_and_fuzzy() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+
+    let input = ListUsers {
+      q: Some("ali".to_string()),
+      ..Default::default()
+    };
+    let users = state.fetch_chat_users(1, 1, input).await?.users;
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].email, "alice@acme.org");
+
+    let input = ListUsers {
+      q: Some("chen".to_string()),
+      ..Default::default()
+    };
+    let users = state.fetch_chat_users(1, 1, input).await?.users;
+    assert_eq!(users.len(), 4);
+
+    let input = ListUsers {
+      q: Some("%".to_string()),
+      ..Default::default()
+    };
+    let users = state.fetch_chat_users(1, 1, input).await?.users;
+    assert!(users.is_empty());
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn fetch_chat_users_should_order_fuzzy_matches_by_similarity() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+
+    // "alie chen" is closer to Charlie Chen than to Alice Chen, who has the lower id
+    let input = ListUsers {
+      q: Some("alie chen".to_string()),
+      ..Default::default()
+    };
+    let users = state.fetch_chat_users(1, 1, input).await?.users;
+    let all = users.iter().map(|u| u.id).collect::<Vec<_>>();
+    assert_eq!(all, [4, 2]);
+
+    // the cursor keeps the similarity order across pages
+    let mut paged = Vec::new();
+    let mut cursor = None;
+    loop {
+      let input = ListUsers {
+        q: Some("alie chen".to_string()),
+        cursor,
+        limit: Some(1),
+        ..Default::default()
+      };
+      let page = state.fetch_chat_users(1, 1, input).await?;
+      paged.extend(page.users.iter().map(|u| u.id));
+      let Some(next) = page.next_cursor else {
+        break;
+      };
+      cursor = Some(next);
+    }
+    assert_eq!(paged, all);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn fetch_chat_users_should_hide_members_of_chats_the_user_cannot_see() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let input = ListUsers {
+      chat_id: Some(2),
+      ..Default::default()
+    };
+    // daisy isn't in the private channel
+    let err = state
+      .fetch_chat_users(1, 5, input.clone())
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::NotFound(_)));
+    let users = state.fetch_chat_users(1, 1, input).await?.users;
+    assert_eq!(users.len(), 3);
+
+    // public channels are visible to everyone in the workspace
+    let input = ListUsers {
+      chat_id: Some(1),
+      ..Default::default()
+    };
+    let users = state.fetch_chat_users(1, 5, input).await?.users;
+    assert_eq!(users.len(), 5);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn fetch_chat_users_should_paginate_and_filter() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+
+    let input = ListUsers {
+      limit: Some(2),
+      ..Default::default()
+    };
+    let page = state.fetch_chat_users(1, 1, input).await?;
+    assert_eq!(page.users.iter().map(|u| u.id).collect::<Vec<_>>(), [1, 2]);
+
+    // the cursor holds even when the last user of the page no longer matches
+    sqlx::query("UPDATE users SET is_active = FALSE WHERE id = 2")
+      .execute(&state.pool)
+      .await?;
+    let input = ListUsers {
+      cursor: page.next_cursor,
+      limit: Some(2),
+      active: Some(true),
+      ..Default::default()
+    };
+    let page = state.fetch_chat_users(1, 1, input).await?;
+    assert_eq!(page.users.iter().map(|u| u.id).collect::<Vec<_>>(), [3, 4]);
+    sqlx::query("UPDATE users SET is_active = TRUE WHERE id = 2")
+      .execute(&state.pool)
+      .await?;
+
+    // the last page has no cursor, a zero limit still returns a user
+    let input = ListUsers {
+      cursor: page.next_cursor,
+      limit: Some(0),
+      ..Default::default()
+    };
+    let page = state.fetch_chat_users(1, 1, input).await?;
+    assert_eq!(page.users.iter().map(|u| u.id).collect::<Vec<_>>(), [5]);
+    assert!(page.next_cursor.is_none());
+
+    let input = ListUsers {
+      cursor: Some("bogus".to_string()),
+      ..Default::default()
+    };
+    let err = state.fetch_chat_users(1, 1, input).await.unwrap_err();
+    assert!(matches!(err, AppError::InvalidPagination(_)));
+
+    sqlx::query("UPDATE users SET is_bot = TRUE, is_active = FALSE WHERE id = 5")
+      .execute(&state.pool)
+      .await?;
+    let input = ListUsers {
+      bot: Some(true),
+      ..Default::default()
+    };
+    let users = state.fetch_chat_users(1, 1, input).await?.users;
+    assert_eq!(users.len(), 1);
+    assert!(users[0].is_bot);
+
+    let input = ListUsers {
+      active: Some(true),
+      ..Default::default()
+    };
+    let users = state.fetch_chat_users(1, 1, input).await?.users;
+    assert_eq!(users.len(), 4);
+
+    let input = ListUsers {
+      chat_id: Some(2),
+      ..Default::default()
+    };
+    let users = state.fetch_chat_users(1, 1, input).await?.users;
+    assert_eq!(users.iter().map(|u| u.id).collect::<Vec<_>>(), [1, 2, 3]);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn update_profile_should_work() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let file = ChatFile::new(1, "avatar.png", b"avatar");
+    let path = file.path(&state.config.server.base_dir);
+    std::fs::create_dir_all(path.parent().expect("file path parent should exists"))?;
+    std::fs::write(&path, b"avatar")?;
+
+    let input = UpdateProfile {
+      display_name: Some("hal".to_string()),
+      title: Some("Engineer".to_string()),
+      timezone: Some("Asia/Shanghai".to_string()),
+      avatar_url: Some(file.url()),
+      status_text: Some("in a meeting".to_string()),
+      status_emoji: Some(":calendar:".to_string()),
+      pronouns: Some("they/them".to_string()),
+      ..Default::default()
+    };
+    let user = state.update_profile(1, 1, input).await?;
+    assert_eq!(user.fullname, "Hal Di");
+    assert_eq!(user.display_name.as_deref(), Some("hal"));
+    assert_eq!(user.timezone.as_deref(), Some("Asia/Shanghai"));
+    assert_eq!(user.avatar_url, Some(file.url()));
+
+    // empty string clears a field, missing fields are unchanged
+    let input = UpdateProfile {
+      title: Some("".to_string()),
+      ..Default::default()
+    };
+    state.update_profile(1, 1, input).await?;
+    let user = state
+      .get_chat_user_by_id(1)
+      .await?
+      .expect("user should exist");
+    assert_eq!(user.title, None);
+    assert_eq!(user.display_name.as_deref(), Some("hal"));
+    assert_eq!(user.status_text.as_deref(), Some("in a meeting"));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn expired_status_should_be_hidden() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let input = UpdateProfile {
+      status_text: Some("lunch".to_string()),
+      status_expires_at: Some(Some(Utc::now() - chrono::Duration::minutes(1))),
+      ..Default::default()
+    };
+    state.update_profile(1, 1, input).await?;
+    let user = state
+      .get_chat_user_by_id(1)
+      .await?
+      .expect("user should exist");
+    assert_eq!(user.status_text, None);
+    assert_eq!(user.status_expires_at, None);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn status_expiry_should_update_on_its_own() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    // an expiry without a status is rejected
+    let input: UpdateProfile =
+      serde_json::from_str(r#"{"status_expires_at": "2099-01-01T00:00:00Z"}"#)?;
+    let err = state.update_profile(1, 1, input).await.unwrap_err();
+    assert!(matches!(err, AppError::UpdateUserError(_)));
+
+    let input = UpdateProfile {
+      status_text: Some("on leave".to_string()),
+      ..Default::default()
+    };
+    state.update_profile(1, 1, input).await?;
+
+    let input: UpdateProfile =
+      serde_json::from_str(r#"{"status_expires_at": "2099-01-01T00:00:00Z"}"#)?;
+    let user = state.update_profile(1, 1, input).await?;
+    assert_eq!(user.status_text.as_deref(), Some("on leave"));
+    assert!(user.status_expires_at.is_some());
+
+    // null clears the expiry, a missing field keeps it
+    let input: UpdateProfile = serde_json::from_str(r#"{"pronouns": "they/them"}"#)?;
+    let user = state.update_profile(1, 1, input).await?;
+    assert!(user.status_expires_at.is_some());
+    let input: UpdateProfile = serde_json::from_str(r#"{"status_expires_at": null}"#)?;
+    let user = state.update_profile(1, 1, input).await?;
+    assert_eq!(user.status_expires_at, None);
+    assert_eq!(user.status_text.as_deref(), Some("on leave"));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn update_profile_with_invalid_input_should_fail() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let input = UpdateProfile {
+      timezone: Some("Mars/Olympus".to_string()),
+      ..Default::default()
+    };
+    let err = state.update_profile(1, 1, input).await.unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "update user error: Invalid timezone: Mars/Olympus"
+    );
+
+    let input = UpdateProfile {
+      avatar_url: Some("/files/1/abc/def/notexist.png".to_string()),
+      ..Default::default()
+    };
+    let err = state.update_profile(1, 1, input).await.unwrap_err();
+    assert!(matches!(err, AppError::UpdateUserError(_)));
+
+    let input = UpdateProfile {
+      pronouns: Some("x".repeat(33)),
+      ..Default::default()
+    };
+    let err = state.update_profile(1, 1, input).await.unwrap_err();
+    assert!(matches!(err, AppError::UpdateUserError(_)));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn change_password_should_work() -> Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let input = CreateUser::new("acme", "Hal", "hal2@acme.org", "123456");
+    let user = state.create_user(&input).await?;
+
+    let input = ChangePassword {
+      old_password: "wrong".to_string(),
+      new_password: "654321".to_string(),
+    };
+    let err = state
+      .change_password(user.id as _, &input)
+      .await
+      .unwrap_err();
+    assert!(matches!(err, AppError::InvalidPassword));
+
+    let input = ChangePassword {
+      old_password: "123456".to_string(),
+      new_password: "654321".to_string(),
+    };
+    state.change_password(user.id as _, &input).await?;
+    let user = state
+      .verify_user(&SigninUser::new("hal2@acme.org", "654321"))
+      .await?;
+    assert!(user.is_some());
+    Ok(())
+  }
 }
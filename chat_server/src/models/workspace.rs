@@ -69,12 +69,31 @@ impl AppState {
 
     Ok(ws)
   }
+
+  /// The workspace owner and users flagged as admin can manage every chat in the workspace.
+  pub async fn is_workspace_admin(&self, ws_id: u64, user_id: u64) -> Result<bool, AppError> {
+    let is_admin: (bool,) = sqlx::query_as(
+      r#"
+      SELECT EXISTS(
+        SELECT 1
+        FROM users u JOIN workspaces w ON w.id = u.ws_id
+        WHERE u.id = $2 AND u.ws_id = $1 AND (u.is_admin OR w.owner_id = u.id)
+      )
+      "#,
+    )
+    .bind(ws_id as i64)
+    .bind(user_id as i64)
+    .fetch_one(&self.pool)
+    .await?;
+
+    Ok(is_admin.0)
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
-  use crate::models::CreateUser;
+  use crate::models::{CreateUser, ListUsers};
   use anyhow::{Ok, Result};
 
   #[tokio::test]
@@ -107,7 +126,10 @@ mod tests {
   #[tokio::test]
   async fn workspace_should_fetch_all_chat_users() -> Result<()> {
     let (_tdb, state) = AppState::new_for_test().await?;
-    let users = state.fetch_chat_users(1).await?;
+    let users = state
+      .fetch_chat_users(1, 1, ListUsers::default())
+      .await?
+      .users;
     assert_eq!(users.len(), 5);
     Ok(())
   }
@@ -0,0 +1,15 @@
+-- Add migration script here
+-- account flags for the workspace user directory
+ALTER TABLE users
+  ADD COLUMN is_bot boolean NOT NULL DEFAULT FALSE,
+  ADD COLUMN is_active boolean NOT NULL DEFAULT TRUE;
+
+-- trigram indexes for prefix and fuzzy search on fullname and email
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS users_fullname_trgm_index ON users USING gin(fullname gin_trgm_ops);
+
+CREATE INDEX IF NOT EXISTS users_email_trgm_index ON users USING gin(email gin_trgm_ops);
+
+-- create index for users for ws_id and id, used by cursor pagination
+CREATE INDEX IF NOT EXISTS users_ws_id_id_index ON users(ws_id, id);
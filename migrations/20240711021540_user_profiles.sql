@@ -0,0 +1,38 @@
+-- Add migration script here
+-- editable profile fields for users
+ALTER TABLE users
+  ADD COLUMN display_name varchar(64),
+  ADD COLUMN title varchar(128),
+  ADD COLUMN timezone varchar(64),
+  -- url of an uploaded chat file, e.g. /files/1/a13/e8e/928ba9.png
+  ADD COLUMN avatar_url varchar(256),
+  ADD COLUMN status_text varchar(128),
+  ADD COLUMN status_emoji varchar(32),
+  ADD COLUMN status_expires_at timestamptz,
+  ADD COLUMN pronouns varchar(32);
+
+-- public profile of users, custom status is hidden once it expires
+CREATE OR REPLACE VIEW chat_users AS
+SELECT
+  id,
+  ws_id,
+  fullname,
+  email,
+  is_bot,
+  is_active,
+  display_name,
+  title,
+  timezone,
+  avatar_url,
+  CASE WHEN status_expires_at IS NULL OR status_expires_at > now() THEN
+    status_text
+  END AS status_text,
+  CASE WHEN status_expires_at IS NULL OR status_expires_at > now() THEN
+    status_emoji
+  END AS status_emoji,
+  CASE WHEN status_expires_at IS NULL OR status_expires_at > now() THEN
+    status_expires_at
+  END AS status_expires_at,
+  pronouns
+FROM
+  users;
@@ -0,0 +1,16 @@
+-- Add migration script here
+-- if new message added, notify with the message id, notify_server loads the message and
+-- the chat members to deliver to, which would not fit in a notify payload
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    RAISE NOTICE 'add_to_message: %', NEW;
+    PERFORM
+      pg_notify('chat_message_created', json_build_object('message_id', NEW.id)::text);
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
@@ -0,0 +1,159 @@
+-- Add migration script here
+-- create chat role: member, admin, creator
+CREATE TYPE chat_role AS ENUM(
+  'member',
+  'admin',
+  'creator'
+);
+
+-- chat membership, one row per user in a chat
+CREATE TABLE IF NOT EXISTS chat_members(
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id),
+  role chat_role NOT NULL DEFAULT 'member',
+  -- id of the last message the user has read in the chat
+  last_read_id bigint,
+  joined_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (chat_id, user_id)
+);
+
+-- create index for chat_members for user_id, used to find the chats of a user
+CREATE INDEX IF NOT EXISTS chat_members_user_id_index ON chat_members(user_id, chat_id);
+
+-- migrate existing members
+INSERT INTO chat_members(chat_id, user_id, joined_at)
+SELECT
+  id,
+  unnest(members),
+  created_at
+FROM
+  chats
+ON CONFLICT
+  DO NOTHING;
+
+ALTER TABLE chats
+  DROP COLUMN members;
+
+-- member ids of a chat
+CREATE OR REPLACE FUNCTION chat_members_of(cid bigint)
+  RETURNS bigint[]
+  AS $$
+  SELECT
+    coalesce(array_agg(user_id ORDER BY user_id), '{}')
+  FROM
+    chat_members
+  WHERE
+    chat_id = cid;
+$$
+LANGUAGE sql
+STABLE;
+
+-- chat row with its members, same shape as chat_core::Chat
+CREATE OR REPLACE FUNCTION chat_json(c chats, members bigint[])
+  RETURNS jsonb
+  AS $$
+  SELECT
+    to_jsonb(c) || jsonb_build_object('members', members);
+$$
+LANGUAGE sql
+STABLE;
+
+-- members are inserted after the chat row, so new chats are announced at commit. Payloads
+-- only carry ids, notify_server loads the chat and its members, which would not fit.
+CREATE OR REPLACE FUNCTION add_to_chat()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  USERS bigint[];
+  i integer;
+BEGIN
+  RAISE NOTICE 'add_to_chat: %', NEW;
+  IF TG_OP = 'INSERT' THEN
+    -- remember the chat is new, so that inserting its members doesn't notify
+    PERFORM
+      set_config('chat.new_' || NEW.id, 'true', TRUE);
+    RETURN NEW;
+  END IF;
+  -- runs before the delete, so members are still there, but the chat is gone by the time
+  -- notify_server gets the event. The members are sent in chunks to fit the payload, and the
+  -- chat without the fields that can grow long.
+  USERS := chat_members_of(OLD.id);
+  i := 1;
+  LOOP
+    PERFORM
+      pg_notify('chat_updated', json_build_object('op', TG_OP, 'chat_id', OLD.id, 'chat', chat_json(OLD, '{}') - 'topic' - 'description' - 'member_key', 'user_ids', USERS[i:i + 199])::text);
+    i := i + 200;
+    EXIT WHEN i > cardinality(USERS);
+  END LOOP;
+  RETURN OLD;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION new_chat_created()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  PERFORM
+    pg_notify('chat_updated', json_build_object('op', TG_OP, 'chat_id', NEW.id)::text);
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS add_to_chat_trigger ON chats;
+
+CREATE TRIGGER add_to_chat_trigger
+  AFTER INSERT ON chats
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_chat();
+
+CREATE TRIGGER remove_chat_trigger
+  BEFORE DELETE ON chats
+  FOR EACH ROW
+  EXECUTE FUNCTION add_to_chat();
+
+CREATE CONSTRAINT TRIGGER new_chat_trigger
+  AFTER INSERT ON chats DEFERRABLE INITIALLY DEFERRED
+  FOR EACH ROW
+  EXECUTE FUNCTION new_chat_created();
+
+-- if members of an existing chat changed, notify with the user who joined or left
+CREATE OR REPLACE FUNCTION update_chat_members()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  cid bigint;
+  uid bigint;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    cid := NEW.chat_id;
+    uid := NEW.user_id;
+  ELSE
+    cid := OLD.chat_id;
+    uid := OLD.user_id;
+  END IF;
+  IF current_setting('chat.new_' || cid, TRUE) = 'true' THEN
+    RETURN NULL;
+  END IF;
+  -- the chat itself is being deleted
+  IF NOT EXISTS (
+    SELECT
+      1
+    FROM
+      chats
+    WHERE
+      id = cid) THEN
+    RETURN NULL;
+  END IF;
+  PERFORM
+    pg_notify('chat_updated', json_build_object('op', 'UPDATE', 'chat_id', cid, 'user_id', uid)::text);
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER update_chat_members_trigger
+  AFTER INSERT OR DELETE ON chat_members
+  FOR EACH ROW
+  EXECUTE FUNCTION update_chat_members();
@@ -0,0 +1,7 @@
+-- Add migration script here
+-- topic shown in the channel browser and the chat header
+ALTER TABLE chats
+  ADD COLUMN topic varchar(250);
+
+-- create index for chats for ws_id and type, used by the channel browser
+CREATE INDEX IF NOT EXISTS chats_ws_id_type_index ON chats(ws_id, type);
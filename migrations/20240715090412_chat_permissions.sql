@@ -0,0 +1,31 @@
+-- Add migration script here
+-- workspace admins can manage every chat in their workspace, besides the workspace owner
+ALTER TABLE users
+  ADD COLUMN is_admin boolean NOT NULL DEFAULT FALSE;
+
+-- chats migrated from the members array have no creator, take their earliest member
+UPDATE
+  chat_members m
+SET
+  role = 'creator'
+FROM (
+  SELECT DISTINCT ON (chat_id)
+    chat_id,
+    user_id
+  FROM
+    chat_members
+  ORDER BY
+    chat_id,
+    joined_at,
+    user_id) f
+WHERE
+  m.chat_id = f.chat_id
+  AND m.user_id = f.user_id
+  AND NOT EXISTS (
+    SELECT
+      1
+    FROM
+      chat_members c
+    WHERE
+      c.chat_id = m.chat_id
+      AND c.role = 'creator');
@@ -0,0 +1,9 @@
+-- Add migration script here
+-- archived chats are read-only and hidden from default listings
+ALTER TABLE chats
+  ADD COLUMN archived_at timestamptz;
+
+-- permanently deleting a chat removes its history too
+ALTER TABLE messages
+  DROP CONSTRAINT messages_chat_id_fkey,
+  ADD CONSTRAINT messages_chat_id_fkey FOREIGN KEY (chat_id) REFERENCES chats(id) ON DELETE CASCADE;
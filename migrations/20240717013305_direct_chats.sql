@@ -0,0 +1,71 @@
+-- Add migration script here
+-- sorted member ids of single and group chats, so there is one chat per member set
+ALTER TABLE chats
+  ADD COLUMN member_key bigint[];
+
+UPDATE
+  chats c
+SET
+  member_key = k.member_key
+FROM (
+  SELECT DISTINCT ON (ws_id, type, chat_members_of(id))
+    id,
+    chat_members_of(id) AS member_key
+  FROM
+    chats
+  WHERE
+    type IN ('single', 'group')
+  ORDER BY
+    ws_id,
+    type,
+    chat_members_of(id),
+    id) k
+WHERE
+  c.id = k.id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS chats_member_key_index ON chats(ws_id, type, member_key)
+WHERE
+  member_key IS NOT NULL;
+
+-- keep the key in sync with the members once the transaction is done changing them
+CREATE OR REPLACE FUNCTION update_chat_member_key()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  cid bigint;
+  KEY bigint[];
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    cid := NEW.chat_id;
+  ELSE
+    cid := OLD.chat_id;
+  END IF;
+  KEY := chat_members_of(cid);
+  BEGIN
+    UPDATE
+      chats
+    SET
+      member_key = KEY
+    WHERE
+      id = cid
+      AND type IN ('single', 'group')
+      AND member_key IS DISTINCT FROM KEY;
+  EXCEPTION
+    -- another chat already has these members, this one is no longer the canonical chat
+    WHEN unique_violation THEN
+      UPDATE
+        chats
+      SET
+        member_key = NULL
+      WHERE
+        id = cid;
+  END;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE CONSTRAINT TRIGGER update_chat_member_key_trigger
+  AFTER INSERT OR DELETE ON chat_members DEFERRABLE INITIALLY DEFERRED
+  FOR EACH ROW
+  EXECUTE FUNCTION update_chat_member_key();
@@ -0,0 +1,103 @@
+-- Add migration script here
+-- channel metadata, the slug is the normalized name used to address a channel as #slug
+ALTER TABLE chats
+  ADD COLUMN description varchar(500),
+  ADD COLUMN icon varchar(250),
+  ADD COLUMN created_by bigint REFERENCES users(id),
+  ADD COLUMN slug varchar(80);
+
+UPDATE
+  chats c
+SET
+  created_by = m.user_id
+FROM
+  chat_members m
+WHERE
+  m.chat_id = c.id
+  AND m.role = 'creator';
+
+-- same as channel_slug in chat_server: ASCII letters are lowercased, runs of other ASCII
+-- characters become a dash and non-ASCII characters are kept, so the result doesn't depend
+-- on the database locale
+CREATE OR REPLACE FUNCTION channel_slug(name text)
+  RETURNS text
+  AS $$
+  SELECT
+    nullif(rtrim(left(trim(BOTH '-' FROM regexp_replace(translate(name, 'ABCDEFGHIJKLMNOPQRSTUVWXYZ', 'abcdefghijklmnopqrstuvwxyz'), '[^a-z0-9\u0080-\U0010FFFF]+', '-', 'g')), 60), '-'), '')
+$$
+LANGUAGE sql
+IMMUTABLE;
+
+-- existing channels with clashing names get their id appended
+UPDATE
+  chats c
+SET
+  slug = CASE WHEN s.n = 1 THEN
+    s.slug
+  ELSE
+    s.slug || '-' || c.id
+  END
+FROM (
+  SELECT
+    id,
+    slug,
+    row_number() OVER (PARTITION BY ws_id, slug ORDER BY id) AS n
+  FROM (
+    SELECT
+      id,
+      ws_id,
+      channel_slug(name) AS slug
+    FROM
+      chats
+    WHERE
+      type IN ('public_channel', 'private_channel')
+      AND name IS NOT NULL) t) s
+WHERE
+  c.id = s.id
+  AND s.slug IS NOT NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS chats_slug_index ON chats(ws_id, slug)
+WHERE
+  slug IS NOT NULL;
+
+-- history of channel metadata changes, one row per changed field
+CREATE TABLE IF NOT EXISTS chat_changes(
+  id bigserial PRIMARY KEY,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  changed_by bigint NOT NULL REFERENCES users(id),
+  field varchar(32) NOT NULL,
+  old_value text,
+  new_value text,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS chat_changes_chat_id_index ON chat_changes(chat_id, id DESC);
+
+-- notify the members once per update, with the ids of all fields changed by it. notify_server
+-- loads the changes and the chat, old and new values can be too long for the payload.
+CREATE OR REPLACE FUNCTION chat_changes_created()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  rec record;
+BEGIN
+  FOR rec IN
+  SELECT
+    chat_id,
+    array_agg(n.id ORDER BY n.id) AS change_ids
+  FROM
+    new_changes n
+  GROUP BY
+    chat_id LOOP
+      PERFORM
+        pg_notify('chat_changed', json_build_object('chat_id', rec.chat_id, 'change_ids', rec.change_ids)::text);
+    END LOOP;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_changes_created_trigger
+  AFTER INSERT ON chat_changes REFERENCING NEW TABLE AS new_changes
+  FOR EACH STATEMENT
+  EXECUTE FUNCTION chat_changes_created();
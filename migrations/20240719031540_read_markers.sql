@@ -0,0 +1,19 @@
+-- Add migration script here
+-- tell the user's other devices when they read a chat
+CREATE OR REPLACE FUNCTION chat_read_updated()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF NEW.last_read_id IS DISTINCT FROM OLD.last_read_id THEN
+    PERFORM
+      pg_notify('chat_read', json_build_object('chat_id', NEW.chat_id, 'user_id', NEW.user_id, 'last_read_id', NEW.last_read_id)::text);
+  END IF;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER chat_read_updated_trigger
+  AFTER UPDATE OF last_read_id ON chat_members
+  FOR EACH ROW
+  EXECUTE FUNCTION chat_read_updated();
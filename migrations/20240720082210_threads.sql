@@ -0,0 +1,86 @@
+-- Add migration script here
+-- a reply belongs to a top-level message of the same chat, and can also be shown in the chat
+ALTER TABLE messages
+  ADD COLUMN parent_id bigint REFERENCES messages(id) ON DELETE CASCADE,
+  ADD COLUMN also_in_chat boolean NOT NULL DEFAULT FALSE,
+  ADD COLUMN reply_count integer NOT NULL DEFAULT 0,
+  ADD COLUMN last_reply_at timestamptz;
+
+CREATE INDEX IF NOT EXISTS messages_parent_id_index ON messages(parent_id, id)
+WHERE
+  parent_id IS NOT NULL;
+
+-- keep reply stats of the parent message up to date
+CREATE OR REPLACE FUNCTION message_reply_added()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  UPDATE
+    messages
+  SET
+    reply_count = reply_count + 1,
+    last_reply_at = NEW.created_at
+  WHERE
+    id = NEW.parent_id;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_reply_added_trigger
+  AFTER INSERT ON messages
+  FOR EACH ROW
+  WHEN (NEW.parent_id IS NOT NULL)
+  EXECUTE FUNCTION message_reply_added();
+
+-- removed replies no longer count, the stats are recounted from the replies that are left
+CREATE OR REPLACE FUNCTION message_reply_stats()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  UPDATE
+    messages p
+  SET
+    reply_count = s.reply_count,
+    last_reply_at = s.last_reply_at
+  FROM (
+    SELECT
+      count(*) AS reply_count,
+      max(created_at) AS last_reply_at
+    FROM
+      messages
+    WHERE
+      parent_id = OLD.parent_id) s
+WHERE
+  p.id = OLD.parent_id;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_reply_stats_trigger
+  AFTER DELETE ON messages
+  FOR EACH ROW
+  WHEN (OLD.parent_id IS NOT NULL)
+  EXECUTE FUNCTION message_reply_stats();
+
+-- replies go to thread views, and to the chat too if they are also shown there
+CREATE OR REPLACE FUNCTION add_to_message()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    RAISE NOTICE 'add_to_message: %', NEW;
+    IF NEW.parent_id IS NULL OR NEW.also_in_chat THEN
+      PERFORM
+        pg_notify('chat_message_created', json_build_object('message_id', NEW.id)::text);
+    END IF;
+    IF NEW.parent_id IS NOT NULL THEN
+      PERFORM
+        pg_notify('thread_reply_created', json_build_object('message_id', NEW.id)::text);
+    END IF;
+  END IF;
+  RETURN NEW;
+END;
+$$
+LANGUAGE plpgsql;
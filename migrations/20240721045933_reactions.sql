@@ -0,0 +1,44 @@
+-- Add migration script here
+-- emoji reactions, a user can react to a message once per emoji
+CREATE TABLE IF NOT EXISTS message_reactions(
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  user_id bigint NOT NULL REFERENCES users(id),
+  emoji varchar(64) NOT NULL,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (message_id, user_id, emoji)
+);
+
+-- notify the chat members when a reaction is added or removed
+CREATE OR REPLACE FUNCTION message_reaction_changed()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  rec message_reactions;
+  cid bigint;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    rec := NEW;
+  ELSE
+    rec := OLD;
+  END IF;
+  SELECT
+    chat_id INTO cid
+  FROM
+    messages
+  WHERE
+    id = rec.message_id;
+  -- the message itself is being deleted
+  IF NOT FOUND THEN
+    RETURN NULL;
+  END IF;
+  PERFORM
+    pg_notify('message_reaction_changed', json_build_object('op', TG_OP, 'reaction', to_jsonb(rec) || jsonb_build_object('chat_id', cid))::text);
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_reaction_changed_trigger
+  AFTER INSERT OR DELETE ON message_reactions
+  FOR EACH ROW
+  EXECUTE FUNCTION message_reaction_changed();
@@ -0,0 +1,72 @@
+-- Add migration script here
+-- edited and deleted markers, deleted messages are kept as tombstones without content
+ALTER TABLE messages
+  ADD COLUMN edited_at timestamptz,
+  ADD COLUMN deleted_at timestamptz;
+
+-- previous versions of edited messages
+CREATE TABLE IF NOT EXISTS message_revisions(
+  id bigserial PRIMARY KEY,
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  content text NOT NULL,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS message_revisions_message_id_index ON message_revisions(message_id, id);
+
+-- notify the chat members when a message is edited or deleted
+CREATE OR REPLACE FUNCTION message_changed()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  op text;
+BEGIN
+  IF NEW.deleted_at IS NOT NULL THEN
+    op := 'DELETE';
+  ELSE
+    op := 'UPDATE';
+  END IF;
+  PERFORM
+    pg_notify('chat_message_updated', json_build_object('op', op, 'message_id', NEW.id)::text);
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_changed_trigger
+  AFTER UPDATE ON messages
+  FOR EACH ROW
+  WHEN (OLD.edited_at IS DISTINCT FROM NEW.edited_at OR OLD.deleted_at IS DISTINCT FROM NEW.deleted_at)
+  EXECUTE FUNCTION message_changed();
+
+-- deleted replies are tombstones that no longer count in the reply stats
+CREATE OR REPLACE FUNCTION message_reply_stats()
+  RETURNS TRIGGER
+  AS $$
+BEGIN
+  UPDATE
+    messages p
+  SET
+    reply_count = s.reply_count,
+    last_reply_at = s.last_reply_at
+  FROM (
+    SELECT
+      count(*) AS reply_count,
+      max(created_at) AS last_reply_at
+    FROM
+      messages
+    WHERE
+      parent_id = OLD.parent_id
+      AND deleted_at IS NULL) s
+WHERE
+  p.id = OLD.parent_id;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_reply_deleted_trigger
+  AFTER UPDATE OF deleted_at ON messages
+  FOR EACH ROW
+  WHEN (OLD.parent_id IS NOT NULL AND OLD.deleted_at IS NULL AND NEW.deleted_at IS NOT NULL)
+  EXECUTE FUNCTION message_reply_stats();
@@ -0,0 +1,65 @@
+-- Add migration script here
+CREATE TYPE mention_kind AS ENUM(
+  'user',
+  'channel',
+  'here'
+);
+
+-- mentions found in messages, user_id is only set for user mentions
+CREATE TABLE IF NOT EXISTS message_mentions(
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  kind mention_kind NOT NULL,
+  user_id bigint REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS message_mentions_unique_index ON message_mentions(message_id, kind, coalesce(user_id, 0));
+
+-- used by the mentions feed
+CREATE INDEX IF NOT EXISTS message_mentions_user_id_index ON message_mentions(user_id, message_id DESC);
+
+CREATE INDEX IF NOT EXISTS message_mentions_chat_id_index ON message_mentions(chat_id, message_id DESC)
+WHERE
+  kind <> 'user';
+
+-- muted chats don't alert, mentions still do
+ALTER TABLE chat_members
+  ADD COLUMN muted boolean NOT NULL DEFAULT FALSE;
+
+-- notify once per message with everyone it newly mentions, notify_server resolves @channel
+-- and @here. Long lists of users are split over several notifications to fit the payload.
+CREATE OR REPLACE FUNCTION message_mentions_created()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  rec record;
+  i integer;
+BEGIN
+  FOR rec IN
+  SELECT
+    message_id,
+    coalesce(array_agg(user_id) FILTER (WHERE kind = 'user'), '{}') AS user_ids,
+    bool_or(kind = 'channel') AS channel,
+    bool_or(kind = 'here') AS here
+  FROM
+    new_mentions
+  GROUP BY
+    message_id LOOP
+      i := 1;
+      LOOP
+        PERFORM
+          pg_notify('message_mentioned', json_build_object('message_id', rec.message_id, 'user_ids', rec.user_ids[i:i + 199], 'channel', rec.channel AND i = 1, 'here', rec.here AND i = 1)::text);
+        i := i + 200;
+        EXIT WHEN i > cardinality(rec.user_ids);
+      END LOOP;
+    END LOOP;
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_mentions_created_trigger
+  AFTER INSERT ON message_mentions REFERENCING NEW TABLE AS new_mentions
+  FOR EACH STATEMENT
+  EXECUTE FUNCTION message_mentions_created();
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- full-text index on message content, queries must use the same expression to hit it
+CREATE INDEX IF NOT EXISTS messages_content_search_index ON messages USING GIN (to_tsvector('english', content));
@@ -0,0 +1,58 @@
+-- Add migration script here
+-- a message is pinned at most once
+CREATE TABLE IF NOT EXISTS message_pins(
+  id bigserial PRIMARY KEY,
+  message_id bigint NOT NULL UNIQUE REFERENCES messages(id) ON DELETE CASCADE,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  pinned_by bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS message_pins_chat_id_index ON message_pins(chat_id, id DESC);
+
+-- private to the user who saved them
+CREATE TABLE IF NOT EXISTS bookmarks(
+  id bigserial PRIMARY KEY,
+  user_id bigint NOT NULL REFERENCES users(id),
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  note varchar(500),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (user_id, message_id)
+);
+
+CREATE INDEX IF NOT EXISTS bookmarks_user_id_index ON bookmarks(user_id, id DESC);
+
+-- notify the chat members when a message is pinned or unpinned
+CREATE OR REPLACE FUNCTION message_pin_changed()
+  RETURNS TRIGGER
+  AS $$
+DECLARE
+  rec message_pins;
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    rec := NEW;
+  ELSE
+    rec := OLD;
+  END IF;
+  -- the message or the chat itself is being deleted
+  IF NOT EXISTS (
+    SELECT
+      1
+    FROM
+      messages
+    WHERE
+      id = rec.message_id) THEN
+    RETURN NULL;
+  END IF;
+  PERFORM
+    pg_notify('message_pin_changed', json_build_object('op', TG_OP, 'pin', rec)::text);
+  RETURN NULL;
+END;
+$$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER message_pin_changed_trigger
+  AFTER INSERT OR DELETE ON message_pins
+  FOR EACH ROW
+  EXECUTE FUNCTION message_pin_changed();
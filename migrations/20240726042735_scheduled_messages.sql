@@ -0,0 +1,29 @@
+-- Add migration script here
+CREATE TYPE scheduled_message_status AS ENUM(
+  'pending',
+  'sent',
+  'failed'
+);
+
+-- messages to send later, the dispatcher sends them through the normal message path
+CREATE TABLE IF NOT EXISTS scheduled_messages(
+  id bigserial PRIMARY KEY,
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  sender_id bigint NOT NULL REFERENCES users(id),
+  content text NOT NULL,
+  files text[] NOT NULL DEFAULT '{}',
+  parent_id bigint REFERENCES messages(id) ON DELETE CASCADE,
+  also_in_chat boolean NOT NULL DEFAULT FALSE,
+  send_at timestamptz NOT NULL,
+  status scheduled_message_status NOT NULL DEFAULT 'pending',
+  -- the message it was sent as, or why it couldn't be sent
+  message_id bigint REFERENCES messages(id) ON DELETE SET NULL,
+  error text,
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS scheduled_messages_send_at_index ON scheduled_messages(send_at)
+WHERE
+  status = 'pending';
+
+CREATE INDEX IF NOT EXISTS scheduled_messages_sender_id_index ON scheduled_messages(sender_id, send_at);
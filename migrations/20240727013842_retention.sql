@@ -0,0 +1,35 @@
+-- Add migration script here
+-- delete messages older than this many days, a chat setting overrides the workspace one
+ALTER TABLE workspaces
+  ADD COLUMN retention_days integer CHECK (retention_days > 0);
+
+ALTER TABLE chats
+  ADD COLUMN retention_days integer CHECK (retention_days > 0),
+  -- keeps the messages of the chat whatever the workspace setting
+  ADD COLUMN keep_forever boolean NOT NULL DEFAULT FALSE;
+
+-- finds the messages that still refer to uploaded files before they are removed
+CREATE INDEX IF NOT EXISTS messages_files_index ON messages USING GIN (files);
+
+-- messages past the retention of their chat, threads are kept while they have newer replies
+CREATE OR REPLACE VIEW expired_messages AS
+SELECT
+  m.id,
+  m.chat_id,
+  c.ws_id,
+  coalesce(c.retention_days, w.retention_days) AS retention_days
+FROM
+  messages m
+  JOIN chats c ON c.id = m.chat_id
+  JOIN workspaces w ON w.id = c.ws_id
+WHERE
+  NOT c.keep_forever
+  AND m.created_at < now() - make_interval(days => coalesce(c.retention_days, w.retention_days))
+  AND NOT EXISTS (
+    SELECT
+      1
+    FROM
+      messages r
+    WHERE
+      r.parent_id = m.id
+      AND r.created_at >= now() - make_interval(days => coalesce(c.retention_days, w.retention_days)));
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- content parsed into versioned blocks, JSON null for messages sent before it existed
+ALTER TABLE messages
+  ADD COLUMN rich_text jsonb NOT NULL DEFAULT 'null';
@@ -0,0 +1,14 @@
+-- Add migration script here
+-- previews of the links in a message, fetched after it is sent
+ALTER TABLE messages
+  ADD COLUMN link_previews jsonb NOT NULL DEFAULT '[]';
+
+-- members see the previews arrive as a message update
+DROP TRIGGER IF EXISTS message_changed_trigger ON messages;
+
+CREATE TRIGGER message_changed_trigger
+  AFTER UPDATE ON messages
+  FOR EACH ROW
+  WHEN (OLD.edited_at IS DISTINCT FROM NEW.edited_at OR OLD.deleted_at IS DISTINCT FROM NEW.deleted_at
+    OR OLD.link_previews IS DISTINCT FROM NEW.link_previews)
+  EXECUTE FUNCTION message_changed();
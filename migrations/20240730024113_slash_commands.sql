@@ -0,0 +1,14 @@
+-- Add migration script here
+-- custom slash commands of a workspace, answered by a webhook
+CREATE TABLE IF NOT EXISTS slash_commands(
+  id bigserial PRIMARY KEY,
+  ws_id bigint NOT NULL REFERENCES workspaces(id) ON DELETE CASCADE,
+  name varchar(32) NOT NULL,
+  description varchar(250) NOT NULL DEFAULT '',
+  url text NOT NULL,
+  -- key of the HMAC signature of the webhook calls
+  secret text NOT NULL,
+  created_by bigint NOT NULL REFERENCES users(id),
+  created_at timestamptz DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (ws_id, name)
+);
@@ -0,0 +1,16 @@
+-- Add migration script here
+-- client generated nonce of the request that sent a message, echoed in the notify event
+ALTER TABLE messages
+  ADD COLUMN nonce varchar(64);
+
+-- a nonce is used for one message per sender and chat, until it expires
+CREATE TABLE IF NOT EXISTS message_nonces(
+  chat_id bigint NOT NULL REFERENCES chats(id) ON DELETE CASCADE,
+  sender_id bigint NOT NULL REFERENCES users(id),
+  nonce varchar(64) NOT NULL,
+  message_id bigint NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+  created_at timestamptz NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (chat_id, sender_id, nonce)
+);
+
+CREATE INDEX IF NOT EXISTS message_nonces_created_at_index ON message_nonces(created_at);
@@ -0,0 +1,3 @@
+-- Add migration script here
+-- message pages walk a chat by id in either direction
+CREATE INDEX IF NOT EXISTS messages_chat_id_id_index ON messages(chat_id, id);
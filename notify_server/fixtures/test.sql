@@ -0,0 +1,15 @@
+INSERT INTO workspaces(name, owner_id)
+  VALUES ('acme', 0);
+
+INSERT INTO users(ws_id, email, fullname, password_hash)
+  VALUES (1, 'hal@acme.org', 'Hal Di', ''),
+(1, 'alice@acme.org', 'Alice Chen', ''),
+(1, 'bob@acme.org', 'Bob Chen', '');
+
+INSERT INTO chats(ws_id, name, slug, type, created_by)
+  VALUES (1, 'general', 'general', 'public_channel', 1);
+
+-- bob isn't in the channel
+INSERT INTO chat_members(chat_id, user_id)
+  VALUES (1, 1),
+(1, 2);
@@ -6,6 +6,7 @@ use std::{env, fs::File};
 pub struct AppConfig {
   pub server: ServerConfig,
   pub auth: AuthConfig,
+  pub presence: PresenceConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -13,6 +14,12 @@ pub struct AuthConfig {
   pub pk: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PresenceConfig {
+  /// seconds a user stays online after the last connection is closed
+  pub grace_period: u64,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerConfig {
   pub port: u16,
@@ -15,6 +15,15 @@ pub enum AppError {
 
   #[error("jwt error: {0}")]
   JwtError(#[from] jwt_simple::Error),
+
+  #[error("sql error: {0}")]
+  SqlxError(#[from] sqlx::Error),
+
+  #[error("invalid presence query: {0}")]
+  InvalidPresenceQuery(String),
+
+  #[error("not a member of chat {0}")]
+  NotChatMember(u64),
 }
 
 impl ErrorOutput {
@@ -30,6 +39,9 @@ impl IntoResponse for AppError {
     let status = match &self {
       Self::JwtError(_) => StatusCode::FORBIDDEN,
       Self::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+      Self::SqlxError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+      Self::InvalidPresenceQuery(_) => StatusCode::BAD_REQUEST,
+      Self::NotChatMember(_) => StatusCode::FORBIDDEN,
     };
 
     (status, Json(ErrorOutput::new(self.to_string()))).into_response()
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use notify_server::{get_router, setup_pg_listener};
+use notify_server::{get_router, AppConfig};
 use tokio::net::TcpListener;
 use tracing::{info, level_filters::LevelFilter};
 use tracing_subscriber::{fmt::Layer, layer::SubscriberExt, util::SubscriberInitExt, Layer as _};
@@ -9,10 +9,10 @@ async fn main() -> Result<()> {
   let layer = Layer::new().with_filter(LevelFilter::INFO);
   tracing_subscriber::registry().with(layer).init();
 
-  let addr = "0.0.0.0:6687";
-  setup_pg_listener().await?;
+  let config = AppConfig::load()?;
+  let addr = format!("0.0.0.0:{}", config.server.port);
 
-  let app = get_router();
+  let app = get_router(config).await?;
   let listener = TcpListener::bind(&addr).await?;
   info!("Listening on: {}", addr);
 
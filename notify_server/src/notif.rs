@@ -1,19 +1,34 @@
 use std::{collections::HashSet, sync::Arc};
 
-use crate::AppState;
-use chat_core::{Chat, Message};
+use crate::{AppState, Presence, PresenceStatus, Typing};
+use chat_core::{Chat, ChatChange, Message, Pin, Reaction, ReadMarker};
 use futures::StreamExt;
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgListener;
 use tracing::{info, warn};
 
 #[derive(Debug, Serialize, Deserialize)]
-#[serde(tag = "type")]
+#[serde(tag = "event")]
 pub enum AppEvent {
   NewChat(Chat),
   AddToChat(Chat),
   RemoveFromChat(Chat),
   NewMessage(Message),
+  NewThreadReply(Message),
+  MessageUpdated(Message),
+  MessageDeleted(Message),
+  ChatChanged(ChatChanged),
+  ChatRead(ReadMarker),
+  ReactionAdded(Reaction),
+  ReactionRemoved(Reaction),
+  MessagePinned(Pin),
+  MessageUnpinned(Pin),
+  /// High priority, clients alert on it even if the user muted the chat.
+  Mentioned(Message),
+  PresenceChanged(Presence),
+  /// Ephemeral, relayed by the notify server without going through the database.
+  TypingStarted(Typing),
+  TypingStopped(Typing),
 }
 
 #[derive(Debug)]
@@ -22,30 +37,90 @@ struct Notification {
   event: Arc<AppEvent>,
 }
 
+/// New chats and member changes only carry ids. Deleted chats are gone by the time the event
+/// is handled, so they come with the chat and a chunk of its members.
 #[derive(Debug, Serialize, Deserialize)]
 struct ChatUpdated {
   op: String,
-  old: Option<Chat>,
-  new: Option<Chat>,
+  chat_id: i64,
+  /// the user who joined or left
+  user_id: Option<i64>,
+  chat: Option<Chat>,
+  #[serde(default)]
+  user_ids: Vec<i64>,
+}
+
+/// Channel metadata changes, with the chat as it is after them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChatChanged {
+  pub chat: Chat,
+  pub changes: Vec<ChatChange>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatChangesCreated {
+  chat_id: i64,
+  change_ids: Vec<i64>,
+}
+
+// message payloads only carry ids, the message and the members to deliver to are loaded here
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatMessageUpdated {
+  op: String,
+  message_id: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ReactionChanged {
+  op: String,
+  reaction: Reaction,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PinChanged {
+  op: String,
+  pin: Pin,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MessageMentioned {
+  message_id: i64,
+  user_ids: Vec<i64>,
+  channel: bool,
+  here: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct ChatMessageCreated {
-  message: Message,
-  members: Vec<i64>,
+  message_id: i64,
 }
 
 pub async fn setup_pg_listener(state: AppState) -> anyhow::Result<()> {
   let mut listener = PgListener::connect(&state.config.server.db_url).await?;
   listener.listen("chat_updated").await?;
   listener.listen("chat_message_created").await?;
+  listener.listen("chat_changed").await?;
+  listener.listen("chat_read").await?;
+  listener.listen("thread_reply_created").await?;
+  listener.listen("message_reaction_changed").await?;
+  listener.listen("chat_message_updated").await?;
+  listener.listen("message_mentioned").await?;
+  listener.listen("message_pin_changed").await?;
 
   let mut stream = listener.into_stream();
 
   tokio::spawn(async move {
     while let Some(Ok(notif)) = stream.next().await {
       info!("Received notification: {:?}", notif);
-      let notification = Notification::load(notif.channel(), notif.payload())?;
+      let notification = match Notification::load(notif.channel(), notif.payload(), &state).await {
+        Ok(notification) => notification,
+        Err(e) => {
+          warn!("Failed to load notification: {}", e);
+          continue;
+        }
+      };
+      state.track_notification(&notification.event);
       let users = &state.users;
       for user_id in &notification.user_ids {
         if let Some(tx) = users.get(user_id) {
@@ -56,35 +131,206 @@ pub async fn setup_pg_listener(state: AppState) -> anyhow::Result<()> {
         }
       }
     }
-    Ok::<_, anyhow::Error>(())
   });
   Ok(())
 }
 
+impl AppState {
+  /// Keeps the in-memory typing state in line with the database events.
+  fn track_notification(&self, event: &AppEvent) {
+    match event {
+      // every member change is an update with the members after it, leaving and removals too
+      AppEvent::NewChat(chat) | AppEvent::AddToChat(chat) => {
+        let members = chat.members.iter().map(|id| *id as u64).collect();
+        self.chat_members.set(chat.id as _, members);
+      }
+      AppEvent::RemoveFromChat(chat) => self.chat_members.remove(chat.id as _),
+      // clients replace the indicator with the message
+      AppEvent::NewMessage(message) | AppEvent::NewThreadReply(message) => {
+        self
+          .typing
+          .stop(message.chat_id as _, message.sender_id as _);
+      }
+      _ => {}
+    }
+  }
+}
+
+impl AppState {
+  async fn load_message(&self, id: i64) -> anyhow::Result<Message> {
+    let message = sqlx::query_as(
+      r#"
+      SELECT id, chat_id, sender_id, content, rich_text, files, parent_id, also_in_chat,
+        reply_count, last_reply_at, link_previews, edited_at, deleted_at, nonce, created_at
+      FROM messages
+      WHERE id = $1
+      "#,
+    )
+    .bind(id)
+    .fetch_optional(&self.pool)
+    .await?;
+    // removed by retention or a chat delete before the event got here
+    message.ok_or_else(|| anyhow::anyhow!("Message {} not found", id))
+  }
+
+  async fn load_chat(&self, id: i64) -> anyhow::Result<Chat> {
+    let chat = sqlx::query_as(
+      r#"
+      SELECT id, ws_id, name, type, topic, description, icon, slug, created_by,
+        chat_members_of(id) AS members, archived_at, created_at
+      FROM chats
+      WHERE id = $1
+      "#,
+    )
+    .bind(id)
+    .fetch_optional(&self.pool)
+    .await?;
+    chat.ok_or_else(|| anyhow::anyhow!("Chat {} not found", id))
+  }
+
+  async fn load_chat_changes(&self, ids: &[i64]) -> anyhow::Result<Vec<ChatChange>> {
+    let changes = sqlx::query_as(
+      r#"
+      SELECT id, chat_id, changed_by, field, old_value, new_value, created_at
+      FROM chat_changes
+      WHERE id = ANY($1)
+      ORDER BY id
+      "#,
+    )
+    .bind(ids)
+    .fetch_all(&self.pool)
+    .await?;
+    Ok(changes)
+  }
+
+  async fn chat_member_set(&self, chat_id: i64) -> anyhow::Result<HashSet<u64>> {
+    let members = self.chat_member_ids(chat_id as _).await?;
+    Ok(members.iter().copied().collect())
+  }
+}
+
 impl Notification {
-  fn load(r#type: &str, payload: &str) -> anyhow::Result<Self> {
+  async fn load(r#type: &str, payload: &str, state: &AppState) -> anyhow::Result<Self> {
     match r#type {
       "chat_updated" => {
         let payload: ChatUpdated = serde_json::from_str(payload)?;
         info!("ChatUpdated payload: {:?}", payload);
-        let user_ids = get_affected_chat_user_ids(payload.old.as_ref(), payload.new.as_ref());
-        let event = match payload.op.as_str() {
-          "INSERT" => AppEvent::NewChat(payload.new.expect("new should exist")),
-          "UPDATE" => AppEvent::AddToChat(payload.new.expect("new should exist")),
-          "DELETE" => AppEvent::RemoveFromChat(payload.old.expect("old should exist")),
+        let (mut user_ids, event) = match payload.op.as_str() {
+          "INSERT" => {
+            let chat = state.load_chat(payload.chat_id).await?;
+            (member_set(&chat), AppEvent::NewChat(chat))
+          }
+          "UPDATE" => {
+            let chat = state.load_chat(payload.chat_id).await?;
+            (member_set(&chat), AppEvent::AddToChat(chat))
+          }
+          "DELETE" => {
+            let chat = payload
+              .chat
+              .ok_or_else(|| anyhow::anyhow!("Deleted chat {} missing", payload.chat_id))?;
+            let user_ids = payload.user_ids.iter().map(|v| *v as u64).collect();
+            (user_ids, AppEvent::RemoveFromChat(chat))
+          }
           _ => return Err(anyhow::anyhow!("Unknown operation: {}", payload.op)),
         };
+        // members who left are told too
+        user_ids.extend(payload.user_id.map(|v| v as u64));
         Ok(Self {
           user_ids,
           event: Arc::new(event),
         })
       }
-      "chat_message_created" => {
+      "chat_message_created" | "thread_reply_created" => {
         let payload: ChatMessageCreated = serde_json::from_str(payload)?;
-        let user_ids = payload.members.iter().map(|v| *v as u64).collect();
+        let message = state.load_message(payload.message_id).await?;
+        let user_ids = state.chat_member_set(message.chat_id).await?;
+        let event = if r#type == "chat_message_created" {
+          AppEvent::NewMessage(message)
+        } else {
+          AppEvent::NewThreadReply(message)
+        };
+        Ok(Self {
+          user_ids,
+          event: Arc::new(event),
+        })
+      }
+      "chat_changed" => {
+        let payload: ChatChangesCreated = serde_json::from_str(payload)?;
+        let chat = state.load_chat(payload.chat_id).await?;
+        let changes = state.load_chat_changes(&payload.change_ids).await?;
+        Ok(Self {
+          user_ids: member_set(&chat),
+          event: Arc::new(AppEvent::ChatChanged(ChatChanged { chat, changes })),
+        })
+      }
+      "chat_read" => {
+        // only the reader's own devices care about their read marker
+        let payload: ReadMarker = serde_json::from_str(payload)?;
+        Ok(Self {
+          user_ids: HashSet::from([payload.user_id as u64]),
+          event: Arc::new(AppEvent::ChatRead(payload)),
+        })
+      }
+      "chat_message_updated" => {
+        let payload: ChatMessageUpdated = serde_json::from_str(payload)?;
+        let message = state.load_message(payload.message_id).await?;
+        let user_ids = state.chat_member_set(message.chat_id).await?;
+        let event = match payload.op.as_str() {
+          "UPDATE" => AppEvent::MessageUpdated(message),
+          "DELETE" => AppEvent::MessageDeleted(message),
+          _ => return Err(anyhow::anyhow!("Unknown operation: {}", payload.op)),
+        };
+        Ok(Self {
+          user_ids,
+          event: Arc::new(event),
+        })
+      }
+      "message_reaction_changed" => {
+        let payload: ReactionChanged = serde_json::from_str(payload)?;
+        let user_ids = state.chat_member_set(payload.reaction.chat_id).await?;
+        let event = match payload.op.as_str() {
+          "INSERT" => AppEvent::ReactionAdded(payload.reaction),
+          "DELETE" => AppEvent::ReactionRemoved(payload.reaction),
+          _ => return Err(anyhow::anyhow!("Unknown operation: {}", payload.op)),
+        };
         Ok(Self {
           user_ids,
-          event: Arc::new(AppEvent::NewMessage(payload.message)),
+          event: Arc::new(event),
+        })
+      }
+      "message_pin_changed" => {
+        let payload: PinChanged = serde_json::from_str(payload)?;
+        let user_ids = state.chat_member_set(payload.pin.chat_id).await?;
+        let event = match payload.op.as_str() {
+          "INSERT" => AppEvent::MessagePinned(payload.pin),
+          "DELETE" => AppEvent::MessageUnpinned(payload.pin),
+          _ => return Err(anyhow::anyhow!("Unknown operation: {}", payload.op)),
+        };
+        Ok(Self {
+          user_ids,
+          event: Arc::new(event),
+        })
+      }
+      "message_mentioned" => {
+        let payload: MessageMentioned = serde_json::from_str(payload)?;
+        let message = state.load_message(payload.message_id).await?;
+        let mut user_ids: HashSet<u64> = if payload.channel {
+          state.chat_member_set(message.chat_id).await?
+        } else if payload.here {
+          // @here only reaches members that are online right now
+          let members = state.chat_member_set(message.chat_id).await?;
+          members
+            .into_iter()
+            .filter(|id| state.presence.get(*id).status == PresenceStatus::Online)
+            .collect()
+        } else {
+          HashSet::new()
+        };
+        user_ids.extend(payload.user_ids.iter().map(|v| *v as u64));
+        user_ids.remove(&(message.sender_id as u64));
+        Ok(Self {
+          user_ids,
+          event: Arc::new(AppEvent::Mentioned(message)),
         })
       }
       _ => Err(anyhow::anyhow!("Unknown channel: {}", r#type)),
@@ -92,20 +338,217 @@ impl Notification {
   }
 }
 
-fn get_affected_chat_user_ids(old: Option<&Chat>, new: Option<&Chat>) -> HashSet<u64> {
-  match (old, new) {
-    (Some(old), Some(new)) => {
-      // diff old/new members, if identical, no need to notify, otherwise notify the union of both
-      let old_user_ids: HashSet<_> = old.members.iter().map(|v| *v as u64).collect();
-      let new_user_ids: HashSet<_> = new.members.iter().map(|v| *v as u64).collect();
-      if old_user_ids == new_user_ids {
-        HashSet::new()
-      } else {
-        old_user_ids.union(&new_user_ids).copied().collect()
+fn member_set(chat: &Chat) -> HashSet<u64> {
+  chat.members.iter().map(|v| *v as u64).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::AppError;
+  use chat_core::ChatType;
+
+  async fn next_notification(
+    listener: &mut PgListener,
+    state: &AppState,
+  ) -> anyhow::Result<Notification> {
+    let notif = listener.recv().await?;
+    Notification::load(notif.channel(), notif.payload(), state).await
+  }
+
+  #[tokio::test]
+  async fn mentions_should_reach_the_right_users() -> anyhow::Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let mut listener = PgListener::connect_with(&state.pool).await?;
+    listener.listen("message_mentioned").await?;
+    sqlx::query("INSERT INTO chat_members(chat_id, user_id) VALUES (1, 3)")
+      .execute(&state.pool)
+      .await?;
+    state.presence.connect(1);
+    state.presence.connect(3);
+
+    let mention = |kind: &'static str, user_id: Option<i64>| {
+      let pool = state.pool.clone();
+      async move {
+        sqlx::query(
+          r#"
+          WITH m AS (
+            INSERT INTO messages(chat_id, sender_id, content) VALUES (1, 1, 'hi') RETURNING id
+          )
+          INSERT INTO message_mentions(message_id, chat_id, kind, user_id)
+          SELECT id, 1, $1::mention_kind, $2 FROM m
+          "#,
+        )
+        .bind(kind)
+        .bind(user_id)
+        .execute(&pool)
+        .await
       }
+    };
+
+    mention("user", Some(2)).await?;
+    let notification = next_notification(&mut listener, &state).await?;
+    assert_eq!(notification.user_ids, HashSet::from([2]));
+    assert!(matches!(*notification.event, AppEvent::Mentioned(_)));
+    // the sender isn't notified of their own @channel
+    mention("channel", None).await?;
+    let notification = next_notification(&mut listener, &state).await?;
+    assert_eq!(notification.user_ids, HashSet::from([2, 3]));
+    mention("here", None).await?;
+    let notification = next_notification(&mut listener, &state).await?;
+    assert_eq!(notification.user_ids, HashSet::from([3]));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn chat_changed_should_reach_the_members() -> anyhow::Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let mut listener = PgListener::connect_with(&state.pool).await?;
+    listener.listen("chat_changed").await?;
+
+    // as written by update_chat, the chat and its change history in one go
+    sqlx::query(
+      r#"
+      WITH c AS (
+        UPDATE chats SET topic = 'launch' WHERE id = 1 RETURNING id
+      )
+      INSERT INTO chat_changes(chat_id, changed_by, field, old_value, new_value)
+      SELECT id, 2, 'topic', NULL, 'launch' FROM c
+      "#,
+    )
+    .execute(&state.pool)
+    .await?;
+    let notification = next_notification(&mut listener, &state).await?;
+    assert_eq!(notification.user_ids, HashSet::from([1, 2]));
+    let AppEvent::ChatChanged(changed) = &*notification.event else {
+      panic!("expected a chat changed event");
+    };
+    assert_eq!(changed.chat.r#type, ChatType::PublicChannel);
+    assert_eq!(changed.chat.topic.as_deref(), Some("launch"));
+    assert_eq!(changed.changes[0].field, "topic");
+
+    let event = serde_json::to_value(&*notification.event)?;
+    assert_eq!(event["event"], "ChatChanged");
+    assert_eq!(event["chat"]["type"], "PublicChannel");
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn deleting_a_large_chat_should_reach_every_member() -> anyhow::Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let mut listener = PgListener::connect_with(&state.pool).await?;
+    listener.listen("chat_updated").await?;
+
+    // far more members than fit in one notify payload
+    sqlx::query(
+      r#"
+      WITH u AS (
+        INSERT INTO users(ws_id, email, fullname, password_hash)
+        SELECT 1, 'user' || n || '@acme.org', 'User ' || n, ''
+        FROM generate_series(1, 500) n
+        RETURNING id
+      )
+      INSERT INTO chat_members(chat_id, user_id)
+      SELECT 1, id FROM u
+      "#,
+    )
+    .execute(&state.pool)
+    .await?;
+    let members: Vec<(i64,)> = sqlx::query_as("SELECT user_id FROM chat_members WHERE chat_id = 1")
+      .fetch_all(&state.pool)
+      .await?;
+    let members: HashSet<u64> = members.into_iter().map(|(id,)| id as u64).collect();
+    assert_eq!(members.len(), 502);
+    for _ in 0..500 {
+      let notification = next_notification(&mut listener, &state).await?;
+      assert!(matches!(*notification.event, AppEvent::AddToChat(_)));
     }
-    (Some(old), None) => old.members.iter().map(|v| *v as u64).collect(),
-    (None, Some(new)) => new.members.iter().map(|v| *v as u64).collect(),
-    _ => HashSet::new(),
+
+    sqlx::query("DELETE FROM chats WHERE id = 1")
+      .execute(&state.pool)
+      .await?;
+    let mut user_ids = HashSet::new();
+    while user_ids.len() < members.len() {
+      let notification = next_notification(&mut listener, &state).await?;
+      let AppEvent::RemoveFromChat(chat) = &*notification.event else {
+        panic!("expected a remove from chat event");
+      };
+      assert_eq!(chat.id, 1);
+      user_ids.extend(notification.user_ids);
+    }
+    assert_eq!(user_ids, members);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn new_message_should_echo_the_nonce() -> anyhow::Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let mut listener = PgListener::connect_with(&state.pool).await?;
+    listener.listen("chat_message_created").await?;
+
+    sqlx::query(
+      "INSERT INTO messages(chat_id, sender_id, content, nonce) VALUES (1, 1, 'hi', 'c0ffee')",
+    )
+    .execute(&state.pool)
+    .await?;
+    let notification = next_notification(&mut listener, &state).await?;
+    assert_eq!(notification.user_ids, HashSet::from([1, 2]));
+    let AppEvent::NewMessage(message) = &*notification.event else {
+      panic!("expected a new message event");
+    };
+    assert_eq!(message.content, "hi");
+    assert_eq!(message.nonce.as_deref(), Some("c0ffee"));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn large_messages_should_be_delivered() -> anyhow::Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let mut listener = PgListener::connect_with(&state.pool).await?;
+    listener.listen("chat_message_created").await?;
+    listener.listen("chat_message_updated").await?;
+
+    // far more than fits in a notify payload
+    let content = "x".repeat(10_000);
+    let (id,): (i64,) = sqlx::query_as(
+      "INSERT INTO messages(chat_id, sender_id, content) VALUES (1, 1, $1) RETURNING id",
+    )
+    .bind(&content)
+    .fetch_one(&state.pool)
+    .await?;
+    let notification = next_notification(&mut listener, &state).await?;
+    let AppEvent::NewMessage(message) = &*notification.event else {
+      panic!("expected a new message event");
+    };
+    assert_eq!(message.content, content);
+
+    sqlx::query("UPDATE messages SET content = $2, edited_at = now() WHERE id = $1")
+      .bind(id)
+      .bind("y".repeat(10_000))
+      .execute(&state.pool)
+      .await?;
+    let notification = next_notification(&mut listener, &state).await?;
+    assert!(matches!(*notification.event, AppEvent::MessageUpdated(_)));
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn leaving_should_update_the_member_cache() -> anyhow::Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    state.user_typing(1, 2).await?;
+    let mut listener = PgListener::connect_with(&state.pool).await?;
+    listener.listen("chat_updated").await?;
+
+    sqlx::query("DELETE FROM chat_members WHERE chat_id = 1 AND user_id = 2")
+      .execute(&state.pool)
+      .await?;
+    let notification = next_notification(&mut listener, &state).await?;
+    assert_eq!(notification.user_ids, HashSet::from([1, 2]));
+    state.track_notification(&notification.event);
+
+    // the cached members no longer have alice
+    let err = state.user_typing(1, 2).await.unwrap_err();
+    assert!(matches!(err, AppError::NotChatMember(1)));
+    Ok(())
   }
 }
@@ -0,0 +1,298 @@
+use crate::{AppError, AppEvent, AppState};
+use axum::{
+  extract::{Query, State},
+  response::IntoResponse,
+  Extension, Json,
+};
+use chat_core::User;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+  Online,
+  Idle,
+  Offline,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Presence {
+  pub user_id: u64,
+  pub status: PresenceStatus,
+  pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PresenceQuery {
+  /// comma separated user ids, e.g. `1,2,3`
+  pub ids: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetPresence {
+  pub status: PresenceStatus,
+}
+
+const MAX_PRESENCE_LOOKUP: usize = 500;
+
+#[derive(Debug)]
+struct PresenceEntry {
+  connections: usize,
+  status: PresenceStatus,
+  last_seen_at: DateTime<Utc>,
+  // bumped on every connect and disconnect, so a grace timer knows if it is stale
+  epoch: u64,
+}
+
+/// Tracks open event stream connections per user and derives their presence.
+#[derive(Debug, Default)]
+pub struct PresenceTracker {
+  entries: DashMap<u64, PresenceEntry>,
+}
+
+impl PresenceTracker {
+  /// Registers a new connection, returns the new presence if the status changed.
+  pub fn connect(&self, user_id: u64) -> Option<Presence> {
+    let mut entry = self.entries.entry(user_id).or_insert(PresenceEntry {
+      connections: 0,
+      status: PresenceStatus::Offline,
+      last_seen_at: Utc::now(),
+      epoch: 0,
+    });
+    entry.connections += 1;
+    entry.epoch += 1;
+    entry.last_seen_at = Utc::now();
+    if entry.status == PresenceStatus::Offline {
+      entry.status = PresenceStatus::Online;
+      return Some(entry.to_presence(user_id));
+    }
+    None
+  }
+
+  /// Unregisters a connection. If it was the last one, returns the epoch to hand to
+  /// [`PresenceTracker::expire`] once the grace period is over.
+  pub fn disconnect(&self, user_id: u64) -> Option<u64> {
+    let mut entry = self.entries.get_mut(&user_id)?;
+    entry.connections = entry.connections.saturating_sub(1);
+    entry.epoch += 1;
+    entry.last_seen_at = Utc::now();
+    (entry.connections == 0).then_some(entry.epoch)
+  }
+
+  /// Marks the user offline unless they reconnected since `disconnect` returned `epoch`.
+  pub fn expire(&self, user_id: u64, epoch: u64) -> Option<Presence> {
+    let mut entry = self.entries.get_mut(&user_id)?;
+    if entry.epoch != epoch || entry.connections > 0 || entry.status == PresenceStatus::Offline {
+      return None;
+    }
+    entry.status = PresenceStatus::Offline;
+    Some(entry.to_presence(user_id))
+  }
+
+  /// Switches a connected user between online and idle, returns the new presence if it changed.
+  pub fn set_status(&self, user_id: u64, status: PresenceStatus) -> Option<Presence> {
+    let mut entry = self.entries.get_mut(&user_id)?;
+    if entry.connections == 0 || entry.status == status {
+      return None;
+    }
+    entry.status = status;
+    entry.last_seen_at = Utc::now();
+    Some(entry.to_presence(user_id))
+  }
+
+  pub fn get(&self, user_id: u64) -> Presence {
+    match self.entries.get(&user_id) {
+      Some(entry) => entry.to_presence(user_id),
+      None => Presence {
+        user_id,
+        status: PresenceStatus::Offline,
+        last_seen_at: None,
+      },
+    }
+  }
+}
+
+impl PresenceEntry {
+  fn to_presence(&self, user_id: u64) -> Presence {
+    Presence {
+      user_id,
+      status: self.status,
+      last_seen_at: Some(self.last_seen_at),
+    }
+  }
+}
+
+impl AppState {
+  pub(crate) async fn user_connected(&self, user_id: u64) {
+    if let Some(presence) = self.presence.connect(user_id) {
+      self.broadcast_presence(presence).await;
+    }
+  }
+
+  pub(crate) fn user_disconnected(&self, user_id: u64) {
+    let Some(epoch) = self.presence.disconnect(user_id) else {
+      return;
+    };
+    let state = self.clone();
+    let grace_period = Duration::from_secs(self.config.presence.grace_period);
+    tokio::spawn(async move {
+      tokio::time::sleep(grace_period).await;
+      if let Some(presence) = state.presence.expire(user_id, epoch) {
+        state.broadcast_presence(presence).await;
+      }
+    });
+  }
+
+  /// Sends the presence change to every connected user who shares a chat with the user.
+  async fn broadcast_presence(&self, presence: Presence) {
+    info!("Presence changed: {:?}", presence);
+    let user_ids = match self.fetch_chat_partner_ids(presence.user_id).await {
+      Ok(user_ids) => user_ids,
+      Err(e) => {
+        warn!(
+          "Failed to fetch chat partners of {}: {}",
+          presence.user_id, e
+        );
+        return;
+      }
+    };
+
+    let event = Arc::new(AppEvent::PresenceChanged(presence));
+    for user_id in user_ids {
+      if let Some(tx) = self.users.get(&user_id) {
+        // no receivers just means the user has no open connection right now
+        let _ = tx.send(event.clone());
+      }
+    }
+  }
+
+  async fn fetch_chat_partner_ids(&self, user_id: u64) -> Result<Vec<u64>, AppError> {
+    let ids: Vec<(i64,)> = sqlx::query_as(
+      r#"
+      SELECT DISTINCT m.user_id
+      FROM chat_members m JOIN chat_members me ON me.chat_id = m.chat_id
+      WHERE me.user_id = $1
+      "#,
+    )
+    .bind(user_id as i64)
+    .fetch_all(&self.pool)
+    .await?;
+
+    Ok(ids.into_iter().map(|(id,)| id as u64).collect())
+  }
+
+  async fn filter_workspace_user_ids(&self, ws_id: i64, ids: &[i64]) -> Result<Vec<u64>, AppError> {
+    let ids: Vec<(i64,)> = sqlx::query_as(
+      r#"
+      SELECT id
+      FROM users
+      WHERE id = ANY($1) AND ws_id = $2
+      ORDER BY id
+      "#,
+    )
+    .bind(ids)
+    .bind(ws_id)
+    .fetch_all(&self.pool)
+    .await?;
+
+    Ok(ids.into_iter().map(|(id,)| id as u64).collect())
+  }
+}
+
+pub(crate) async fn get_presence_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Query(input): Query<PresenceQuery>,
+) -> Result<impl IntoResponse, AppError> {
+  let ids = input
+    .ids
+    .split(',')
+    .filter(|s| !s.trim().is_empty())
+    .map(|s| s.trim().parse::<i64>())
+    .collect::<Result<Vec<_>, _>>()
+    .map_err(|_| AppError::InvalidPresenceQuery(input.ids.clone()))?;
+  if ids.len() > MAX_PRESENCE_LOOKUP {
+    return Err(AppError::InvalidPresenceQuery(format!(
+      "at most {} ids are allowed",
+      MAX_PRESENCE_LOOKUP
+    )));
+  }
+
+  let ids = state.filter_workspace_user_ids(user.ws_id, &ids).await?;
+  let presences: Vec<_> = ids.into_iter().map(|id| state.presence.get(id)).collect();
+  Ok(Json(presences))
+}
+
+pub(crate) async fn set_presence_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Json(input): Json<SetPresence>,
+) -> Result<impl IntoResponse, AppError> {
+  if input.status == PresenceStatus::Offline {
+    return Err(AppError::InvalidPresenceQuery(
+      "offline is set by closing all connections".to_string(),
+    ));
+  }
+
+  let user_id = user.id as u64;
+  if let Some(presence) = state.presence.set_status(user_id, input.status) {
+    state.broadcast_presence(presence).await;
+  }
+  Ok(Json(state.presence.get(user_id)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn presence_should_go_online_and_offline_after_grace() {
+    let tracker = PresenceTracker::default();
+    assert_eq!(tracker.get(1).status, PresenceStatus::Offline);
+
+    let presence = tracker.connect(1).expect("status should change");
+    assert_eq!(presence.status, PresenceStatus::Online);
+    // a second device doesn't change the status
+    assert!(tracker.connect(1).is_none());
+
+    assert!(tracker.disconnect(1).is_none());
+    let epoch = tracker.disconnect(1).expect("last connection closed");
+    assert_eq!(tracker.get(1).status, PresenceStatus::Online);
+
+    let presence = tracker.expire(1, epoch).expect("status should change");
+    assert_eq!(presence.status, PresenceStatus::Offline);
+  }
+
+  #[test]
+  fn reconnect_within_grace_should_stay_online() {
+    let tracker = PresenceTracker::default();
+    tracker.connect(1);
+    let epoch = tracker.disconnect(1).expect("last connection closed");
+    assert!(tracker.connect(1).is_none());
+
+    assert!(tracker.expire(1, epoch).is_none());
+    assert_eq!(tracker.get(1).status, PresenceStatus::Online);
+  }
+
+  #[test]
+  fn idle_should_only_apply_to_connected_users() {
+    let tracker = PresenceTracker::default();
+    assert!(tracker.set_status(1, PresenceStatus::Idle).is_none());
+
+    tracker.connect(1);
+    let presence = tracker
+      .set_status(1, PresenceStatus::Idle)
+      .expect("status should change");
+    assert_eq!(presence.status, PresenceStatus::Idle);
+    assert!(tracker.set_status(1, PresenceStatus::Idle).is_none());
+
+    let presence = tracker
+      .set_status(1, PresenceStatus::Online)
+      .expect("status should change");
+    assert_eq!(presence.status, PresenceStatus::Online);
+  }
+}
@@ -1,18 +1,80 @@
-use axum::response::{sse::Event, Sse};
-use axum_extra::{headers, TypedHeader};
-use futures::{stream, Stream};
-use std::{convert::Infallible, time::Duration};
-use tokio_stream::StreamExt;
+use crate::{AppEvent, AppState, UserMap};
+use axum::{
+  extract::State,
+  response::{sse::Event, Sse},
+  Extension,
+};
+use chat_core::User;
+use futures::Stream;
+use std::{
+  convert::Infallible,
+  pin::Pin,
+  task::{Context, Poll},
+  time::Duration,
+};
+use tokio::sync::broadcast;
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
 use tracing::info;
 
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Marks the user as disconnected when the event stream is dropped.
+struct ConnectionGuard {
+  state: AppState,
+  user_id: u64,
+}
+
+/// The events of a connection. The stream is dropped before the guard, so the guard sees the
+/// receiver of the connection gone.
+struct EventStream<S> {
+  stream: S,
+  _guard: ConnectionGuard,
+}
+
 pub(crate) async fn sse_handler(
-  TypedHeader(user_agent): TypedHeader<headers::UserAgent>,
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
 ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
-  info!("`{}` connected", user_agent.as_str());
+  let user_id = user.id as u64;
+  info!("User {} connected", user_id);
 
-  let stream = stream::repeat_with(|| Event::default().data("hi"))
-    .map(Ok)
-    .throttle(Duration::from_secs(1));
+  let rx = state
+    .users
+    .entry(user_id)
+    .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+    .subscribe();
+  state.user_connected(user_id).await;
+
+  let stream = BroadcastStream::new(rx).filter_map(|v| v.ok()).map(|v| {
+    let name = match v.as_ref() {
+      AppEvent::NewChat(_) => "NewChat",
+      AppEvent::AddToChat(_) => "AddToChat",
+      AppEvent::RemoveFromChat(_) => "RemoveFromChat",
+      AppEvent::NewMessage(_) => "NewMessage",
+      AppEvent::NewThreadReply(_) => "NewThreadReply",
+      AppEvent::MessageUpdated(_) => "MessageUpdated",
+      AppEvent::MessageDeleted(_) => "MessageDeleted",
+      AppEvent::ChatChanged(_) => "ChatChanged",
+      AppEvent::ChatRead(_) => "ChatRead",
+      AppEvent::ReactionAdded(_) => "ReactionAdded",
+      AppEvent::ReactionRemoved(_) => "ReactionRemoved",
+      AppEvent::MessagePinned(_) => "MessagePinned",
+      AppEvent::MessageUnpinned(_) => "MessageUnpinned",
+      AppEvent::Mentioned(_) => "Mentioned",
+      AppEvent::PresenceChanged(_) => "PresenceChanged",
+      AppEvent::TypingStarted(_) => "TypingStarted",
+      AppEvent::TypingStopped(_) => "TypingStopped",
+    };
+    let v = serde_json::to_string(&v).expect("Failed to serialize event");
+    Ok(Event::default().data(v).event(name))
+  });
+  let stream = EventStream {
+    stream,
+    _guard: ConnectionGuard {
+      state: state.clone(),
+      user_id,
+    },
+  };
 
   Sse::new(stream).keep_alive(
     axum::response::sse::KeepAlive::new()
@@ -20,3 +82,49 @@ pub(crate) async fn sse_handler(
       .text("keep-alive-text"),
   )
 }
+
+impl<S: Stream + Unpin> Stream for EventStream<S> {
+  type Item = S::Item;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    Pin::new(&mut self.stream).poll_next(cx)
+  }
+}
+
+impl Drop for ConnectionGuard {
+  fn drop(&mut self) {
+    info!("User {} disconnected", self.user_id);
+    remove_closed_channel(&self.state.users, self.user_id);
+    self.state.user_disconnected(self.user_id);
+  }
+}
+
+/// Drops the channel of the user once their last connection is closed, events for users
+/// without a channel are skipped.
+fn remove_closed_channel(users: &UserMap, user_id: u64) {
+  users.remove_if(&user_id, |_, tx| tx.receiver_count() == 0);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use dashmap::DashMap;
+  use std::sync::Arc;
+
+  #[test]
+  fn channel_should_be_removed_with_the_last_connection() {
+    let users: UserMap = Arc::new(DashMap::new());
+    let tx = broadcast::channel::<Arc<AppEvent>>(CHANNEL_CAPACITY).0;
+    let rx1 = tx.subscribe();
+    let rx2 = tx.subscribe();
+    users.insert(1, tx);
+
+    drop(rx1);
+    remove_closed_channel(&users, 1);
+    assert!(users.contains_key(&1));
+
+    drop(rx2);
+    remove_closed_channel(&users, 1);
+    assert!(!users.contains_key(&1));
+  }
+}
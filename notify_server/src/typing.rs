@@ -0,0 +1,347 @@
+use crate::{AppError, AppEvent, AppState};
+use axum::{
+  extract::{Path, State},
+  http::StatusCode,
+  response::IntoResponse,
+  Extension,
+};
+use chat_core::User;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tokio::time::Instant;
+use tracing::warn;
+
+/// A member of a chat started or stopped typing in it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Typing {
+  pub chat_id: u64,
+  pub user_id: u64,
+  /// when clients should drop the indicator if no refresh arrives, `None` once stopped
+  pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// What to do with a typing signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypingSignal {
+  /// the user wasn't typing before
+  Started,
+  /// still typing, tell the other members again
+  Refreshed,
+  /// still typing, but the other members were told too recently
+  Throttled,
+}
+
+/// how long a typing signal lasts without a refresh
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+/// at most one typing event per user and chat in this interval
+const TYPING_THROTTLE: Duration = Duration::from_secs(3);
+/// how long chat members are cached, membership events update them in the meantime
+const MEMBERS_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct TypingEntry {
+  relayed_at: Instant,
+  expires_at: Instant,
+}
+
+/// Tracks who is typing in which chat. Typing only lives in memory and expires on its own.
+#[derive(Debug, Default)]
+pub struct TypingTracker {
+  entries: DashMap<(u64, u64), TypingEntry>,
+}
+
+/// Chat members for relaying typing events, so signals don't query the database each time.
+#[derive(Debug, Default)]
+pub struct ChatMemberCache {
+  chats: DashMap<u64, (Arc<Vec<u64>>, Instant)>,
+}
+
+impl TypingTracker {
+  /// Records that the user is typing in the chat, extending the expiry.
+  pub fn start(&self, chat_id: u64, user_id: u64, now: Instant) -> TypingSignal {
+    // a new entry counts as expired, like one that wasn't cleaned up yet
+    let mut entry = self
+      .entries
+      .entry((chat_id, user_id))
+      .or_insert(TypingEntry {
+        relayed_at: now,
+        expires_at: now,
+      });
+    let signal = if entry.expires_at <= now {
+      entry.relayed_at = now;
+      TypingSignal::Started
+    } else if now.duration_since(entry.relayed_at) < TYPING_THROTTLE {
+      TypingSignal::Throttled
+    } else {
+      entry.relayed_at = now;
+      TypingSignal::Refreshed
+    };
+    entry.expires_at = now + TYPING_TIMEOUT;
+    signal
+  }
+
+  /// Returns true if the user was typing in the chat.
+  pub fn stop(&self, chat_id: u64, user_id: u64) -> bool {
+    self.entries.remove(&(chat_id, user_id)).is_some()
+  }
+
+  pub fn expires_at(&self, chat_id: u64, user_id: u64) -> Option<Instant> {
+    self
+      .entries
+      .get(&(chat_id, user_id))
+      .map(|entry| entry.expires_at)
+  }
+
+  /// Drops the typing signal if it wasn't refreshed before `now`, returns true if it did.
+  pub fn expire(&self, chat_id: u64, user_id: u64, now: Instant) -> bool {
+    self
+      .entries
+      .remove_if(&(chat_id, user_id), |_, entry| entry.expires_at <= now)
+      .is_some()
+  }
+}
+
+impl ChatMemberCache {
+  pub fn get(&self, chat_id: u64) -> Option<Arc<Vec<u64>>> {
+    let entry = self.chats.get(&chat_id)?;
+    let (members, cached_at) = entry.value();
+    (cached_at.elapsed() < MEMBERS_TTL).then(|| members.clone())
+  }
+
+  pub fn set(&self, chat_id: u64, members: Vec<u64>) -> Arc<Vec<u64>> {
+    let members = Arc::new(members);
+    self
+      .chats
+      .insert(chat_id, (members.clone(), Instant::now()));
+    members
+  }
+
+  pub fn remove(&self, chat_id: u64) {
+    self.chats.remove(&chat_id);
+  }
+}
+
+impl AppState {
+  /// Relays a typing signal to the other members of the chat, throttled per user and chat.
+  pub(crate) async fn user_typing(&self, chat_id: u64, user_id: u64) -> Result<(), AppError> {
+    let members = self.chat_member_ids(chat_id).await?;
+    if !members.contains(&user_id) {
+      return Err(AppError::NotChatMember(chat_id));
+    }
+    match self.typing.start(chat_id, user_id, Instant::now()) {
+      TypingSignal::Throttled => return Ok(()),
+      TypingSignal::Refreshed => {}
+      // only the first signal starts a timer, refreshes push back its deadline
+      TypingSignal::Started => self.spawn_typing_timer(chat_id, user_id),
+    }
+    let expires_at = Utc::now() + TYPING_TIMEOUT;
+    let typing = Typing {
+      chat_id,
+      user_id,
+      expires_at: Some(expires_at),
+    };
+    self.relay_typing(&members, AppEvent::TypingStarted(typing));
+    Ok(())
+  }
+
+  pub(crate) async fn user_stopped_typing(
+    &self,
+    chat_id: u64,
+    user_id: u64,
+  ) -> Result<(), AppError> {
+    if !self.typing.stop(chat_id, user_id) {
+      return Ok(());
+    }
+    let members = self.chat_member_ids(chat_id).await?;
+    self.relay_typing(&members, typing_stopped(chat_id, user_id));
+    Ok(())
+  }
+
+  fn spawn_typing_timer(&self, chat_id: u64, user_id: u64) {
+    let state = self.clone();
+    tokio::spawn(async move {
+      // gone once the user stopped or sent a message
+      while let Some(expires_at) = state.typing.expires_at(chat_id, user_id) {
+        tokio::time::sleep_until(expires_at).await;
+        if state.typing.expire(chat_id, user_id, Instant::now()) {
+          match state.chat_member_ids(chat_id).await {
+            Ok(members) => state.relay_typing(&members, typing_stopped(chat_id, user_id)),
+            Err(e) => warn!("Failed to fetch members of chat {}: {}", chat_id, e),
+          }
+          return;
+        }
+      }
+    });
+  }
+
+  /// Sends the event to the connected members, the typing user isn't told about themselves.
+  fn relay_typing(&self, members: &[u64], event: AppEvent) {
+    let user_id = match &event {
+      AppEvent::TypingStarted(typing) | AppEvent::TypingStopped(typing) => typing.user_id,
+      _ => return,
+    };
+    let event = Arc::new(event);
+    for member in members.iter().filter(|id| **id != user_id) {
+      if let Some(tx) = self.users.get(member) {
+        // no receivers just means the user has no open connection right now
+        let _ = tx.send(event.clone());
+      }
+    }
+  }
+
+  pub(crate) async fn chat_member_ids(&self, chat_id: u64) -> Result<Arc<Vec<u64>>, AppError> {
+    if let Some(members) = self.chat_members.get(chat_id) {
+      return Ok(members);
+    }
+    let ids: Vec<(i64,)> = sqlx::query_as(
+      r#"
+      SELECT user_id
+      FROM chat_members
+      WHERE chat_id = $1
+      "#,
+    )
+    .bind(chat_id as i64)
+    .fetch_all(&self.pool)
+    .await?;
+
+    let members = ids.into_iter().map(|(id,)| id as u64).collect();
+    Ok(self.chat_members.set(chat_id, members))
+  }
+}
+
+fn typing_stopped(chat_id: u64, user_id: u64) -> AppEvent {
+  AppEvent::TypingStopped(Typing {
+    chat_id,
+    user_id,
+    expires_at: None,
+  })
+}
+
+pub(crate) async fn start_typing_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path(chat_id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+  state.user_typing(chat_id, user.id as _).await?;
+  Ok(StatusCode::NO_CONTENT)
+}
+
+pub(crate) async fn stop_typing_handler(
+  Extension(user): Extension<User>,
+  State(state): State<AppState>,
+  Path(chat_id): Path<u64>,
+) -> Result<impl IntoResponse, AppError> {
+  state.user_stopped_typing(chat_id, user.id as _).await?;
+  Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tokio::sync::broadcast::error::TryRecvError;
+
+  fn typing_event(event: &AppEvent) -> &Typing {
+    match event {
+      AppEvent::TypingStarted(typing) | AppEvent::TypingStopped(typing) => typing,
+      _ => panic!("expected a typing event, got {:?}", event),
+    }
+  }
+
+  #[tokio::test]
+  async fn typing_should_be_relayed_to_the_other_members() -> anyhow::Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let mut hal = state.connect_for_test(1);
+    let mut alice = state.connect_for_test(2);
+
+    // bob isn't in the channel
+    let err = state.user_typing(1, 3).await.unwrap_err();
+    assert!(matches!(err, AppError::NotChatMember(1)));
+
+    state.user_typing(1, 1).await?;
+    let event = alice.try_recv()?;
+    assert!(matches!(*event, AppEvent::TypingStarted(_)));
+    assert_eq!(typing_event(&event).user_id, 1);
+    assert!(typing_event(&event).expires_at.is_some());
+    // the typing user isn't told about themselves
+    assert_eq!(hal.try_recv().unwrap_err(), TryRecvError::Empty);
+
+    // a signal right after the first one isn't relayed
+    state.user_typing(1, 1).await?;
+    assert_eq!(alice.try_recv().unwrap_err(), TryRecvError::Empty);
+
+    state.user_stopped_typing(1, 1).await?;
+    let event = alice.try_recv()?;
+    assert!(matches!(*event, AppEvent::TypingStopped(_)));
+    // stopping twice says nothing
+    state.user_stopped_typing(1, 1).await?;
+    assert_eq!(alice.try_recv().unwrap_err(), TryRecvError::Empty);
+    Ok(())
+  }
+
+  #[tokio::test]
+  async fn typing_should_stop_when_it_expires() -> anyhow::Result<()> {
+    let (_tdb, state) = AppState::new_for_test().await?;
+    let mut hal = state.connect_for_test(1);
+
+    // alice's signal is about to expire
+    let almost = TYPING_TIMEOUT - Duration::from_millis(50);
+    state.typing.start(1, 2, Instant::now() - almost);
+    state.spawn_typing_timer(1, 2);
+
+    let event = tokio::time::timeout(Duration::from_secs(2), hal.recv()).await??;
+    assert!(matches!(*event, AppEvent::TypingStopped(_)));
+    assert_eq!(typing_event(&event).user_id, 2);
+    assert_eq!(state.typing.expires_at(1, 2), None);
+    Ok(())
+  }
+
+  #[test]
+  fn typing_should_be_throttled() {
+    let tracker = TypingTracker::default();
+    let now = Instant::now();
+    assert_eq!(tracker.start(1, 1, now), TypingSignal::Started);
+    assert_eq!(
+      tracker.start(1, 1, now + Duration::from_secs(1)),
+      TypingSignal::Throttled
+    );
+    assert_eq!(
+      tracker.start(1, 1, now + Duration::from_secs(3)),
+      TypingSignal::Refreshed
+    );
+    // other users and chats are tracked on their own
+    assert_eq!(tracker.start(1, 2, now), TypingSignal::Started);
+    assert_eq!(tracker.start(2, 1, now), TypingSignal::Started);
+  }
+
+  #[test]
+  fn typing_should_expire_without_refresh() {
+    let tracker = TypingTracker::default();
+    let now = Instant::now();
+    tracker.start(1, 1, now);
+    // a throttled signal still pushes back the expiry
+    tracker.start(1, 1, now + Duration::from_secs(2));
+    assert_eq!(tracker.expires_at(1, 1), Some(now + Duration::from_secs(8)));
+
+    assert!(!tracker.expire(1, 1, now + Duration::from_secs(6)));
+    assert!(tracker.expire(1, 1, now + Duration::from_secs(8)));
+    assert_eq!(tracker.expires_at(1, 1), None);
+
+    // an expired signal that wasn't cleaned up yet starts over
+    tracker.start(1, 1, now);
+    assert_eq!(
+      tracker.start(1, 1, now + Duration::from_secs(7)),
+      TypingSignal::Started
+    );
+  }
+
+  #[test]
+  fn stop_should_only_report_active_typing() {
+    let tracker = TypingTracker::default();
+    assert!(!tracker.stop(1, 1));
+    tracker.start(1, 1, Instant::now());
+    assert!(tracker.stop(1, 1));
+    assert!(!tracker.stop(1, 1));
+  }
+}